@@ -16,13 +16,22 @@
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+//the C LZVN code is only built with the c-lzvn feature, the default rust-lzvn feature needs nothing
+#[cfg(feature = "c-lzvn")]
 extern crate bindgen;
+#[cfg(feature = "c-lzvn")]
 extern crate cc;
 
-use std::env;
-use std::path::PathBuf;
+#[cfg(feature = "c-lzvn")]
+use std::{env, path::PathBuf};
 
 fn main() {
+    #[cfg(feature = "c-lzvn")]
+    build_lzvn();
+}
+
+#[cfg(feature = "c-lzvn")]
+fn build_lzvn() {
     let lzvn_path = env::current_dir().unwrap()
                     .join("src")
                     .join("ext")
@@ -0,0 +1,225 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//splits a directory of firmwares named like testfws/, "sepfw.<board>.<build>.bin", on a pool of threads
+
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use prettytable::{format, row, Table};
+use serde::Serialize;
+
+use crate::{
+    firmware::{ModuleKind, SepFormat},
+    manifest::to_json,
+    map_file, split_buf, with_output, ArchiveFormat, OutputFormat, SepSplitError, SplitOptions,
+};
+
+/// What was found in a firmware that was split successfully
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub format: SepFormat,
+    pub apps: usize,   // The number of apps, without SEPOS
+    pub shlibs: usize, // The number of shared libraries
+}
+
+/// The result of splitting one firmware of a batch
+#[derive(Debug)]
+pub struct BatchEntry {
+    pub input: PathBuf,  // The firmware that was split
+    pub outdir: PathBuf, // The directory or archive it was split into, `<outdir>/<board>.<build>[.tar|.zip]`
+    pub result: Result<BatchSummary, SepSplitError>,
+}
+
+//gets the board and build from a file name like "sepfw.N61.16G192.bin"
+fn board_build(fname: &str) -> Option<(&str, &str)> {
+    fname.strip_prefix("sepfw.")?.strip_suffix(".bin")?.split_once('.')
+}
+
+fn split_one(input: &Path, outdir: &Path, verbose: usize, opts: &SplitOptions) -> Result<BatchSummary, SepSplitError> {
+    let krnl = map_file(input)?;
+    let firmware = split_buf(&krnl, opts, BufWriter::new(Box::new(std::io::sink())))?;
+    if verbose >= 1 {
+        for warning in &firmware.warnings {
+            eprintln!("Warning: {}: {warning}", input.display());
+        }
+    }
+    with_output(outdir, 0, opts, |sink| Ok(firmware.write_sink(sink).map(|_| ())?))?;
+    let count = |kind| firmware.modules.iter().filter(|m| m.kind == kind).count();
+    Ok(BatchSummary { format: firmware.format, apps: count(ModuleKind::App), shlibs: count(ModuleKind::SharedLib) })
+}
+
+fn print_summary(entries: &[BatchEntry], output: OutputFormat) -> Result<(), SepSplitError> {
+    let mut stdout = std::io::stdout().lock();
+    if output == OutputFormat::Json {
+        #[derive(Serialize)]
+        struct Summary {
+            input: String,
+            directory: String,
+            error: Option<String>,
+            format: Option<String>,
+            apps: Option<usize>,
+            shlibs: Option<usize>,
+        }
+        let entries: Vec<_> = entries.iter().map(|entry| Summary {
+            input: entry.input.to_string_lossy().into_owned(),
+            directory: entry.outdir.to_string_lossy().into_owned(),
+            error: entry.result.as_ref().err().map(ToString::to_string),
+            format: entry.result.as_ref().ok().map(|s| s.format.to_string()),
+            apps: entry.result.as_ref().ok().map(|s| s.apps),
+            shlibs: entry.result.as_ref().ok().map(|s| s.shlibs),
+        }).collect();
+        writeln!(stdout, "{}", to_json(&entries))?;
+        return Ok(())
+    }
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_BOX_CHARS);
+    table.set_titles(row!["Firmware", "Status", "Format", "Apps", "Shared libs"]);
+    for entry in entries {
+        let name = entry.input.file_name().map_or_else(|| entry.input.to_string_lossy(), |n| n.to_string_lossy());
+        match &entry.result {
+            Ok(summary) => table.add_row(row![name, "ok", summary.format, r->summary.apps, r->summary.shlibs]),
+            Err(err) => table.add_row(row![name, Fr->format!("error {}: {err}", err.exit_code()), "", "", ""]),
+        };
+    }
+    table.print(&mut stdout)?;
+    let failed = entries.iter().filter(|e| e.result.is_err()).count();
+    writeln!(stdout, "{} split, {failed} failed", entries.len() - failed)?;
+    Ok(())
+}
+
+/// Splits every `sepfw.<board>.<build>.bin` firmware in `indir` into `<outdir>/<board>.<build>/`, in parallel.
+///
+/// If `opts.archive` is set, each firmware goes into an archive instead, like `<outdir>/<board>.<build>.tar`.
+/// A firmware that fails to split does not stop the others, its error is kept in its `BatchEntry`.
+/// # Arguments
+/// * `indir` - The directory with the firmwares, other files are ignored
+/// * `outdir` - The output directory to create the subdirectories in
+/// * `verbose` - The verbosity level (0 for no output, 1 or more to print a summary table, or JSON if `opts.format` is JSON)
+/// * `opts` - The options to split every firmware with
+/// # Returns
+/// * The result of every firmware, sorted by file name
+/// # Errors
+/// * `indir` could not be read
+/// * Errors while writing the summary to stdout
+pub fn split_dir(indir: &Path, outdir: &Path, verbose: usize, opts: &SplitOptions) -> Result<Vec<BatchEntry>, SepSplitError> {
+    let mut inputs: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(indir)? {
+        let path = entry?.path();
+        let Some((board, build)) = path.file_name().and_then(|n| n.to_str()).and_then(board_build) else { continue };
+        let subdir = outdir.join(match opts.archive {
+            None => format!("{board}.{build}"),
+            Some(ArchiveFormat::Tar) => format!("{board}.{build}.tar"),
+            Some(ArchiveFormat::Zip) => format!("{board}.{build}.zip"),
+        });
+        if path.is_file() {
+            inputs.push((path, subdir));
+        }
+    }
+    inputs.sort();
+
+    //each worker takes the next firmware until there are none left
+    let next = AtomicUsize::new(0);
+    let workers = thread::available_parallelism().map_or(1, NonZeroUsize::get).min(inputs.len());
+    let mut entries: Vec<BatchEntry> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers).map(|_| scope.spawn(|| {
+            let mut done = Vec::new();
+            while let Some((input, subdir)) = inputs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                let result = split_one(input, subdir, verbose, opts);
+                done.push(BatchEntry { input: input.clone(), outdir: subdir.clone(), result });
+            }
+            done
+        })).collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap_or_else(|err| std::panic::resume_unwind(err))).collect()
+    });
+    entries.sort_by(|a, b| a.input.cmp(&b.input));
+
+    if verbose >= 1 {
+        print_summary(&entries, opts.format)?;
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{board_build, split_dir};
+    use crate::{firmware::SepFormat, utils::crc32, SepSplitError, SplitOptions};
+
+    //an old 32-bit SEP: the legion header points to the monitor boot args, which point to the kernel boot args,
+    //followed by the first version of the SEPOS bootargs for SEPOS and one app
+    fn firmware() -> Vec<u8> {
+        let mut fw = vec![0; 0x12000];
+        let mut put = |off: usize, bytes: &[u8]| fw[off..off + bytes.len()].copy_from_slice(bytes);
+        put(0x400, &1u32.to_le_bytes());
+        put(0x404, &0x800u32.to_le_bytes());
+        put(0x408, b"Built by legion2");
+        put(0x810, &0x900u32.to_le_bytes()); //kernel boot args
+        put(0x948, &1u32.to_le_bytes()); //apps
+        put(0x1000, b"kernel");
+        for (off, phys, size, name) in [(0xa38, 0x10000u64, 0x1800u32, b"SEPOS       "), (0xa68, 0x11800, 0x800, b"SEPD        ")] {
+            put(off, &phys.to_le_bytes());
+            put(off + 12, &size.to_le_bytes());
+            put(off + 20, name);
+            put(phys as usize, name);
+        }
+        let crc = crc32(&fw[0x10000..0x11800]);
+        fw[0x92c..0x930].copy_from_slice(&crc.to_le_bytes());
+        fw
+    }
+
+    #[test]
+    fn test_board_build() {
+        assert_eq!(board_build("sepfw.N61.16G192.bin"), Some(("N61", "16G192")));
+        assert_eq!(board_build("sepfw.N61.bin"), None);
+        assert_eq!(board_build("N61.16G192.bin"), None);
+    }
+
+    #[test]
+    fn test_split_dir() {
+        let dir = env::temp_dir().join(format!("sepsplit-{}-batch", process::id()));
+        let (indir, outdir) = (dir.join("in"), dir.join("out"));
+        fs::create_dir_all(&indir).unwrap();
+        fs::write(indir.join("sepfw.a.b.bin"), firmware()).unwrap();
+        fs::write(indir.join("sepfw.x.y.bin"), b"not a SEP firmware").unwrap();
+        fs::write(indir.join("notes.txt"), b"ignored").unwrap();
+
+        //the corrupt firmware does not stop the valid one, and the other file is not split
+        let entries = split_dir(&indir, &outdir, 0, &SplitOptions::default()).unwrap();
+        assert_eq!(entries.iter().map(|e| e.input.clone()).collect::<Vec<_>>(), [indir.join("sepfw.a.b.bin"), indir.join("sepfw.x.y.bin")]);
+        let summary = entries[0].result.as_ref().unwrap();
+        assert_eq!((summary.format, summary.apps, summary.shlibs), (SepFormat::Bits32 { old: true }, 1, 0));
+        assert_eq!(entries[0].outdir, outdir.join("a.b"));
+        assert!(matches!(entries[1].result, Err(SepSplitError::UnknownFormat)));
+
+        let manifest = fs::read_to_string(outdir.join("a.b/manifest.json")).unwrap();
+        assert!(manifest.contains("\"matches\": true"));
+        assert!(outdir.join("a.b/sepdump02_SEPOS").is_file());
+        assert_eq!(fs::read(outdir.join("a.b/sepdump03_SEPD")).unwrap(), firmware()[0x11800..]);
+        assert!(!outdir.join("x.y").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Differences between two SEP firmwares, usually of the same device across builds.
+    Modules are matched by kind and name, in order, as apps can share their truncated name.
+    Addresses are not compared, they move whenever a module before them changes size,
+    and neither is the CRC32 in the SEP HDR, as it changes with any app.
+*/
+
+use std::{
+    fmt,
+    io::BufWriter,
+};
+
+use prettytable::{format, row, Table};
+use serde::{Serialize, Serializer};
+use uuid::Uuid;
+
+use crate::{
+    firmware::{ModuleKind, SepFirmware, SepFormat, SepModule},
+    manifest::{modules, to_json},
+    split_image, utils::opt_str, Fix, SepSplitError, SplitOptions, SrcVer,
+};
+
+/// A module that is in both firmwares, with its old and new values
+#[derive(Debug, Clone, Serialize)]
+pub struct ModuleChange {
+    pub kind: ModuleKind,
+    pub name: String,
+    #[serde(serialize_with = "changed")]
+    pub uuid: (Option<Uuid>, Option<Uuid>),
+    #[serde(serialize_with = "changed")]
+    pub srcver: (Option<SrcVer>, Option<SrcVer>),
+    #[serde(serialize_with = "old_new")]
+    pub size_text: (u64, u64),
+    #[serde(serialize_with = "old_new")]
+    pub size_data: (u64, u64),
+}
+
+/// A field of the SEP HDR struct that changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct HeaderChange {
+    pub field: &'static str,
+    pub old: u64,
+    pub new: u64,
+}
+
+/// What changed from one SEP firmware to another, see `firmware_diff`
+#[derive(Debug, Clone, Serialize)]
+pub struct FirmwareDiff {
+    #[serde(serialize_with = "changed")]
+    pub format: (SepFormat, SepFormat),
+    pub header: Vec<HeaderChange>, // Only if both firmwares have a SEP HDR struct
+    #[serde(serialize_with = "modules")]
+    pub added: Vec<SepModule>,     // The modules that are only in the new firmware
+    #[serde(serialize_with = "modules")]
+    pub removed: Vec<SepModule>,   // The modules that are only in the old firmware
+    pub changed: Vec<ModuleChange>, // The modules in both that changed
+    pub unchanged: usize,          // The number of modules in both that did not change
+}
+
+impl ModuleChange {
+    fn new(old: &SepModule, new: &SepModule) -> Self {
+        Self {
+            kind: old.kind,
+            name: old.name.clone(),
+            uuid: (old.uuid, new.uuid),
+            srcver: (old.srcver, new.srcver),
+            size_text: (old.size_text, new.size_text),
+            size_data: (old.size_data, new.size_data),
+        }
+    }
+
+    #[must_use]
+    pub fn uuid_changed(&self) -> bool {
+        self.uuid.0 != self.uuid.1
+    }
+
+    #[must_use]
+    pub fn srcver_changed(&self) -> bool {
+        self.srcver.0 != self.srcver.1
+    }
+
+    #[must_use]
+    pub const fn size_changed(&self) -> bool {
+        self.size_text.0 != self.size_text.1 || self.size_data.0 != self.size_data.1
+    }
+
+    fn is_changed(&self) -> bool {
+        self.uuid_changed() || self.srcver_changed() || self.size_changed()
+    }
+}
+
+/// Splits two SEP firmwares in memory and compares them, without reconstructing any module.
+/// # Arguments
+/// * `old` - The bytes of the old SEP firmware, either raw or in an IM4P
+/// * `new` - The bytes of the new SEP firmware, either raw or in an IM4P
+/// * `opts` - The options to split both with
+/// # Errors
+/// * The same errors as `split_bytes`, for either firmware
+pub fn firmware_diff(old: &[u8], new: &[u8], opts: &SplitOptions) -> Result<FirmwareDiff, SepSplitError> {
+    let split = |input| split_image(input, opts, BufWriter::new(Box::new(std::io::sink())), Fix::None);
+    Ok(split(old)?.diff(&split(new)?))
+}
+
+impl SepFirmware<'_> {
+    /// What changed from this firmware to `new`, see `firmware_diff`
+    #[must_use]
+    pub fn diff(&self, new: &SepFirmware) -> FirmwareDiff {
+        let header = match (&self.header, &new.header) {
+            (Some(old), Some(new)) => old.fields().into_iter().zip(new.fields())
+                .filter(|((field, old), (_, new))| *field != "crc32" && old != new)
+                .map(|((field, old), (_, new))| HeaderChange { field, old, new })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let mut added: Vec<&SepModule> = new.modules.iter().filter(|m| m.kind != ModuleKind::Struct).collect();
+        let mut removed = Vec::new();
+        let mut changed = Vec::new();
+        let mut unchanged = 0;
+        for old in self.modules.iter().filter(|m| m.kind != ModuleKind::Struct) {
+            //what is left in added once every old module is matched was added
+            let Some(pos) = added.iter().position(|m| m.kind == old.kind && m.name == old.name) else {
+                removed.push(old.clone());
+                continue
+            };
+            let change = ModuleChange::new(old, added.remove(pos));
+            if change.is_changed() {
+                changed.push(change);
+            } else {
+                unchanged += 1;
+            }
+        }
+
+        FirmwareDiff {
+            format: (self.format, new.format),
+            header,
+            added: added.into_iter().cloned().collect(),
+            removed,
+            changed,
+            unchanged,
+        }
+    }
+}
+
+impl FirmwareDiff {
+    /// If the firmwares have no differences
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.format.0 == self.format.1 && self.header.is_empty() && self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// The differences as pretty printed JSON, the unchanged values of a changed module are null (sizes are always there)
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        to_json(self)
+    }
+}
+
+//a value as {old, new}
+fn old_new<T: Serialize, S: Serializer>((old, new): &(T, T), serializer: S) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct OldNew<'a, T> {
+        old: &'a T,
+        new: &'a T,
+    }
+    OldNew { old, new }.serialize(serializer)
+}
+
+//a value as {old, new} if it changed, null if it did not
+fn changed<T: Serialize + PartialEq, S: Serializer>(values: &(T, T), serializer: S) -> Result<S::Ok, S::Error> {
+    if values.0 == values.1 {
+        serializer.serialize_none()
+    } else {
+        old_new(values, serializer)
+    }
+}
+
+//like "0x1000 -> 0x1400 (+0x400)", empty if the size did not change
+fn size_delta((old, new): (u64, u64)) -> String {
+    match new.cmp(&old) {
+        std::cmp::Ordering::Equal => String::new(),
+        std::cmp::Ordering::Greater => format!("{old:#x} -> {new:#x} (+{:#x})", new - old),
+        std::cmp::Ordering::Less => format!("{old:#x} -> {new:#x} (-{:#x})", old - new),
+    }
+}
+
+fn opt_delta<T: ToString>((old, new): (Option<T>, Option<T>), changed: bool) -> String {
+    if changed { format!("{} -> {}", opt_str(old), opt_str(new)) } else { String::new() }
+}
+
+impl fmt::Display for FirmwareDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.format.0 != self.format.1 {
+            writeln!(f, "format           {} -> {}", self.format.0, self.format.1)?;
+        }
+        for change in &self.header {
+            writeln!(f, "{:<16} {}", change.field, size_delta((change.old, change.new)))?;
+        }
+        writeln!(f, "modules          {} added, {} removed, {} changed, {} unchanged",
+            self.added.len(), self.removed.len(), self.changed.len(), self.unchanged)?;
+        if self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() {
+            return Ok(())
+        }
+
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_BOX_CHARS);
+        table.set_titles(row!["", "Kind", "Name", "Source version", "UUID", "Text", "Data"]);
+        for (sign, modules) in [("+", &self.added), ("-", &self.removed)] {
+            for module in modules {
+                table.add_row(row![
+                    sign,
+                    module.kind.as_str(),
+                    module.name,
+                    opt_str(module.srcver),
+                    opt_str(module.uuid),
+                    r->format!("{:#x}", module.size_text),
+                    r->format!("{:#x}", module.size_data),
+                ]);
+            }
+        }
+        for change in &self.changed {
+            table.add_row(row![
+                "~",
+                change.kind.as_str(),
+                change.name,
+                opt_delta(change.srcver, change.srcver_changed()),
+                opt_delta(change.uuid, change.uuid_changed()),
+                r->size_delta(change.size_text),
+                r->size_delta(change.size_data),
+            ]);
+        }
+        write!(f, "{table}")
+    }
+}
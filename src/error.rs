@@ -0,0 +1,152 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::fmt;
+
+/// Errors that can happen while splitting a SEP firmware
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SepSplitError {
+    /// No known legion header was found in the firmware
+    UnknownFormat,
+    /// The firmware is inside of an IMG4 container with an encrypted payload
+    Img4Wrapped,
+    /// The IMG4 container could not be parsed
+    BadImg4(&'static str),
+    /// The IV or key used to decrypt the IMG4 payload is not valid hex of the right size
+    BadKey,
+    /// The firmware ended before a struct or module could be read
+    Truncated { offset: usize, needed: usize },
+    /// A struct could not be (de)serialized
+    BadStruct { name: &'static str, source: binrw::Error },
+    /// A module is not a valid Mach-O, or its load commands are inconsistent
+    BadMachO(&'static str),
+    /// A string needed to find the app table is missing
+    MissingString(&'static str),
+    /// The name of a module is not valid UTF-8 or is empty
+    BadName,
+    /// The number of apps in the app table could not be found
+    BadAppCount,
+    /// The compressed firmware could not be decompressed
+    DecompressFailed,
+    /// The firmware could not be compressed again
+    CompressFailed,
+    /// The LZFSE stream is corrupted
+    BadCompression(&'static str),
+    /// The IPSW or OTA zip could not be read
+    BadZip(zip::result::ZipError),
+    /// No SEP firmware for the device was found in the IPSW or OTA zip
+    NoSepFirmware,
+    /// The IPSW or OTA zip has a SEP firmware for more than one device, and only one can be read
+    ManySepFirmwares(Vec<String>),
+    /// The CRC32 stored in the firmware does not match, only returned in strict mode
+    CrcMismatch { expected: u32, actual: u32 },
+    /// A modified module could not be put back into the firmware
+    Repack { module: String, reason: &'static str },
+    /// No module with this name was found in the firmware
+    NoModule(String),
+    /// The template of the output file names is not valid
+    BadTemplate { template: String, reason: &'static str },
+    /// Errors while reading the input or writing the output
+    Io(std::io::Error),
+}
+
+impl SepSplitError {
+    /// The exit code used by the binary and the C `split` function for this error
+    #[must_use]
+    pub const fn exit_code(&self) -> i32 {
+        match self {
+            Self::Io(_)               => 2,
+            Self::UnknownFormat       => 3,
+            Self::Img4Wrapped
+            | Self::BadImg4(_)        => 4,
+            Self::Truncated { .. }    => 5,
+            Self::BadStruct { .. }    => 6,
+            Self::BadMachO(_)         => 7,
+            Self::MissingString(_)
+            | Self::BadName
+            | Self::BadAppCount       => 8,
+            Self::DecompressFailed
+            | Self::CompressFailed
+            | Self::BadCompression(_) => 9,
+            Self::BadKey              => 10,
+            Self::BadZip(_)           => 11,
+            Self::NoSepFirmware
+            | Self::ManySepFirmwares(_) => 12,
+            Self::CrcMismatch { .. }  => 13,
+            Self::Repack { .. }       => 14,
+            Self::NoModule(_)         => 15,
+            Self::BadTemplate { .. }  => 16,
+        }
+    }
+}
+
+impl fmt::Display for SepSplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat => write!(f, "Invalid or unknown kernel inputted"),
+            Self::Img4Wrapped => write!(f, "IMG4 payload is encrypted, please provide the IV and key"),
+            Self::BadImg4(reason) => write!(f, "Invalid IMG4: {reason}"),
+            Self::BadKey => write!(f, "Invalid IV or key, expected 16 and 32 bytes of hex"),
+            Self::Truncated { offset, needed } => write!(f, "Firmware is truncated, needed {needed:#x} bytes at offset {offset:#x}"),
+            Self::BadStruct { name, source } => write!(f, "Unable to (de)serialize {name}, err: {source}"),
+            Self::BadMachO(reason) => write!(f, "Invalid Mach-O: {reason}"),
+            Self::MissingString(s) => write!(f, "Could not find {s} string"),
+            Self::BadName => write!(f, "Module name is not valid UTF-8"),
+            Self::BadAppCount => write!(f, "Could not find the number of apps"),
+            Self::DecompressFailed => write!(f, "Decompression failed (truncated input?)"),
+            Self::CompressFailed => write!(f, "Compression failed (firmware too large?)"),
+            Self::BadCompression(reason) => write!(f, "Invalid LZFSE stream: {reason}"),
+            Self::BadZip(err) => write!(f, "Invalid zip: {err}"),
+            Self::NoSepFirmware => write!(f, "No SEP firmware found in the archive"),
+            Self::ManySepFirmwares(names) => write!(f, "More than one SEP firmware found in the archive, select the device of one of {}", names.join(", ")),
+            Self::CrcMismatch { expected, actual } => write!(f, "CRC32 mismatch, expected {expected:#010x} but got {actual:#010x} (corrupted or wrongly decrypted?)"),
+            Self::Repack { module, reason } => write!(f, "Unable to repack {module}: {reason}"),
+            Self::NoModule(name) => write!(f, "No module named {name} in the firmware"),
+            Self::BadTemplate { template, reason } => write!(f, "Invalid file name template {template}: {reason}"),
+            Self::Io(err) => write!(f, "I/O error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for SepSplitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::BadStruct { source, .. } => Some(source),
+            Self::BadZip(err) => Some(err),
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SepSplitError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+//the zip errors that come from reading the file are I/O errors, the rest are a bad zip
+impl From<zip::result::ZipError> for SepSplitError {
+    fn from(err: zip::result::ZipError) -> Self {
+        match err {
+            zip::result::ZipError::Io(err) => Self::Io(err),
+            err => Self::BadZip(err),
+        }
+    }
+}
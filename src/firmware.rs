@@ -0,0 +1,595 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//in-memory representation of a split SEP firmware
+
+use std::{
+    borrow::Cow,
+    convert::Infallible,
+    fmt,
+    io::{self, Seek, Write},
+    ops::Range,
+    path::Path,
+    str::FromStr,
+};
+use prettytable::{format, row, Table};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use uuid::Uuid;
+use zip::ZipWriter;
+
+use crate::utils::{glob_match, opt_str, strslice, SEPApp64, SEPDataHDR64, SEPDataHDR64Ver2, SrcVer};
+use crate::{
+    hash::{FirmwareHashes, ModuleHashes},
+    macho::MachO,
+    rebase::Rebase,
+    sink::{DirSink, Sink, TarSink},
+    SepSplitError,
+};
+
+/// The layout of the SEP firmware that was detected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SepFormat {
+    /// 32-bit SEP, `old` if the apps are described by the first version of the bootargs
+    Bits32 { old: bool },
+    /// 64-bit SEP, `subversion` is the legion subversion (2, 3 or 4),
+    /// `old` if the legion header does not point to the SEP HDR struct
+    Bits64 { subversion: u8, old: bool },
+}
+
+/// The fields of the SEP HDR struct of a 64-bit SEP, 0 if not present in this version
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SepHeader {
+    pub kernel_heap_size: u64,      // The size of the kernel's heap
+    pub kernel_base_paddr: u64,     // The address of the kernel in the firmware
+    pub kernel_max_paddr: u64,      // The maximum address of the kernel in the firmware
+    pub app_images_base_paddr: u64, // The address of the apps in the firmware
+    pub app_images_max_paddr: u64,  // The maximum address of the apps in the firmware
+    pub paddr_max: u64,             // The size of the SEP firmware image
+    pub tz0_min_size: u64,          // The minimum size of the TZ0 region
+    pub tz1_min_size: u64,          // The minimum size of the TZ1 region
+    pub ar_min_size: u64,           // The minimum size of the Anti Replay region
+    pub non_ar_min_size: u64,       // The minimum size of the non-Anti Replay region
+    pub shm_base: u64,              // The base address of the shared memory region
+    pub shm_size: u64,              // The size of the shared memory region
+    pub crc32: u32,                 // CRC32 of all of the apps after SEPOS
+    pub coredump_sup: bool,         // If coredumps are supported
+    pub n_apps: u32,                // The number of apps
+    pub n_shlibs: u32,              // The number of shared libraries
+}
+
+/// A CRC32 stored in the firmware, checked against the region it describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrcCheck {
+    pub expected: u32, // The CRC32 stored in the firmware
+    pub actual: u32,   // The CRC32 of the region
+    pub start: u64,    // The start of the region in the firmware
+    pub end: u64,      // The end of the region in the firmware
+    pub offset: u64,   // Where the CRC32 is stored in the firmware
+}
+
+/// What part of the firmware a module was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModuleKind {
+    Boot,       // everything before the kernel
+    Kernel,     // the SEP kernel
+    Struct,     // the structs before SEPOS
+    Rootserver, // SEPOS
+    App,        // an app
+    #[serde(rename = "shlib")]
+    SharedLib,  // a shared library, placed after the apps
+}
+
+/// Selects the modules to split
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleSelector {
+    Name(String), // A glob of the module name, with * and ?, ignoring case
+    Index(usize), // The index of the module, like in the file names
+    Uuid(Uuid),   // The UUID of the module
+}
+
+//a part of a module that was fixed while splitting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Patch {
+    Bytes(Vec<u8>),      // the fixed load commands
+    Image(Range<usize>), // bytes from elsewhere in the firmware, like the rw segments
+}
+
+//a module as a range of the firmware with the fixed parts patched over it,
+//so only the bytes that change are copied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleBytes {
+    pub(crate) range: Range<usize>,
+    pub(crate) patches: Vec<(usize, Patch)>, // sorted by offset, never overlapping
+    pub(crate) unfixed: Option<&'static str>, // why the load commands could not be fixed, if they could not
+}
+
+impl ModuleBytes {
+    pub(crate) const fn new(range: Range<usize>) -> Self {
+        Self { range, patches: Vec::new(), unfixed: None }
+    }
+
+    const fn len(&self) -> usize {
+        self.range.end - self.range.start
+    }
+
+    //patches the module at `at`, older patches it covers are replaced and the ones it only overlaps are merged into it
+    pub(crate) fn patch(&mut self, image: &[u8], at: usize, patch: Patch) {
+        let patch_len = |patch: &Patch| match patch {
+            Patch::Bytes(bytes) => bytes.len(),
+            Patch::Image(range) => range.len(),
+        };
+        let end = at + patch_len(&patch);
+        let overlaps = |(off, old): &(usize, Patch)| *off < end && at < off + patch_len(old);
+        if self.patches.iter().filter(|p| overlaps(p)).all(|(off, old)| at <= *off && off + patch_len(old) <= end) {
+            self.patches.retain(|p| !overlaps(p));
+            let pos = self.patches.partition_point(|(off, _)| *off < at);
+            self.patches.insert(pos, (at, patch));
+            return
+        }
+
+        let mut merged = self.to_vec(image);
+        let bytes = match &patch {
+            Patch::Bytes(bytes) => bytes.as_slice(),
+            Patch::Image(range) => &image[range.clone()],
+        };
+        merged[at..end].copy_from_slice(bytes);
+        self.patches = vec![(0, Patch::Bytes(merged))];
+    }
+
+    fn write(&self, image: &[u8], out: &mut impl Write) -> io::Result<()> {
+        let base = &image[self.range.clone()];
+        let mut pos = 0;
+        for (off, patch) in &self.patches {
+            out.write_all(&base[pos..*off])?;
+            let bytes = match patch {
+                Patch::Bytes(bytes) => bytes.as_slice(),
+                Patch::Image(range) => &image[range.clone()],
+            };
+            out.write_all(bytes)?;
+            pos = off + bytes.len();
+        }
+        out.write_all(&base[pos..])
+    }
+
+    pub(crate) fn to_vec(&self, image: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.len());
+        self.write(image, &mut out).expect("writing to a Vec can not fail");
+        out
+    }
+}
+
+/// A single module split from the SEP firmware
+#[derive(Debug, Clone, Serialize)]
+pub struct SepModule {
+    pub kind: ModuleKind,
+    pub index: Option<usize>,   // The index of the module, not present for the struct dump
+    pub name: String,           // The name of the module, without padding
+    pub uuid: Option<Uuid>,     // The UUID of the module as stored in the SEP app table
+    pub phys_text: u64,         // The address of the module's Mach-O in the firmware
+    pub size_text: u64,         // The size of the module's Mach-O (without the rw segments, if they are seperate)
+    pub phys_data: u64,         // The address of the module's rw segments, 0 if not seperate
+    pub size_data: u64,         // The size of the module's rw segments, 0 if not seperate
+    pub virt: u64,              // The virtual address of the module
+    pub entry: u64,             // The entry/main function of the module
+    pub srcver: Option<SrcVer>, // The source version of the module as stored in the SEP app table
+    pub lc_uuid: Option<Uuid>,  // The UUID in the module's LC_UUID, if it is a Mach-O with one, read like the UUIDs of the SEP structs
+    pub lc_srcver: Option<SrcVer>, // The source version in the module's LC_SOURCE_VERSION, if it is a Mach-O with one
+    pub stack_size: u64,        // The size of the module's stack, 0 if not known
+    pub stack_paddr: u64,       // The physical address of the module's stack, 0 if not known (only SEPOS has one)
+    pub mem_size: u64,          // The size of the module's memory, 0 if not known
+    pub heap_mem_size: u64,     // The size of the module's heap, 0 if not known
+    #[serde(serialize_with = "compact_ver")]
+    pub compact_ver: Option<(u32, u32)>, // The start and end of the compact version (0xFFFF_FFFF if not versioned)
+    #[serde(skip)]
+    pub(crate) size_fields: Option<(usize, Option<usize>)>, // The offsets of the size_text and size_data fields in the SEP app table
+    #[serde(rename = "file")]
+    pub file_name: String,      // The name of the file this module is written to, see NameTemplate
+    pub hashes: Option<ModuleHashes>, // The hashes of the module, only once it is split (not for firmware_info)
+    pub rebase: Option<Rebase>, // How the module was moved to its virtual address, only if it was rebased
+    #[serde(skip)]
+    pub(crate) bytes: ModuleBytes, // The reconstructed module, see SepFirmware::macho
+}
+
+/// A SEP firmware split into its modules, borrowing the firmware image when it is not compressed
+#[derive(Debug, Clone)]
+pub struct SepFirmware<'a> {
+    pub format: SepFormat,
+    pub hdr_offset: u64,           // The offset of the SEP HDR struct (64-bit SEP) or of the monitor boot args (32-bit SEP)
+    pub hdr_uuid: Option<Uuid>,    // The UUID in the legion header, only for iOS 16 and later
+    pub header: Option<SepHeader>, // The SEP HDR struct, only for 64-bit SEP
+    pub crc: Option<CrcCheck>,     // The CRC32 of the apps (of SEPOS for 32-bit SEP), if the firmware has one
+    pub hashes: Option<FirmwareHashes>, // The hashes of the input and of the image, only once it is split
+    pub modules: Vec<SepModule>,
+    pub warnings: Vec<String>,       // The modules that could not be fixed or rebased, or do not match the SEP app table
+    pub(crate) image: Cow<'a, [u8]>, // The decompressed firmware, the modules are ranges of it
+}
+
+impl SepModule {
+    //module not described by the SEP app table (boot, kernel, struct)
+    pub(crate) fn raw(kind: ModuleKind, index: Option<usize>, name: &str, phys: u64, bytes: ModuleBytes) -> Self {
+        Self {
+            kind,
+            index,
+            name: name.to_owned(),
+            uuid: None,
+            phys_text: phys,
+            size_text: bytes.len() as u64,
+            phys_data: 0,
+            size_data: 0,
+            virt: 0,
+            entry: 0,
+            srcver: None,
+            lc_uuid: None,
+            lc_srcver: None,
+            stack_size: 0,
+            stack_paddr: 0,
+            mem_size: 0,
+            heap_mem_size: 0,
+            compact_ver: None,
+            size_fields: None,
+            file_name: String::new(),
+            hashes: None,
+            rebase: None,
+            bytes,
+        }
+    }
+
+    //module described by a 64-bit SEP app table entry
+    pub(crate) fn from_app64(kind: ModuleKind, index: usize, app: &SEPApp64, is_old: bool, bytes: ModuleBytes) -> Result<Self, SepSplitError> {
+        Ok(Self {
+            uuid: Some(Uuid::from_bytes_le(app.app_uuid)),
+            size_text: app.size_text,
+            phys_data: app.phys_data,
+            size_data: app.size_data,
+            virt: app.virt,
+            entry: app.ventry,
+            srcver: (!is_old).then_some(app.srcver),
+            stack_size: app.stack_size,
+            mem_size: app.mem_size,
+            heap_mem_size: app.heap_mem_size,
+            compact_ver: Some((app.compact_ver_start, app.compact_ver_end)),
+            ..Self::raw(kind, Some(index), strslice(&app.app_name)?, app.phys_text, bytes)
+        })
+    }
+
+    /// The size of the reconstructed module
+    #[must_use]
+    pub const fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// If the module's `LC_UUID` is not the UUID in the SEP app table
+    #[must_use]
+    pub fn uuid_mismatch(&self) -> bool {
+        matches!((self.uuid, self.lc_uuid), (Some(table), Some(lc)) if table != lc)
+    }
+
+    /// If the module's `LC_SOURCE_VERSION` is not the source version in the SEP app table
+    #[must_use]
+    pub fn srcver_mismatch(&self) -> bool {
+        matches!((self.srcver, self.lc_srcver), (Some(table), Some(lc)) if table != lc)
+    }
+
+    /// What does not match between the SEP app table and the load commands, like `["uuid", "srcver"]`
+    #[must_use]
+    pub fn mismatches(&self) -> Vec<&'static str> {
+        [("uuid", self.uuid_mismatch()), ("srcver", self.srcver_mismatch())].into_iter()
+            .filter_map(|(field, mismatch)| mismatch.then_some(field))
+            .collect()
+    }
+}
+
+//the compact version as {start, end}
+#[allow(clippy::ref_option)] //serde passes the field by reference
+fn compact_ver<S: Serializer>(compact_ver: &Option<(u32, u32)>, serializer: S) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct CompactVer {
+        start: u32,
+        end: u32,
+    }
+    compact_ver.map(|(start, end)| CompactVer { start, end }).serialize(serializer)
+}
+
+impl ModuleKind {
+    /// The name of the kind, as used in the manifest
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Boot => "boot",
+            Self::Kernel => "kernel",
+            Self::Struct => "struct",
+            Self::Rootserver => "rootserver",
+            Self::App => "app",
+            Self::SharedLib => "shlib",
+        }
+    }
+}
+
+impl ModuleSelector {
+    /// Checks if the selector matches the module
+    #[must_use]
+    pub fn matches(&self, module: &SepModule) -> bool {
+        self.selects(module.index, &module.name, module.uuid)
+    }
+
+    pub(crate) fn selects(&self, index: Option<usize>, name: &str, uuid: Option<Uuid>) -> bool {
+        match self {
+            Self::Name(glob) => glob_match(glob, name),
+            Self::Index(i) => index == Some(*i),
+            Self::Uuid(u) => uuid == Some(*u),
+        }
+    }
+}
+
+//an index if it is a number, a UUID if it parses as one, a name glob otherwise
+impl FromStr for ModuleSelector {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.parse().map_or_else(|_| Uuid::parse_str(s).map_or_else(|_| Self::Name(s.to_owned()), Self::Uuid), Self::Index))
+    }
+}
+
+impl fmt::Display for ModuleSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(glob) => write!(f, "{glob}"),
+            Self::Index(i) => write!(f, "{i}"),
+            Self::Uuid(uuid) => write!(f, "{uuid}"),
+        }
+    }
+}
+
+impl CrcCheck {
+    /// Checks if the CRC32 of the region matches the one stored in the firmware
+    #[must_use]
+    pub const fn matches(&self) -> bool {
+        self.expected == self.actual
+    }
+
+    /// The CRC32 check as pretty printed JSON, like in the manifest
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        crate::manifest::to_json(self)
+    }
+}
+
+impl Serialize for CrcCheck {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut crc = serializer.serialize_struct("CrcCheck", 6)?;
+        crc.serialize_field("expected", &self.expected)?;
+        crc.serialize_field("actual", &self.actual)?;
+        crc.serialize_field("start", &self.start)?;
+        crc.serialize_field("end", &self.end)?;
+        crc.serialize_field("offset", &self.offset)?;
+        crc.serialize_field("matches", &self.matches())?;
+        crc.end()
+    }
+}
+
+impl fmt::Display for CrcCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CRC32 of {:#x}..{:#x} is {:#010x}, ", self.start, self.end, self.actual)?;
+        if self.matches() {
+            write!(f, "matches")
+        } else {
+            write!(f, "MISMATCH, expected {:#010x}", self.expected)
+        }
+    }
+}
+
+//the format as {generation, bits, subversion, old}, generation is how it is displayed
+impl Serialize for SepFormat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (bits, subversion, old) = match *self {
+            Self::Bits32 { old } => (32u32, None, old),
+            Self::Bits64 { subversion, old } => (64, Some(subversion), old),
+        };
+        let mut format = serializer.serialize_struct("SepFormat", 4)?;
+        format.serialize_field("generation", &self.to_string())?;
+        format.serialize_field("bits", &bits)?;
+        format.serialize_field("subversion", &subversion)?;
+        format.serialize_field("old", &old)?;
+        format.end()
+    }
+}
+
+impl fmt::Display for SepFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bits32 { old } => write!(f, "32-bit{}", if *old { " (old)" } else { "" }),
+            Self::Bits64 { subversion, old } => write!(f, "64-bit ver{subversion}{}", if *old { " (old)" } else { "" }),
+        }
+    }
+}
+
+impl SepHeader {
+    pub(crate) const fn from_hdr64(hdr: &SEPDataHDR64, n_apps: u32, n_shlibs: u32) -> Self {
+        Self {
+            kernel_heap_size: hdr.kernel_heap_size,
+            kernel_base_paddr: hdr.kernel_base_paddr,
+            kernel_max_paddr: hdr.kernel_max_paddr,
+            app_images_base_paddr: hdr.app_images_base_paddr,
+            app_images_max_paddr: hdr.app_images_max_paddr,
+            paddr_max: hdr.paddr_max,
+            tz0_min_size: hdr.tz0_min_size,
+            tz1_min_size: hdr.tz1_min_size,
+            ar_min_size: hdr.ar_min_size,
+            non_ar_min_size: hdr.non_ar_min_size,
+            shm_base: hdr.shm_base,
+            shm_size: hdr.shm_size,
+            crc32: hdr.crc32.val,
+            coredump_sup: hdr.coredump_sup != 0,
+            n_apps,
+            n_shlibs,
+        }
+    }
+
+    /// Every field with its name as in the manifest, `coredump_sup` is 0 or 1
+    #[must_use]
+    pub fn fields(&self) -> [(&'static str, u64); 16] {
+        [
+            ("kernel_heap_size", self.kernel_heap_size),
+            ("kernel_base_paddr", self.kernel_base_paddr),
+            ("kernel_max_paddr", self.kernel_max_paddr),
+            ("app_images_base_paddr", self.app_images_base_paddr),
+            ("app_images_max_paddr", self.app_images_max_paddr),
+            ("paddr_max", self.paddr_max),
+            ("tz0_min_size", self.tz0_min_size),
+            ("tz1_min_size", self.tz1_min_size),
+            ("ar_min_size", self.ar_min_size),
+            ("non_ar_min_size", self.non_ar_min_size),
+            ("shm_base", self.shm_base),
+            ("shm_size", self.shm_size),
+            ("crc32", u64::from(self.crc32)),
+            ("coredump_sup", u64::from(self.coredump_sup)),
+            ("n_apps", u64::from(self.n_apps)),
+            ("n_shlibs", u64::from(self.n_shlibs)),
+        ]
+    }
+
+    pub(crate) fn from_hdr64_ver2(hdr: &SEPDataHDR64Ver2) -> Self {
+        Self {
+            kernel_base_paddr: hdr.kernel_base_paddr,
+            kernel_max_paddr: hdr.kernel_max_paddr,
+            crc32: hdr.crc32.val,
+            coredump_sup: hdr.coredump_sup != 0,
+            n_apps: hdr.n_apps,
+            n_shlibs: hdr.n_shlibs,
+            ..Self::default()
+        }
+    }
+}
+
+impl SepFirmware<'_> {
+    pub(crate) const fn new(format: SepFormat) -> Self {
+        Self { format, hdr_offset: 0, hdr_uuid: None, header: None, crc: None, hashes: None, modules: Vec::new(), warnings: Vec::new(), image: Cow::Borrowed(&[]) }
+    }
+
+    /// The decompressed firmware image the modules were split from
+    #[must_use]
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+
+    /// The reconstructed module, only copied if it had to be fixed
+    #[must_use]
+    pub fn macho(&self, module: &SepModule) -> Cow<'_, [u8]> {
+        if module.bytes.patches.is_empty() {
+            Cow::Borrowed(&self.image[module.bytes.range.clone()])
+        } else {
+            Cow::Owned(module.bytes.to_vec(&self.image))
+        }
+    }
+
+    /// Writes the reconstructed module without copying it
+    /// # Errors
+    /// * Errors while writing to `out`
+    pub fn write_module(&self, module: &SepModule, out: &mut impl Write) -> io::Result<()> {
+        module.bytes.write(&self.image, out)
+    }
+
+    /// Finds a module by its name, or by the name of the file it is written to
+    #[must_use]
+    pub fn module(&self, name: &str) -> Option<&SepModule> {
+        self.modules.iter().find(|m| m.name == name).or_else(|| self.modules.iter().find(|m| m.file_name == name))
+    }
+
+    //reads LC_UUID and LC_SOURCE_VERSION of every module, warning about the ones that do not match the SEP app table
+    //and about the ones that could not be fixed
+    pub(crate) fn read_load_commands(&mut self) {
+        for module in &mut self.modules {
+            if let Some(err) = module.bytes.unfixed {
+                self.warnings.push(format!("the load commands of {} could not be fixed: {err}", module.name));
+            }
+            let Ok(macho) = MachO::parse(&self.image[module.bytes.range.clone()]) else { continue };
+            //the SEP structs have a copy of the uuid_t of LC_UUID, and their UUIDs are read as little endian
+            module.lc_uuid = macho.uuid().map(|uuid| Uuid::from_bytes_le(*uuid.as_bytes()));
+            module.lc_srcver = macho.source_version();
+            if module.uuid_mismatch() {
+                self.warnings.push(format!("LC_UUID of {} is {}, but the SEP app table has {}, the offsets may be wrong",
+                    module.name, opt_str(module.lc_uuid), opt_str(module.uuid)));
+            }
+            if module.srcver_mismatch() {
+                self.warnings.push(format!("LC_SOURCE_VERSION of {} is {}, but the SEP app table has {}, the offsets may be wrong",
+                    module.name, opt_str(module.lc_srcver), opt_str(module.srcver)));
+            }
+        }
+    }
+
+    /// A table of the modules, with their index, kind, name, file name, size and UUID,
+    /// and what does not match between the SEP app table and the load commands
+    #[must_use]
+    pub fn module_table(&self) -> String {
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_BOX_CHARS);
+        table.set_titles(row!["#", "Kind", "Name", "File", "Size", "UUID", "Mismatch"]);
+        for module in &self.modules {
+            table.add_row(row![
+                r->module.index.map_or_else(String::new, |i| i.to_string()),
+                module.kind.as_str(),
+                module.name,
+                module.file_name,
+                r->format!("{:#x}", module.size()),
+                module.uuid.map_or_else(String::new, |uuid| uuid.to_string()),
+                module.mismatches().join(", "),
+            ]);
+        }
+        table.to_string()
+    }
+
+    /// Writes every module into `outdir`, using the module's `file_name`, and the manifest as `manifest.json`
+    /// # Errors
+    /// * Errors while writing to the output directory
+    pub fn write_to(&self, outdir: &Path) -> Result<(), std::io::Error> {
+        self.write_sink(&mut DirSink { dir: outdir, clobber: true }).map(|_| ())
+    }
+
+    /// Writes the manifest and every module into a tar archive, the manifest first
+    /// # Errors
+    /// * Errors while writing to `out`
+    pub fn write_tar(&self, out: impl Write) -> Result<(), std::io::Error> {
+        let mut tar = TarSink::new(out);
+        self.write_sink(&mut tar)?;
+        tar.finish().map(|_| ())
+    }
+
+    /// Writes the manifest and every module into a zip archive, the manifest first and every file stored uncompressed
+    /// # Errors
+    /// * Errors while writing to `out`
+    pub fn write_zip(&self, out: impl Write + Seek) -> Result<(), std::io::Error> {
+        let mut zip = ZipWriter::new(out);
+        self.write_sink(&mut zip)?;
+        zip.finish()?;
+        Ok(())
+    }
+
+    //writes the manifest and then the modules
+    //returns the name of every file and if it was written
+    pub(crate) fn write_sink(&self, sink: &mut dyn Sink) -> Result<Vec<(String, bool)>, std::io::Error> {
+        let manifest = "manifest.json".to_owned();
+        let written = sink.add_bytes(&manifest, self.to_json().as_bytes())?;
+        let mut files = vec![(manifest, written)];
+        for module in &self.modules {
+            let written = sink.add(&module.file_name, module.size() as u64, &mut |mut out| self.write_module(module, &mut out))?;
+            files.push((module.file_name.clone(), written));
+        }
+        Ok(files)
+    }
+}
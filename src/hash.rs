@@ -0,0 +1,152 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Hashes to tell which firmwares and modules are the same across builds, SHA-256 and optionally SHA-1 of:
+        input   the firmware as it was given, still in its IM4P if it had one
+        image   the firmware once it is decrypted and decompressed, the modules are ranges of it
+        raw     each module's bytes at phys_text in the image, before anything is fixed
+        fixed   each module as it is written, with its load commands and rw segments fixed (and slid with --rebase)
+*/
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use serde::{Serialize, Serializer};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::firmware::{SepFirmware, SepModule};
+
+/// The SHA-256 of some bytes, and their SHA-1 if it was asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Digests {
+    #[serde(serialize_with = "hex_digest")]
+    pub sha256: [u8; 32],
+    #[serde(serialize_with = "hex_sha1")]
+    pub sha1: Option<[u8; 20]>,
+}
+
+/// The hashes of a module, as it is stored in the firmware and as it is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ModuleHashes {
+    pub raw: Digests,   // The bytes at phys_text, before its load commands and rw segments are fixed
+    pub fixed: Digests, // The reconstructed module, see SepFirmware::macho
+}
+
+/// The hashes of a firmware, as it was given and once it is decrypted and decompressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FirmwareHashes {
+    pub input: Digests,
+    pub image: Digests,
+}
+
+//hashes everything written to it
+struct Hasher {
+    sha256: Sha256,
+    sha1: Option<Sha1>,
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sha256.update(buf);
+        if let Some(sha1) = &mut self.sha1 {
+            sha1.update(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Hasher {
+    fn new(sha1: bool) -> Self {
+        Self { sha256: Sha256::new(), sha1: sha1.then(Sha1::new) }
+    }
+
+    fn finish(self) -> Digests {
+        Digests { sha256: self.sha256.finalize().into(), sha1: self.sha1.map(|sha1| sha1.finalize().into()) }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
+
+//the digests are lowercase hex in the JSON
+fn hex_digest<S: Serializer>(digest: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex(digest))
+}
+
+#[allow(clippy::ref_option)] //serde passes the field by reference
+fn hex_sha1<S: Serializer>(digest: &Option<[u8; 20]>, serializer: S) -> Result<S::Ok, S::Error> {
+    digest.as_ref().map(|digest| hex(digest)).serialize(serializer)
+}
+
+impl Digests {
+    /// Hashes `data`, with SHA-1 too if `sha1`
+    #[must_use]
+    pub fn of(data: &[u8], sha1: bool) -> Self {
+        let mut hasher = Hasher::new(sha1);
+        hasher.sha256.update(data);
+        if let Some(sha1) = &mut hasher.sha1 {
+            sha1.update(data);
+        }
+        hasher.finish()
+    }
+
+    /// The SHA-256 as lowercase hex
+    #[must_use]
+    pub fn sha256_hex(&self) -> String {
+        hex(&self.sha256)
+    }
+
+    /// The SHA-1 as lowercase hex, if it was computed
+    #[must_use]
+    pub fn sha1_hex(&self) -> Option<String> {
+        self.sha1.as_ref().map(|sha1| hex(sha1))
+    }
+}
+
+impl SepFirmware<'_> {
+    /// Hashes a module before and after it is fixed, the fixed one is streamed so it is never copied
+    #[must_use]
+    pub fn module_hashes(&self, module: &SepModule, sha1: bool) -> ModuleHashes {
+        let raw = Digests::of(&self.image[module.bytes.range.clone()], sha1);
+        let mut hasher = Hasher::new(sha1);
+        //writing to the hasher can not fail
+        let _ = self.write_module(module, &mut hasher);
+        ModuleHashes { raw, fixed: hasher.finish() }
+    }
+
+    //fills in the hashes of the firmware and of every module
+    pub(crate) fn hash(&mut self, input: &[u8], sha1: bool) {
+        self.hashes = Some(FirmwareHashes { input: Digests::of(input, sha1), image: Digests::of(&self.image, sha1) });
+        let hashes: Vec<_> = self.modules.iter().map(|module| self.module_hashes(module, sha1)).collect();
+        for (module, hashes) in self.modules.iter_mut().zip(hashes) {
+            module.hashes = Some(hashes);
+        }
+    }
+}
@@ -0,0 +1,375 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Minimal DER parsing of IMG4 containers, only what is needed to get to the payload.
+    IM4P ::= SEQUENCE {
+        IA5String "IM4P",
+        IA5String type,           // "sepi" for the SEP firmware
+        IA5String description,
+        OCTET STRING payload,
+        OCTET STRING kbag,        // optional, DER encoded SEQUENCE OF Keybag
+        SEQUENCE compression,     // optional, { INTEGER algorithm, INTEGER uncompressed size }
+    }
+    Keybag ::= SEQUENCE { INTEGER type, OCTET STRING iv, OCTET STRING key }
+    A full IMG4 is SEQUENCE { IA5String "IMG4", IM4P, [0] IM4M, [1] IM4R }, the IM4P is used.
+*/
+
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use crate::SepSplitError;
+
+const TAG_INTEGER:      u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_IA5STRING:    u8 = 0x16;
+const TAG_SEQUENCE:     u8 = 0x30;
+
+/// A keybag of an IM4P, the IV and key are encrypted with the device's GID key
+#[derive(Debug, Clone, Copy)]
+pub struct Keybag<'a> {
+    pub kind: u64, // 1 for production, 2 for development
+    pub iv: &'a [u8],
+    pub key: &'a [u8],
+}
+
+/// The decrypted IV and key of a keybag, used to decrypt the payload with AES-256-CBC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecryptKey {
+    pub iv: [u8; 16],
+    pub key: [u8; 32],
+}
+
+/// The compression info of an IM4P payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub algorithm: u64, // 1 for LZFSE
+    pub size: u64,      // The size of the decompressed payload
+}
+
+/// A parsed IM4P, borrowing from the input
+#[derive(Debug, Clone)]
+pub struct Im4p<'a> {
+    pub fourcc: &'a str,      // The type of the payload, "sepi" for the SEP firmware
+    pub description: &'a str, // The build description
+    pub payload: &'a [u8],    // The (possibly encrypted or compressed) payload
+    pub kbags: Vec<Keybag<'a>>, // Empty if the payload is not encrypted
+    pub compression: Option<Compression>, // Present if the payload is compressed
+}
+
+//splits off one DER element, returning the tag, the contents and the rest of the buffer
+fn read_tlv(buf: &[u8]) -> Result<(u8, &[u8], &[u8]), SepSplitError> {
+    let (&tag, rest) = buf.split_first().ok_or(SepSplitError::BadImg4("missing tag"))?;
+    let (&first, mut rest) = rest.split_first().ok_or(SepSplitError::BadImg4("missing length"))?;
+    let len = if first & 0x80 == 0 {
+        usize::from(first)
+    } else {
+        //long form, the low bits are the number of length bytes
+        let n = usize::from(first & 0x7f);
+        if n == 0 || n > std::mem::size_of::<usize>() || rest.len() < n {
+            return Err(SepSplitError::BadImg4("invalid length"))
+        }
+        let (lenbytes, tail) = rest.split_at(n);
+        rest = tail;
+        lenbytes.iter().fold(0, |acc, &b| acc << 8 | usize::from(b))
+    };
+    if rest.len() < len {
+        return Err(SepSplitError::BadImg4("element is truncated"))
+    }
+    let (contents, rest) = rest.split_at(len);
+    Ok((tag, contents, rest))
+}
+
+//same as above, but errors if the tag is not the expected one
+fn expect_tlv(buf: &[u8], expected: u8) -> Result<(&[u8], &[u8]), SepSplitError> {
+    match read_tlv(buf)? {
+        (tag, contents, rest) if tag == expected => Ok((contents, rest)),
+        _ => Err(SepSplitError::BadImg4("unexpected tag"))
+    }
+}
+
+fn read_string(buf: &[u8]) -> Result<(&str, &[u8]), SepSplitError> {
+    let (contents, rest) = expect_tlv(buf, TAG_IA5STRING)?;
+    let string = std::str::from_utf8(contents).map_err(|_| SepSplitError::BadImg4("string is not valid UTF-8"))?;
+    Ok((string, rest))
+}
+
+fn read_integer(buf: &[u8]) -> Result<(u64, &[u8]), SepSplitError> {
+    let (contents, rest) = expect_tlv(buf, TAG_INTEGER)?;
+    if contents.is_empty() || contents.len() > 9 {
+        return Err(SepSplitError::BadImg4("invalid integer"))
+    }
+    Ok((contents.iter().fold(0, |acc, &b| acc << 8 | u64::from(b)), rest))
+}
+
+//gets the magic string of the outer sequence, if there is one
+fn magic(buf: &[u8]) -> Option<&str> {
+    let (contents, _) = expect_tlv(buf, TAG_SEQUENCE).ok()?;
+    read_string(contents).ok().map(|(magic, _)| magic)
+}
+
+/// Checks if the buffer is an IM4P or a full IMG4
+#[must_use]
+pub fn is_img4(buf: &[u8]) -> bool {
+    matches!(magic(buf), Some("IM4P" | "IMG4"))
+}
+
+impl<'a> Im4p<'a> {
+    /// Parses an IM4P, or the IM4P inside of a full IMG4
+    /// # Errors
+    /// * The buffer is not a valid IM4P or IMG4
+    pub fn parse(buf: &'a [u8]) -> Result<Self, SepSplitError> {
+        let (contents, _) = expect_tlv(buf, TAG_SEQUENCE)?;
+        let (magic, rest) = read_string(contents)?;
+        match magic {
+            "IMG4" => return Self::parse(rest),
+            "IM4P" => (),
+            _ => return Err(SepSplitError::BadImg4("not an IM4P"))
+        }
+
+        let (fourcc, rest) = read_string(rest)?;
+        let (description, rest) = read_string(rest)?;
+        let (payload, rest) = expect_tlv(rest, TAG_OCTET_STRING)?;
+
+        let mut kbags = Vec::new();
+        let mut rest = rest;
+        if let Ok((kbag, tail)) = expect_tlv(rest, TAG_OCTET_STRING) {
+            rest = tail;
+            let (mut bags, _) = expect_tlv(kbag, TAG_SEQUENCE)?;
+            while !bags.is_empty() {
+                let (bag, tail) = expect_tlv(bags, TAG_SEQUENCE)?;
+                bags = tail;
+                let (kind, bag) = read_integer(bag)?;
+                let (iv, bag) = expect_tlv(bag, TAG_OCTET_STRING)?;
+                let (key, _) = expect_tlv(bag, TAG_OCTET_STRING)?;
+                kbags.push(Keybag { kind, iv, key });
+            }
+        }
+
+        let compression = match expect_tlv(rest, TAG_SEQUENCE) {
+            Ok((info, _)) => {
+                let (algorithm, info) = read_integer(info)?;
+                let (size, _) = read_integer(info)?;
+                Some(Compression { algorithm, size })
+            },
+            Err(_) => None
+        };
+
+        Ok(Self { fourcc, description, payload, kbags, compression })
+    }
+
+    /// Checks if the payload is encrypted
+    #[must_use]
+    pub const fn is_encrypted(&self) -> bool {
+        !self.kbags.is_empty()
+    }
+}
+
+//decode a hex string into a fixed size array
+fn from_hex<const N: usize>(hex: &str) -> Result<[u8; N], SepSplitError> {
+    let hex = hex.trim().trim_start_matches("0x");
+    //only hex digits, from_str_radix would also take a sign
+    if hex.len() != N * 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(SepSplitError::BadKey)
+    }
+    let mut out = [0; N];
+    for (byte, digits) in out.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        //infallable, the string is checked to be hex digits
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).unwrap(), 16).map_err(|_| SepSplitError::BadKey)?;
+    }
+    Ok(out)
+}
+
+impl DecryptKey {
+    /// Parses the IV and key from seperate hex strings
+    /// # Errors
+    /// * The IV is not 16 bytes or the key is not 32 bytes of hex
+    pub fn from_hex_parts(iv: &str, key: &str) -> Result<Self, SepSplitError> {
+        Ok(Self { iv: from_hex(iv)?, key: from_hex(key)? })
+    }
+
+    /// Parses the IV and key from one hex string, with the IV first (like img4lib's `-k`)
+    /// # Errors
+    /// * The string is not 48 bytes of hex
+    pub fn from_hex(ivkey: &str) -> Result<Self, SepSplitError> {
+        let ivkey: [u8; 48] = from_hex(ivkey)?;
+        let mut out = Self { iv: [0; 16], key: [0; 32] };
+        out.iv.copy_from_slice(&ivkey[..16]);
+        out.key.copy_from_slice(&ivkey[16..]);
+        Ok(out)
+    }
+
+    /// Decrypts the payload, a trailing partial block is left as is
+    #[must_use]
+    pub fn decrypt(&self, payload: &[u8]) -> Vec<u8> {
+        let mut out = payload.to_owned();
+        let mut cipher = cbc::Decryptor::<aes::Aes256>::new(&self.key.into(), &self.iv.into());
+        for block in out.chunks_exact_mut(16) {
+            cipher.decrypt_block_mut(block.into());
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_img4, read_tlv, DecryptKey, Im4p, TAG_INTEGER, TAG_OCTET_STRING, TAG_IA5STRING, TAG_SEQUENCE};
+    use crate::SepSplitError;
+
+    //a DER element, with the long form of the length when it needs it
+    fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let len = contents.len();
+        let mut out = vec![tag];
+        match len {
+            0..=0x7f => out.push(len as u8),
+            0x80..=0xff => out.extend([0x81, len as u8]),
+            _ => out.extend([0x82, (len >> 8) as u8, len as u8]),
+        }
+        out.extend_from_slice(contents);
+        out
+    }
+
+    fn seq(elements: &[Vec<u8>]) -> Vec<u8> {
+        tlv(TAG_SEQUENCE, &elements.concat())
+    }
+
+    fn string(s: &str) -> Vec<u8> {
+        tlv(TAG_IA5STRING, s.as_bytes())
+    }
+
+    fn im4p_der(payload: &[u8], kbags: &[(u8, [u8; 16], [u8; 32])], compression: Option<(u8, u32)>) -> Vec<u8> {
+        let mut elements = vec![string("IM4P"), string("sepi"), string("AppleSEPOS-2044.0.0"), tlv(TAG_OCTET_STRING, payload)];
+        if !kbags.is_empty() {
+            let bags: Vec<_> = kbags.iter().map(|(kind, iv, key)| seq(&[tlv(TAG_INTEGER, &[*kind]), tlv(TAG_OCTET_STRING, iv), tlv(TAG_OCTET_STRING, key)])).collect();
+            elements.push(tlv(TAG_OCTET_STRING, &seq(&bags)));
+        }
+        if let Some((algorithm, size)) = compression {
+            //a leading zero keeps the size positive, like in Apple's IM4Ps
+            elements.push(seq(&[tlv(TAG_INTEGER, &[algorithm]), tlv(TAG_INTEGER, &[&[0][..], &size.to_be_bytes()].concat())]));
+        }
+        seq(&elements)
+    }
+
+    //the test vector of CBC-AES256 in NIST SP 800-38A, F.2.5
+    const KEY: &str = "603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4";
+    const IV: &str = "000102030405060708090a0b0c0d0e0f";
+    const PLAIN: [u8; 32] = [
+        0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a,
+        0xae, 0x2d, 0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c, 0x9e, 0xb7, 0x6f, 0xac, 0x45, 0xaf, 0x8e, 0x51,
+    ];
+    const CIPHER: [u8; 32] = [
+        0xf5, 0x8c, 0x4c, 0x04, 0xd6, 0xe5, 0xf1, 0xba, 0x77, 0x9e, 0xab, 0xfb, 0x5f, 0x7b, 0xfb, 0xd6,
+        0x9c, 0xfc, 0x4e, 0x96, 0x7e, 0xdb, 0x80, 0x8d, 0x67, 0x9f, 0x77, 0x7b, 0xc6, 0x70, 0x2c, 0x7d,
+    ];
+
+    #[test]
+    fn test_read_tlv() {
+        assert_eq!(read_tlv(&[0x04, 0x02, 1, 2, 3]).unwrap(), (0x04, &[1, 2][..], &[3][..]));
+        let long = [&[0x04, 0x82, 0x01, 0x00][..], &[0xaa; 0x100]].concat();
+        assert_eq!(read_tlv(&long).unwrap().1.len(), 0x100);
+        for (der, reason) in [
+            (&[][..], "missing tag"),
+            (&[0x04], "missing length"),
+            (&[0x04, 0x80], "invalid length"),
+            (&[0x04, 0x89, 0, 0, 0, 0, 0, 0, 0, 0, 1], "invalid length"),
+            (&[0x04, 0x82, 0x01], "invalid length"),
+            (&[0x04, 0x03, 1, 2], "element is truncated"),
+            (&[0x04, 0x88, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], "element is truncated"),
+        ] {
+            assert!(matches!(read_tlv(der), Err(SepSplitError::BadImg4(r)) if r == reason), "{der:02x?}");
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let der = im4p_der(b"SEPOS", &[], None);
+        assert!(is_img4(&der));
+        let im4p = Im4p::parse(&der).unwrap();
+        assert_eq!((im4p.fourcc, im4p.description, im4p.payload), ("sepi", "AppleSEPOS-2044.0.0", &b"SEPOS"[..]));
+        assert!(!im4p.is_encrypted());
+        assert_eq!(im4p.compression, None);
+
+        //a payload over 255 bytes has 2 length bytes, and so does everything around it
+        let payload = vec![0x5a; 0x1234];
+        let der = im4p_der(&payload, &[(1, [1; 16], [2; 32]), (2, [3; 16], [4; 32])], Some((1, 0x0012_3456)));
+        let im4p = Im4p::parse(&der).unwrap();
+        assert_eq!(im4p.payload, payload);
+        assert!(im4p.is_encrypted());
+        assert_eq!(im4p.kbags.len(), 2);
+        assert_eq!((im4p.kbags[0].kind, im4p.kbags[0].iv, im4p.kbags[0].key), (1, &[1; 16][..], &[2; 32][..]));
+        assert_eq!((im4p.kbags[1].kind, im4p.kbags[1].iv, im4p.kbags[1].key), (2, &[3; 16][..], &[4; 32][..]));
+        let compression = im4p.compression.unwrap();
+        assert_eq!((compression.algorithm, compression.size), (1, 0x0012_3456));
+    }
+
+    #[test]
+    fn test_parse_img4() {
+        let img4 = seq(&[string("IMG4"), im4p_der(b"SEPOS", &[], Some((1, 5))), tlv(0xa0, &seq(&[string("IM4M")]))]);
+        assert!(is_img4(&img4));
+        let im4p = Im4p::parse(&img4).unwrap();
+        assert_eq!(im4p.payload, b"SEPOS");
+        assert_eq!(im4p.compression.map(|c| c.size), Some(5));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let der = im4p_der(b"SEPOS", &[(1, [1; 16], [2; 32])], Some((1, 5)));
+        //every truncation is an error, never a panic
+        for len in 0..der.len() {
+            assert!(Im4p::parse(&der[..len]).is_err(), "{len}");
+        }
+        assert!(!is_img4(b"\x30\x06\x16\x04IM4R"));
+        assert!(matches!(Im4p::parse(&seq(&[string("IM4R")])), Err(SepSplitError::BadImg4("not an IM4P"))));
+        assert!(matches!(Im4p::parse(&tlv(TAG_OCTET_STRING, &string("IM4P"))), Err(SepSplitError::BadImg4("unexpected tag"))));
+        let bad_utf8 = seq(&[tlv(TAG_IA5STRING, b"IM4P"), tlv(TAG_IA5STRING, b"\xff\xfe")]);
+        assert!(matches!(Im4p::parse(&bad_utf8), Err(SepSplitError::BadImg4("string is not valid UTF-8"))));
+        let no_payload = seq(&[string("IM4P"), string("sepi"), string("AppleSEPOS")]);
+        assert!(matches!(Im4p::parse(&no_payload), Err(SepSplitError::BadImg4("missing tag"))));
+        let big_integer = seq(&[string("IM4P"), string("sepi"), string(""), tlv(TAG_OCTET_STRING, b""),
+            seq(&[tlv(TAG_INTEGER, &[1; 10]), tlv(TAG_INTEGER, &[5])])]);
+        assert!(matches!(Im4p::parse(&big_integer), Err(SepSplitError::BadImg4("invalid integer"))));
+    }
+
+    #[test]
+    fn test_decrypt_key() {
+        let key = DecryptKey::from_hex_parts(IV, KEY).unwrap();
+        assert_eq!(key.iv, *b"\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0a\x0b\x0c\x0d\x0e\x0f");
+        assert_eq!(DecryptKey::from_hex(&format!("{IV}{KEY}")).unwrap(), key);
+        assert_eq!(DecryptKey::from_hex(&format!(" 0x{IV}{} \n", KEY.to_uppercase())).unwrap(), key);
+
+        for (iv, key) in [
+            (&IV[2..], KEY),
+            (IV, &KEY[..62]),
+            ("+00102030405060708090a0b0c0d0e0f", KEY),
+            ("g00102030405060708090a0b0c0d0e0f", KEY),
+            ("é0102030405060708090a0b0c0d0e0f", KEY),
+        ] {
+            assert!(matches!(DecryptKey::from_hex_parts(iv, key), Err(SepSplitError::BadKey)), "{iv}");
+        }
+        assert!(matches!(DecryptKey::from_hex(IV), Err(SepSplitError::BadKey)));
+    }
+
+    #[test]
+    fn test_decrypt() {
+        let key = DecryptKey::from_hex_parts(IV, KEY).unwrap();
+        assert_eq!(key.decrypt(&CIPHER), PLAIN);
+        //a trailing partial block is not encrypted
+        let mut payload = CIPHER.to_vec();
+        payload.extend_from_slice(b"SEP");
+        assert_eq!(key.decrypt(&payload), [&PLAIN[..], b"SEP"].concat());
+        assert!(key.decrypt(&[]).is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//report of what is in a firmware, from walking its structs without fixing any module
+
+use std::{
+    fmt,
+    io::BufWriter,
+};
+
+use prettytable::{format, row, Table};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    firmware::{CrcCheck, ModuleKind, SepFirmware, SepFormat, SepHeader, SepModule},
+    manifest::{modules, to_json},
+    split_image, utils::opt_str, Fix, SepSplitError, SplitOptions, SrcVer,
+};
+
+/// The number of bytes of the firmware image taken by each kind of module
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RegionSizes {
+    pub image: u64,      // The whole decompressed image
+    pub boot: u64,       // Everything before the kernel
+    pub kernel: u64,     // The SEP kernel
+    pub rootserver: u64, // SEPOS, with its rw segments
+    pub apps: u64,       // Every app, with their rw segments
+    pub shlibs: u64,     // Every shared library, with their rw segments
+}
+
+/// What is in a SEP firmware, see `firmware_info`
+#[derive(Debug, Clone, Serialize)]
+pub struct FirmwareInfo {
+    pub format: SepFormat,
+    pub subversion: u8,              // The legion subversion, 1 for 32-bit SEP
+    pub hdr_offset: u64,             // The offset of the SEP HDR struct (64-bit SEP) or of the monitor boot args (32-bit SEP)
+    pub hdr_uuid: Option<Uuid>,      // The UUID in the legion header, only for iOS 16 and later
+    pub kernel_uuid: Option<Uuid>,   // The UUID of the kernel, only for 64-bit SEP
+    pub sepos_uuid: Option<Uuid>,    // The UUID of SEPOS
+    pub sepos_srcver: Option<SrcVer>, // The source version of SEPOS, if the SEP app table has one
+    pub header: Option<SepHeader>,   // The SEP HDR struct, only for 64-bit SEP
+    #[serde(rename = "crc32_check")]
+    pub crc: Option<CrcCheck>,       // The CRC32 of the apps (of SEPOS for 32-bit SEP), if the firmware has one
+    pub sizes: RegionSizes,
+    pub n_apps: usize,               // The number of apps, without SEPOS
+    pub n_shlibs: usize,             // The number of shared libraries
+    #[serde(serialize_with = "modules")]
+    pub apps: Vec<SepModule>,        // SEPOS, the apps and the shared libraries
+}
+
+/// Walks the structs of a SEP firmware like `split_bytes`, but only reports what is in it.
+///
+/// The modules are located but not reconstructed, so this is cheaper than splitting.
+/// # Arguments
+/// * `input` - The bytes of the SEP firmware, either raw or in an IM4P
+/// * `opts` - The options to split with
+/// # Errors
+/// * The same errors as `split_bytes`
+pub fn firmware_info(input: &[u8], opts: &SplitOptions) -> Result<FirmwareInfo, SepSplitError> {
+    let fw = split_image(input, opts, BufWriter::new(Box::new(std::io::sink())), Fix::None)?;
+    Ok(fw.info())
+}
+
+impl SepFirmware<'_> {
+    /// The report of what is in the firmware, see `firmware_info`
+    #[must_use]
+    pub fn info(&self) -> FirmwareInfo {
+        let of_kind = |kind| self.modules.iter().filter(move |m| m.kind == kind);
+        let size = |kind| of_kind(kind).map(|m| m.size_text + m.size_data).sum();
+        let sepos = of_kind(ModuleKind::Rootserver).next();
+        FirmwareInfo {
+            format: self.format,
+            subversion: match self.format {
+                SepFormat::Bits32 { .. } => 1,
+                SepFormat::Bits64 { subversion, .. } => subversion,
+            },
+            hdr_offset: self.hdr_offset,
+            hdr_uuid: self.hdr_uuid,
+            kernel_uuid: of_kind(ModuleKind::Kernel).next().and_then(|m| m.uuid),
+            sepos_uuid: sepos.and_then(|m| m.uuid),
+            sepos_srcver: sepos.and_then(|m| m.srcver),
+            header: self.header,
+            crc: self.crc,
+            sizes: RegionSizes {
+                image: self.image.len() as u64,
+                boot: size(ModuleKind::Boot),
+                kernel: size(ModuleKind::Kernel),
+                rootserver: size(ModuleKind::Rootserver),
+                apps: size(ModuleKind::App),
+                shlibs: size(ModuleKind::SharedLib),
+            },
+            n_apps: of_kind(ModuleKind::App).count(),
+            n_shlibs: of_kind(ModuleKind::SharedLib).count(),
+            apps: self.modules.iter()
+                .filter(|m| matches!(m.kind, ModuleKind::Rootserver | ModuleKind::App | ModuleKind::SharedLib))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+impl FirmwareInfo {
+    /// The report as pretty printed JSON, with the same fields as the manifest where they overlap
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        to_json(self)
+    }
+}
+
+impl fmt::Display for FirmwareInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "format           {}", self.format)?;
+        writeln!(f, "legion           subversion {}, {} at {:#x}", self.subversion,
+            if matches!(self.format, SepFormat::Bits32 { .. }) { "boot args" } else { "SEP HDR" }, self.hdr_offset)?;
+        if let Some(uuid) = self.hdr_uuid {
+            writeln!(f, "HDR UUID         {uuid}")?;
+        }
+        writeln!(f, "kernel UUID      {}", opt_str(self.kernel_uuid))?;
+        writeln!(f, "SEPOS UUID       {}", opt_str(self.sepos_uuid))?;
+        writeln!(f, "SEPOS srcver     {}", opt_str(self.sepos_srcver))?;
+        let sizes = &self.sizes;
+        writeln!(f, "sizes            image {:#x}, boot {:#x}, kernel {:#x}, SEPOS {:#x}, apps {:#x}, shared libs {:#x}",
+            sizes.image, sizes.boot, sizes.kernel, sizes.rootserver, sizes.apps, sizes.shlibs)?;
+        writeln!(f, "modules          {} apps, {} shared libs", self.n_apps, self.n_shlibs)?;
+        if let Some(crc) = &self.crc {
+            writeln!(f, "{crc}")?;
+        }
+        for app in self.apps.iter().filter(|app| app.uuid_mismatch()) {
+            writeln!(f, "mismatch         {} has LC_UUID {}, the table has {}", app.name, opt_str(app.lc_uuid), opt_str(app.uuid))?;
+        }
+        for app in self.apps.iter().filter(|app| app.srcver_mismatch()) {
+            writeln!(f, "mismatch         {} has LC_SOURCE_VERSION {}, the table has {}", app.name, opt_str(app.lc_srcver), opt_str(app.srcver))?;
+        }
+
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_BOX_CHARS);
+        table.set_titles(row!["#", "Kind", "Name", "Source version", "UUID", "Text", "Data"]);
+        for app in &self.apps {
+            table.add_row(row![
+                r->opt_str(app.index),
+                app.kind.as_str(),
+                app.name,
+                opt_str(app.srcver),
+                opt_str(app.uuid),
+                r->format!("{:#x}", app.size_text),
+                r->format!("{:#x}", app.size_data),
+            ]);
+        }
+        write!(f, "{table}")
+    }
+}
@@ -26,16 +26,50 @@
 use memchr::memmem;
 
 use std::{
+    borrow::Cow,
     str, 
     path::{Path, PathBuf},
-    process, 
-    io::{Write, BufWriter}, 
-    ffi::c_void,
+    io::{Read, Write, BufWriter}, 
+    ops::Range,
     fs
 };
 
 #[macro_use]
 mod utils;
+mod macho;
+mod error;
+mod firmware;
+mod img4;
+mod lzfse;
+#[cfg(feature = "rust-lzvn")]
+mod lzvn;
+mod manifest;
+mod repack;
+mod batch;
+mod info;
+mod naming;
+mod diff;
+mod hash;
+mod rebase;
+mod regions;
+mod sink;
+
+pub use error::SepSplitError;
+pub use img4::{Compression, DecryptKey, Im4p, Keybag};
+pub use firmware::{CrcCheck, SepFirmware, SepFormat, SepHeader, SepModule, ModuleKind, ModuleSelector};
+use firmware::{ModuleBytes, Patch};
+pub use utils::{filecreate, SrcVer};
+pub use macho::{BuildVersion, Command, DyldInfo, DySymTab, LoadCommand, MachHeader, MachO, Section, Segment, SymTab};
+pub use repack::{repack, repack_file};
+pub use batch::{split_dir, BatchEntry, BatchSummary};
+pub use info::{firmware_info, FirmwareInfo, RegionSizes};
+pub use diff::{firmware_diff, FirmwareDiff, HeaderChange, ModuleChange};
+pub use hash::{Digests, FirmwareHashes, ModuleHashes};
+pub use rebase::{Rebase, RebasedSegment};
+pub use regions::{firmware_regions, Overlap, Region, RegionKind, RegionMap};
+pub use naming::NameTemplate;
+pub use sink::ArchiveFormat;
+use sink::{DirSink, Sink, SubdirSink, TarSink};
 
 #[allow(clippy::wildcard_imports)]
 use utils::*;
@@ -43,219 +77,238 @@ use utils::*;
 use binrw::{
     io::Cursor, 
     BinRead,
-    BinReaderExt,
-    BinWriterExt
+    BinReaderExt
 };
 
+use serde_json::json;
 use uuid::Uuid;
 
+#[cfg(not(any(feature = "rust-lzvn", feature = "c-lzvn")))]
+compile_error!("either the rust-lzvn or the c-lzvn feature is needed to decompress LZVN");
+
+#[cfg(feature = "c-lzvn")]
 #[allow(warnings)]
 mod bindings {
     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 }
 
 //calculate the end of the Mach-O file, by seeing the last possible offset of all segments
+//a bad Mach-O is still sized by its segments, it is only left unfixed by restore_file
 fn calc_size(bytes: &[u8]) -> usize { 
-    if bytes.len() < 1024 { return 0 }
-    let hdr = cast_struct!(MachHeader, bytes);
-    let mut q = MACHHEADER_SIZE;
-    let mut end: u64;
-    let mut tsize = 0;
-
-    if !hdr.is_macho() { return 0 }
-    else if hdr.is64() { q += 4 }
-
-    //check segments in mach-o file
-    for _ in 0..hdr.ncmds {
-        let cmd = cast_struct!(LoadCommand, &bytes[q..]);
-        match cmd.cmd {
-            Cmd::Segment => {
-                let seg = cast_struct!(Segment, &bytes[q+LOADCOMMAND_SIZE..]);
-                end = u64::from(seg.fileoff + seg.filesize);
-                if tsize < end { tsize = end; }
-            },
-            Cmd::Segment64 => {
-                let seg = cast_struct!(Segment64, &bytes[q+LOADCOMMAND_SIZE..]);
-                end = seg.fileoff + seg.filesize;
-                if tsize < end { tsize = end; }
-            },
-            _ => ()
+    if bytes.len() < 1024 || !MachO::is_macho(bytes) { return 0 }
+    MachO::parse(bytes).map_or_else(|_| MachO::scan_file_end(bytes), |macho| macho.file_end()) as usize
+}
+
+//main functions
+
+//finds where the DATA segment is supposed to be, dataoff if it is not where the load commands say
+fn fix_data_segment(macho: &MachO, dataoff: Option<usize>) -> Option<usize> {
+    macho.segment(macho::SEG_DATA).map(|seg| dataoff.unwrap_or(seg.fileoff as usize))
+}
+
+//where __LINKEDIT was in the file and how far fix_linkedit moved it, to move the tables in it along
+struct LinkeditMove {
+    fileoff: u64,
+    filesize: u64,
+    delta: u64,
+    size: u64, // The size of the module, the moved tables have to be in it
+}
+
+impl LinkeditMove {
+    //the moved offset of a table of len bytes, None if it was not in __LINKEDIT or is not in the module once moved
+    fn relocate(&self, off: u32, len: u64) -> Option<u32> {
+        if off == 0 { return Some(0) } //no table
+        let (off, end) = (u64::from(off), u64::from(off).checked_add(len)?);
+        if off < self.fileoff || end > self.fileoff.saturating_add(self.filesize) || end.saturating_add(self.delta) > self.size {
+            return None
         }
-        q += cmd.cmdsize as usize;
+        u32::try_from(off + self.delta).ok()
+    }
+
+    fn symtab(&self, symtab: &macho::SymTab, is64: bool) -> Option<macho::SymTab> {
+        let nlist_size = if is64 { 16 } else { 12 };
+        Some(macho::SymTab {
+            symoff: self.relocate(symtab.symoff, u64::from(symtab.nsyms) * nlist_size)?,
+            stroff: self.relocate(symtab.stroff, u64::from(symtab.strsize))?,
+            ..*symtab
+        })
     }
 
-    tsize as usize
+    fn dysymtab(&self, dysymtab: &macho::DySymTab, is64: bool) -> Option<macho::DySymTab> {
+        let module_size = if is64 { 56 } else { 52 };
+        Some(macho::DySymTab {
+            tocoff: self.relocate(dysymtab.tocoff, u64::from(dysymtab.ntoc) * 8)?,
+            modtaboff: self.relocate(dysymtab.modtaboff, u64::from(dysymtab.nmodtab) * module_size)?,
+            extrefsymoff: self.relocate(dysymtab.extrefsymoff, u64::from(dysymtab.nextrefsyms) * 4)?,
+            indirectsymoff: self.relocate(dysymtab.indirectsymoff, u64::from(dysymtab.nindirectsyms) * 4)?,
+            extreloff: self.relocate(dysymtab.extreloff, u64::from(dysymtab.nextrel) * 8)?,
+            locreloff: self.relocate(dysymtab.locreloff, u64::from(dysymtab.nlocrel) * 8)?,
+            ..*dysymtab
+        })
+    }
 }
 
-//main functions
+//fixes LINKEDIT offsets, and the symbol tables in it if they are in the module, otherwise they are zeroed
+fn fix_linkedit(macho: &mut MachO, size: usize) -> Result<(), SepSplitError> {
+    let min = macho.base().unwrap_or(u64::MAX);
 
-//places the DATA segment specified into where the DATA segment is supposed to be
-fn fix_data_segment(image: &mut [u8], data: &[u8], dataoff: Option<usize>) -> Result<(), String> {
-    let mut p = MACHHEADER_SIZE;
-    
-    let machheader = cast_struct!(MachHeader, image);
-    if !machheader.is_macho() { return Err(String::from("Not macho")) }
-    else if machheader.is64() { p += 4; }
-
-    for _ in 0..machheader.ncmds {
-        let cur_lcmd = cast_struct!(LoadCommand, &image[p..]);
-        match cur_lcmd.cmd {
-            Cmd::Segment => {
-                let seg = cast_struct!(Segment, &image[p+LOADCOMMAND_SIZE..]);
-                if seg.segname == SEG_DATA {
-                    let segoff = dataoff.unwrap_or(seg.fileoff as usize);
-                    image[range_size(segoff, data.len())].copy_from_slice(data);
-                }
-            }
-            Cmd::Segment64 => {
-                let seg = cast_struct!(Segment64, &image[p+LOADCOMMAND_SIZE..]);
-                if seg.segname == SEG_DATA {
-                    let segoff = dataoff.unwrap_or(seg.fileoff as usize);
-                    image[range_size(segoff, data.len())].copy_from_slice(data);
-                }
-            },
-            _ => ()
+    let mut linkedit = None;
+    for seg in macho.segments_mut().filter(|seg| seg.name() == macho::SEG_LINKEDIT) {
+        let delta = seg.vmaddr.checked_sub(min)
+            .and_then(|d| d.checked_sub(seg.fileoff))
+            .ok_or(SepSplitError::BadMachO("__LINKEDIT is before the first segment"))?;
+        linkedit.get_or_insert(LinkeditMove { fileoff: seg.fileoff, filesize: seg.filesize, delta, size: size as u64 });
+        seg.fileoff += delta;
+    }
+
+    //most SEP binaries have no symbols at all, and the tables of some are not in what is in the firmware
+    let is64 = macho.is64();
+    let mut has_symtab = true;
+    for lc in &mut macho.commands {
+        if let Command::SymTab(symtab) = &mut lc.command {
+            let moved = linkedit.as_ref().and_then(|linkedit| linkedit.symtab(symtab, is64));
+            has_symtab &= moved.is_some();
+            *symtab = moved.unwrap_or_default();
         }
-        p += cur_lcmd.cmdsize as usize;
-    };
+    }
+    //the dynamic symbol table indexes the symbol table, so it goes with it
+    for lc in &mut macho.commands {
+        if let Command::DySymTab(dysymtab) = &mut lc.command {
+            *dysymtab = linkedit.as_ref()
+                .filter(|_| has_symtab)
+                .and_then(|linkedit| linkedit.dysymtab(dysymtab, is64))
+                .unwrap_or_default();
+        }
+    }
 
     Ok(())
 }
 
-//fixes LINKEDIT offsets
-fn fix_linkedit(image: &mut [u8]) -> Result<(), String> {
-    let mut min: u64 = u64::MAX;
-    let mut p = MACHHEADER_SIZE;
-    
-    let machheader = cast_struct!(MachHeader, &image[..MACHHEADER_SIZE]);
-    if !machheader.is_macho() { return Err(String::from("Not macho")) }
-    else if machheader.is64() { p += 4; }
-
-    for _ in 0..machheader.ncmds {
-        let cur_lcmd = cast_struct!(LoadCommand, &image[p..]);
-        match cur_lcmd.cmd {
-            Cmd::Segment => {
-                let seg = cast_struct!(Segment, &image[p+LOADCOMMAND_SIZE..]);
-                if seg.segname != SEG_PAGEZERO && min > u64::from(seg.vmaddr) { 
-                    min = u64::from(seg.vmaddr); 
-                }
-            },
-            Cmd::Segment64 => {
-                let seg = cast_struct!(Segment64, &image[p+LOADCOMMAND_SIZE..]);
-                if seg.segname != SEG_PAGEZERO && min > seg.vmaddr { 
-                    min = seg.vmaddr; 
-                }
-            },
-            _ => ()
-        }
-        p += cur_lcmd.cmdsize as usize;
-    };
+//a module that is taken as is from the firmware
+fn raw_module(kernel: &[u8], start: usize, size: usize) -> Result<ModuleBytes, SepSplitError> {
+    slice_size(kernel, start, size)?;
+    Ok(ModuleBytes::new(range_size(start, size)))
+}
 
-    let mut delta: u64;
-    p = MACHHEADER_SIZE + if machheader.is64() {4} else {0};
-
-    for _ in 0..machheader.ncmds {
-        let cur_lcmd = cast_struct!(LoadCommand, &image[p..]);
-        match cur_lcmd.cmd {
-            Cmd::Segment => {
-                let mut seg = cast_struct!(Segment, &image[p+LOADCOMMAND_SIZE..]);
-                if seg.segname == SEG_LINKEDIT  {
-                    delta = u64::from(seg.vmaddr) - min - u64::from(seg.fileoff);
-                    seg.fileoff += delta as u32;
-                }
-                let mut buf = Vec::new();
-                write_struct!(seg, buf);
-                image[range_size(p+LOADCOMMAND_SIZE, buf.len())].copy_from_slice(&buf);
-            },
-            Cmd::Segment64 => {
-                let mut seg = cast_struct!(Segment64, &image[p+LOADCOMMAND_SIZE..]);
-                if seg.segname == SEG_LINKEDIT  { 
-                    delta = seg.vmaddr - min - seg.fileoff;
-                    seg.fileoff += delta;
-                }
-                let mut buf = Vec::new();
-                write_struct!(seg, buf);
-                image[range_size(p+LOADCOMMAND_SIZE, buf.len())].copy_from_slice(&buf);
-            },
-            Cmd::SymTab => {
-                /* what xerub's code did (translated into Rust):
-                    let mut seg = cast_struct!(SymTab, &image[p+LOADCOMMAND_SIZE..]);
-                    if seg.stroff != 0 { seg.stroff += delta as u32};
-                    if seg.symoff != 0 { seg.symoff += delta as u32};
-                    
-                this does not work because there aren't even any symbols in the binaries. */
-
-                let seg = SymTab::default();
-                let mut buf = Vec::new();
-                write_struct!(seg, buf);
-                image[range_size(p+LOADCOMMAND_SIZE, buf.len())].copy_from_slice(&buf);
-            },
-            Cmd::DySymTab => {
-                // same reasons as above
-                let seg = DySymTab::default();
-                let mut buf = Vec::new();
-                write_struct!(seg, buf);
-                image[range_size(p+LOADCOMMAND_SIZE, buf.len())].copy_from_slice(&buf);
+//restores the file's LINKEDIT and optionally DATA segments, only the load commands are copied
+//modules that are not Mach-Os are kept as is, and so is every module if not fix (for info, which only needs the ranges)
+fn restore_file(kernel: &[u8], start: usize, size: usize, data: Option<Range<usize>>, dataoff: Option<usize>, fix: bool) -> Result<ModuleBytes, SepSplitError> {
+    let mut module = raw_module(kernel, start, size)?;
+    if !fix {
+        return Ok(module)
+    }
+    let orig = slice_size(kernel, start, size)?;
+    //a module that can not be fixed is still split, and the firmware warns about it
+    let mut macho = match MachO::parse(orig) {
+        Ok(macho) => macho,
+        Err(SepSplitError::BadMachO(err)) => {
+            module.unfixed = Some(err);
+            return Ok(module)
+        },
+        Err(err) => return Err(err)
+    };
+    match fix_linkedit(&mut macho, size) {
+        Err(SepSplitError::BadMachO(err)) => module.unfixed = Some(err),
+        res => res?
+    }
+    let data = match data {
+        Some(data) => {
+            slice_size(kernel, data.start, data.len())?;
+            match fix_data_segment(&macho, dataoff) {
+                Some(segoff) if segoff.saturating_add(data.len()) > size => {
+                    return Err(SepSplitError::Truncated { offset: segoff, needed: data.len() })
+                },
+                segoff => segoff.map(|segoff| (segoff, data))
             }
-            _ => ()
-        }
-        p += cur_lcmd.cmdsize as usize;
+        },
+        None => None
     };
+    let lcmds = macho.to_bytes()?;
+    if lcmds != orig[..lcmds.len()] {
+        module.patch(kernel, 0, Patch::Bytes(lcmds));
+    }
+    if let Some((segoff, data)) = data {
+        module.patch(kernel, segoff, Patch::Image(data));
+    }
+    Ok(module)
+}
 
-    Ok(())
+//which modules are fixed while splitting: none for info, otherwise the ones selected in the options (all if none are)
+#[derive(Clone, Copy)]
+enum Fix<'a> {
+    None,
+    Selected(&'a [ModuleSelector]),
 }
 
-//restores the file's LINKEDIT and optionally DATA segments, and saves using the name
-fn restore_file(index: usize, buf: &[u8], path: &Path, tail: &str, data_buf: Option<&[u8]>, dataoff: Option<usize>) {
-    let file: PathBuf = path.join(format!("sepdump{index:02}_{tail}"));
-    
-    let mut tmp = buf.to_owned();
-    if let Err(err) = fix_linkedit(&mut tmp) {
-        eprintln!("Error in fix_linkedit function: {err}");
-    }
-    if let Some(data_seg) = data_buf { 
-        if let Err(err) = fix_data_segment(&mut tmp, data_seg, dataoff) {
-            eprintln!("Error in fix_data_segment function: {err}");
-        };
+impl Fix<'_> {
+    fn module(self, index: usize, name: &str, uuid: Option<Uuid>) -> bool {
+        match self {
+            Self::None => false,
+            Self::Selected(selectors) => selectors.is_empty() || selectors.iter().any(|s| s.selects(Some(index), name, uuid)),
+        }
     }
-    filewrite(&file, &tmp);
+}
+
+//grows the region to include start..end, for the region covered by a CRC32
+fn grow_region(region: &mut Option<(u64, u64)>, start: u64, end: u64) {
+    *region = Some(region.map_or((start, end), |(s, e)| (s.min(start), e.max(end))));
+}
+
+//checks the CRC32 stored at offset in the firmware against the region, if the firmware has one
+fn check_crc(kernel: &[u8], expected: u32, offset: usize, region: Option<(u64, u64)>, outbuf: &mut BufWriter<Box<dyn Write>>) -> Result<Option<CrcCheck>, SepSplitError> {
+    let Some((start, end)) = region.filter(|_| expected != 0) else { return Ok(None) };
+    let actual = crc32(slice_size(kernel, start as usize, end.saturating_sub(start) as usize)?);
+    let crc = CrcCheck { expected, actual, start, end, offset: offset as u64 };
+    writeln!(outbuf, "{crc}")?;
+    Ok(Some(crc))
 }
 
 //splits the SEP apps from the 64-bit SEP Firmware by reading the structs
 #[allow(clippy::too_many_lines)] // need to refactor this
-fn split64(mut hdr_offset: usize, kernel: &[u8], outdir: &Path, mut outbuf: BufWriter<Box<dyn Write>>, ver: u8) -> Result<(), std::io::Error> {
+fn split64(mut hdr_offset: usize, kernel: &[u8], mut outbuf: BufWriter<Box<dyn Write>>, ver: u8, fix: Fix) -> Result<SepFirmware<'static>, SepSplitError> {
     writeln!(&mut outbuf, "detected 64 bit SEP")?;
     let is_old = hdr_offset == 0xFFFF;
     if is_old {
         hdr_offset = 0x10F8;
     }
+    let mut fw = SepFirmware::new(SepFormat::Bits64 { subversion: ver, old: is_old });
+    fw.hdr_offset = hdr_offset as u64;
     if ver == 2 {
         // much like old 32-bit SEP
 
-        let hdr = cast_struct!(SEPDataHDR64Ver2, &kernel[hdr_offset..]);
+        let hdr = cast_struct!(SEPDataHDR64Ver2, slice_from(kernel, hdr_offset)?)?;
+        fw.header = Some(SepHeader::from_hdr64_ver2(&hdr));
         //index 0: boot
-        let mut bootout = outdir.join("sepdump00_boot");
-        filewrite(&bootout, &kernel[..0x1000]); 
+        fw.modules.push(SepModule::raw(ModuleKind::Boot, Some(0), "boot", 0, raw_module(kernel, 0, 0x1000)?));
         writeln!(&mut outbuf, "boot         size 0x1000")?;
 
         //index 1: kernel
         //from D20 iOS 11.0 SEP Firmware
         let st = 0x4000;
-        let mut sz = calc_size(&kernel[st..]); //most SEP fws
-        restore_file(1, &kernel[range_size(st, sz)], outdir, "kernel", None, None);
+        let mut sz = calc_size(slice_from(kernel, st)?); //most SEP fws
+        fw.modules.push(SepModule {
+            uuid: Some(Uuid::from_bytes_le(hdr.kernel_uuid)),
+            ..SepModule::raw(ModuleKind::Kernel, Some(1), "kernel", st as u64, restore_file(kernel, st, sz, None, None, fix.module(1, "kernel", Some(Uuid::from_bytes_le(hdr.kernel_uuid))))?)
+        });
 
         writeln!(&mut outbuf, "kernel       size {sz:#x}")?;
 
         //dump struct
-        bootout = outdir.join("sepdump-struct.extra");
-        filewrite(&bootout, &kernel[range_size(0x1000, 0x400)]);
+        fw.modules.push(SepModule::raw(ModuleKind::Struct, None, "struct", 0x1000, raw_module(kernel, 0x1000, 0x400)?));
         writeln!(&mut outbuf, "struct       size 0x400")?;
 
         //SEPOS aka "rootserver"
-        let mut tail = strslice(&hdr.init_name); //get the name of the first image (SEPOS) without spaces;
-        let uuid = Uuid::from_bytes_le(hdr.init_uuid).hyphenated().to_string();
+        let mut tail = strslice(&hdr.init_name)?; //get the name of the first image (SEPOS) without spaces;
+        let uuid = Uuid::from_bytes_le(hdr.init_uuid);
         sz = hdr.init_vsize as usize;
-        restore_file(2, &kernel[range_size(hdr.init_base_paddr as usize, sz)], outdir, tail, None, None);
+        fw.modules.push(SepModule {
+            uuid: Some(uuid),
+            virt: hdr.init_base_vaddr,
+            entry: hdr.init_ventry,
+            stack_size: hdr.stack_size,
+            stack_paddr: hdr.stack_base_paddr,
+            ..SepModule::raw(ModuleKind::Rootserver, Some(2), tail, hdr.init_base_paddr, restore_file(kernel, hdr.init_base_paddr as usize, sz, None, None, fix.module(2, tail, Some(uuid)))?)
+        });
         writeln!(&mut outbuf, "{tail:-12} phys_text {:#08x}, virt {:#06x}, size_text {:#08x}, entry {:#x},\n             UUID {uuid}",
                 hdr.init_base_paddr, hdr.init_base_vaddr, hdr.init_vsize, hdr.init_ventry)?;
 
@@ -266,19 +319,31 @@ fn split64(mut hdr_offset: usize, kernel: &[u8], outdir: &Path, mut outbuf: BufW
         let mut off = 0x1198; // maybe specific to D20 iOS 11.0?
         let sepappsize = 0x58; // maybe specific to D20 iOS 11.0?
         let mut app;
+        let mut region = None;
         while i < (n_apps + shlib) as usize {
-            app = cast_struct!(SEPApp64Ver2, &kernel[off..]);
-            tail = strslice(&app.app_name);
-            restore_file(i, &kernel[range_size(app.phys_text as usize, app.size_text as usize)], outdir, tail, None, None);
-            let uuid = Uuid::from_bytes_le(app.app_uuid).hyphenated().to_string();
+            app = cast_struct!(SEPApp64Ver2, slice_from(kernel, off)?)?;
+            tail = strslice(&app.app_name)?;
+            let uuid = Uuid::from_bytes_le(app.app_uuid);
+            fw.modules.push(SepModule {
+                uuid: Some(uuid),
+                virt: app.virt,
+                entry: app.ventry,
+                stack_size: app.stack_size,
+                size_fields: Some((off + 16, None)),
+                compact_ver: Some((app.compact_ver_start, app.compact_ver_end)),
+                ..SepModule::raw(ModuleKind::App, Some(i), tail, app.phys_text, restore_file(kernel, app.phys_text as usize, app.size_text as usize, None, None, fix.module(i, tail, Some(uuid)))?)
+            });
             writeln!(&mut outbuf, "{tail:-12} phys_text {:#08x}, virt {:#06x}, size_text {:#08x}, entry {:#x},\n             UUID {uuid}",
                 app.phys_text, app.virt, app.size_text,app.ventry)?;
+            grow_region(&mut region, app.phys_text, app.phys_text.saturating_add(app.size_text));
             off += sepappsize;
             i += 1;
         }
-        return Ok(());
+        fw.crc = check_crc(kernel, hdr.crc32.val, hdr_offset + hdr.crc32.pos as usize, region, &mut outbuf)?;
+        outbuf.flush()?;
+        return Ok(fw);
     }
-    let hdr = cast_struct_args!(SEPDataHDR64, &kernel[hdr_offset..], (ver, is_old));
+    let hdr = cast_struct_args!(SEPDataHDR64, slice_from(kernel, hdr_offset)?, (ver, is_old))?;
     let mut off = hdr_offset + if is_old { 0xC0 } else { SEPHDR_SIZE 
                     + if ver == 4 { 56 } else if hdr.ar_min_size == 0 { 0 } else { 24 } //see top of utils.rs file
                     - if hdr.stack_size == 0 && ver != 4 { 24 } else { 0 }
@@ -287,31 +352,46 @@ fn split64(mut hdr_offset: usize, kernel: &[u8], outdir: &Path, mut outbuf: BufW
     let mut n_apps = hdr.n_apps;
     let n_shlibs = if hdr.n_apps == 0 { 
         off += 0x100;
-        n_apps = u32::from_le_bytes(kernel[range_size(hdr_offset+0x210, 4)].try_into().unwrap());
-        u32::from_le_bytes(kernel[range_size(hdr_offset+0x214, 4)].try_into().unwrap())
+        n_apps = u32::from_le_bytes(slice_size(kernel, hdr_offset+0x210, 4)?.try_into().unwrap()); //infallable, slice of 4 bytes
+        u32::from_le_bytes(slice_size(kernel, hdr_offset+0x214, 4)?.try_into().unwrap())
     } else { hdr.n_shlibs };
+    fw.header = Some(SepHeader::from_hdr64(&hdr, n_apps, n_shlibs));
 
     //first part of image, boot
-    let bootout = outdir.join("sepdump00_boot");
-    filewrite(&bootout, &kernel[..hdr.kernel_base_paddr as usize]);
+    fw.modules.push(SepModule::raw(ModuleKind::Boot, Some(0), "boot", 0, raw_module(kernel, 0, hdr.kernel_base_paddr as usize)?));
     writeln!(&mut outbuf, "boot             size {sz:#x}", sz=hdr.kernel_base_paddr as usize)?;
 
     //second part, kernel
-    let mut sz = calc_size(&kernel[hdr.kernel_base_paddr as usize..]);
-    let mut uuid = Uuid::from_bytes_le(hdr.kernel_uuid).hyphenated().to_string();
-    if sz == 0 {
-        filewrite(&outdir.join("sepdump01_kernel"), &kernel[hdr.kernel_base_paddr as usize..hdr.kernel_max_paddr as usize]);
-        sz = (hdr.kernel_max_paddr - hdr.kernel_base_paddr) as usize;
+    let mut sz = calc_size(slice_from(kernel, hdr.kernel_base_paddr as usize)?);
+    let mut uuid = Uuid::from_bytes_le(hdr.kernel_uuid);
+    let krnl_macho = if sz == 0 {
+        sz = hdr.kernel_max_paddr.saturating_sub(hdr.kernel_base_paddr) as usize;
+        raw_module(kernel, hdr.kernel_base_paddr as usize, sz)?
     } else {
-        restore_file(1, &kernel[range_size(hdr.kernel_base_paddr as usize, sz)], outdir, "kernel", None, None);
-    }
+        restore_file(kernel, hdr.kernel_base_paddr as usize, sz, None, None, fix.module(1, "kernel", Some(uuid)))?
+    };
+    fw.modules.push(SepModule {
+        uuid: Some(uuid),
+        ..SepModule::raw(ModuleKind::Kernel, Some(1), "kernel", hdr.kernel_base_paddr, krnl_macho)
+    });
     writeln!(&mut outbuf, "kernel           size {sz:#x},  UUID {uuid}")?;
 
     //SEPOS aka "rootserver"
-    let mut tail = strslice(&hdr.init_name); //get the name of the first image (SEPOS) without spaces;
-    uuid = Uuid::from_bytes_le(hdr.init_uuid).hyphenated().to_string();
-    sz = calc_size(&kernel[hdr.init_base_paddr as usize..]);
-    restore_file(2, &kernel[range_size(hdr.init_base_paddr as usize, sz)], outdir, tail, None, None);
+    let mut tail = strslice(&hdr.init_name)?; //get the name of the first image (SEPOS) without spaces;
+    uuid = Uuid::from_bytes_le(hdr.init_uuid);
+    sz = calc_size(slice_from(kernel, hdr.init_base_paddr as usize)?);
+    fw.modules.push(SepModule {
+        uuid: Some(uuid),
+        virt: hdr.init_base_vaddr,
+        entry: hdr.init_ventry,
+        srcver: (!is_old).then_some(hdr.srcver),
+        stack_size: hdr.stack_size,
+        stack_paddr: hdr.stack_base_paddr,
+        mem_size: hdr.mem_size,
+        heap_mem_size: hdr.heap_mem_size,
+        compact_ver: (ver == 4).then_some((hdr.compact_ver_start, hdr.compact_ver_end)),
+        ..SepModule::raw(ModuleKind::Rootserver, Some(2), tail, hdr.init_base_paddr, restore_file(kernel, hdr.init_base_paddr as usize, sz, None, None, fix.module(2, tail, Some(uuid)))?)
+    });
     writeln!(&mut outbuf, "{tail:<16} size {sz:#x}, UUID {uuid}")?;
 
     //the rest of the apps
@@ -325,73 +405,76 @@ fn split64(mut hdr_offset: usize, kernel: &[u8], outdir: &Path, mut outbuf: BufW
                        }; //similar to reasons as top of utils.rs
     let mut app;
     let mut i = 0;
-    while i < n_apps as usize {
-        app = cast_struct_args!(SEPApp64, &kernel[off..], (ver, is_old));
-        tail = strslice(&app.app_name);
-        let data_buf = &kernel[range_size(app.phys_data as usize, app.size_data as usize)].to_owned();
-        restore_file(i + 3, &kernel[range_size(app.phys_text as usize, (app.size_text + app.size_data) as usize)], outdir, tail, Some(data_buf), None);
-        let uuid = Uuid::from_bytes_le(app.app_uuid).hyphenated().to_string();
-        writeln!(&mut outbuf, "{tail:<16} phys_text {:>#8x}, virt {:>#7x}, size_text {:>#8x}, phys_data {:#x}, size_data {:>#7x}, entry {:#x},\n                 UUID {uuid}",
-            app.phys_text, app.virt, app.size_text, app.phys_data, app.size_data, app.ventry)?;
-        off += sepappsize;
-        i += 1;
-    }
-    let max = (n_apps + n_shlibs) as usize;
+    let mut region = None;
+    let max = n_apps.saturating_add(n_shlibs) as usize;
     while i < max {
-        app = cast_struct_args!(SEPApp64, &kernel[off..], (ver, is_old));
-        tail = strslice(&app.app_name);
-        let data_buf = &kernel[range_size(app.phys_data as usize, app.size_data as usize)].to_owned();
-        restore_file(i + 3, &kernel[range_size(app.phys_text as usize, (app.size_text + app.size_data) as usize)], outdir, tail, Some(data_buf), Some(app.size_text as usize));
-        let uuid = Uuid::from_bytes_le(app.app_uuid).hyphenated().to_string();
+        //shared libraries have their data segment right after the text segment
+        let is_shlib = i >= n_apps as usize;
+        app = cast_struct_args!(SEPApp64, slice_from(kernel, off)?, (ver, is_old))?;
+        tail = strslice(&app.app_name)?;
+        let data = range_size(app.phys_data as usize, app.size_data as usize);
+        let macho = restore_file(kernel, app.phys_text as usize, app.size_text.saturating_add(app.size_data) as usize, Some(data), is_shlib.then_some(app.size_text as usize), fix.module(i + 3, tail, Some(Uuid::from_bytes_le(app.app_uuid))))?;
+        fw.modules.push(SepModule {
+            size_fields: Some((off + 8, Some(off + 24))),
+            ..SepModule::from_app64(if is_shlib { ModuleKind::SharedLib } else { ModuleKind::App }, i + 3, &app, is_old, macho)?
+        });
+        let uuid = Uuid::from_bytes_le(app.app_uuid);
         writeln!(&mut outbuf, "{tail:<16} phys_text {:>#8x}, virt {:>#7x}, size_text {:>#8x}, phys_data {:#x}, size_data {:>#7x}, entry {:#x},\n                 UUID {uuid}",
             app.phys_text, app.virt, app.size_text, app.phys_data, app.size_data, app.ventry)?;
+        grow_region(&mut region, app.phys_text, app.phys_text.saturating_add(app.size_text));
+        if app.size_data != 0 {
+            grow_region(&mut region, app.phys_data, app.phys_data.saturating_add(app.size_data));
+        }
         off += sepappsize;
         i += 1;
     }
-    outbuf.flush()
+    fw.crc = check_crc(kernel, hdr.crc32.val, hdr_offset + hdr.crc32.pos as usize, region, &mut outbuf)?;
+    outbuf.flush()?;
+    Ok(fw)
 }
 
 //splits the SEP apps from the 32-bit SEP Firmware by reading the structs
-fn split32(kernel: &[u8], outdir: &Path, mut sep_info: SEPinfo, mut outbuf: BufWriter<Box<dyn Write>>) -> Result<(), std::io::Error> {
+#[allow(clippy::too_many_lines)] // need to refactor this
+fn split32(kernel: &[u8], mut sep_info: SEPinfo, mut outbuf: BufWriter<Box<dyn Write>>, fix: Fix) -> Result<SepFirmware<'static>, SepSplitError> {
     writeln!(&mut outbuf, "detected 32 bit SEP")?;
 
     //index 0: boot
-    let mut bootout = outdir.join("sepdump00_boot");
-    filewrite(&bootout, &kernel[..0x1000]); 
+    let mut fw = SepFirmware::new(SepFormat::Bits32 { old: false });
+    fw.modules.push(SepModule::raw(ModuleKind::Boot, Some(0), "boot", 0, raw_module(kernel, 0, 0x1000)?));
     writeln!(&mut outbuf, "boot         size 0x1000")?;
 
     //index 1: kernel
     let mut st = 0x1000;
-    let mut sz = calc_size(&kernel[st..]); //most SEP fws
+    let mut sz = calc_size(slice_from(kernel, st)?); //most SEP fws
     
-    if sz == 0 {
-        if kernel[range_size(st, 4)] == [0; 4] {
+    let krnl_macho = if sz == 0 {
+        if slice_size(kernel, st, 4)? == [0; 4] {
             //J97 SEP Firmware
             st = 0x4000;
-            sz = calc_size(&kernel[st..]); 
-            restore_file(1, &kernel[range_size(st, sz)], outdir, "kernel", None, None);
+            sz = calc_size(slice_from(kernel, st)?); 
+            restore_file(kernel, st, sz, None, None, fix.module(1, "kernel", None))?
         } else {
             //N71 SEP or newer SEP Firmware
-            bootout = outdir.join("sepdump01_kernel");
-            filewrite(&bootout, &kernel[range_size(st, 0xe000)]);
             sz = 0xe000;
+            raw_module(kernel, st, sz)?
         }
     } else {
-        restore_file(1, &kernel[range_size(st, sz)], outdir, "kernel", None, None);
-    }
+        restore_file(kernel, st, sz, None, None, fix.module(1, "kernel", None))?
+    };
+    fw.modules.push(SepModule::raw(ModuleKind::Kernel, Some(1), "kernel", st as u64, krnl_macho));
 
     writeln!(&mut outbuf, "kernel       size {sz:#x}")?;
 
     //check for newer SEP
-    let tmp = cast_struct!(SEPAppOld, &kernel[sep_info.sep_app_pos..]);
+    let tmp = cast_struct!(SEPAppOld, slice_from(kernel, sep_info.sep_app_pos)?)?;
     if tmp.size == 0 {
         //64 bit SEP struct in 32 bit SEP
 
         //number of apps must be valid in this case
-        let n_apps = sep_info.sepapps.unwrap();
+        let n_apps = sep_info.sepapps.ok_or(SepSplitError::BadAppCount)?;
         let shlib = sep_info.shlibs.unwrap_or(0);
 
-        let mut app = cast_struct_args!(SEPApp64, &kernel[sep_info.sep_app_pos..], (if shlib == 0 { 0 } else { 4 }, false));
+        let mut app = cast_struct_args!(SEPApp64, slice_from(kernel, sep_info.sep_app_pos)?, (if shlib == 0 { 0 } else { 4 }, false))?;
         let sepappsize = SEPAPP_64_SIZE + match app.srcver.get_major() {
             2100.. => 36,
             1700.. => 4,
@@ -399,22 +482,34 @@ fn split32(kernel: &[u8], outdir: &Path, mut sep_info: SEPinfo, mut outbuf: BufW
         };
         let mut tail;
 
+        //SEPOS' CRC32 covers the whole image, including the structs
+        let mut region = None;
+        grow_region(&mut region, app.phys_text, app.phys_text.saturating_add(app.size_text));
+        if app.size_data != 0 {
+            grow_region(&mut region, app.phys_data, app.phys_data.saturating_add(app.size_data));
+        }
+        fw.crc = check_crc(kernel, sep_info.sepos_crc32, sep_info.sepos_crc32_off, region, &mut outbuf)?;
+
         //dump struct from start of kernel
-        bootout = outdir.join("sepdump-struct.extra");
-        filewrite(&bootout, &kernel[range_size(app.phys_text as usize, 0x1000)]);
+        fw.modules.push(SepModule::raw(ModuleKind::Struct, None, "struct", app.phys_text, raw_module(kernel, app.phys_text as usize, 0x1000)?));
         writeln!(&mut outbuf, "struct       size 0x1000")?;
         app.phys_text += 0x1000;
-        app.size_text -= 0x1000;
+        app.size_text = app.size_text.checked_sub(0x1000).ok_or(SepSplitError::Truncated { offset: app.phys_text as usize, needed: 0x1000 })?;
 
         let mut i = 2;
         while i < n_apps {
             if i != 2 {
-                app = cast_struct_args!(SEPApp64, &kernel[sep_info.sep_app_pos..], (if shlib == 0 { 0 } else { 4 }, false));
+                app = cast_struct_args!(SEPApp64, slice_from(kernel, sep_info.sep_app_pos)?, (if shlib == 0 { 0 } else { 4 }, false))?;
             }
-            tail = strslice(&app.app_name);
-            let data_buf = &kernel[range_size(app.phys_data as usize, app.size_data as usize)].to_owned();
-            restore_file(i, &kernel[range_size(app.phys_text as usize, (app.size_text + app.size_data) as usize)], outdir, tail, Some(data_buf), None);
-            let uuid = Uuid::from_bytes_le(app.app_uuid).hyphenated().to_string();
+            tail = strslice(&app.app_name)?;
+            let data = range_size(app.phys_data as usize, app.size_data as usize);
+            let macho = restore_file(kernel, app.phys_text as usize, app.size_text.saturating_add(app.size_data) as usize, Some(data), None, fix.module(i, tail, Some(Uuid::from_bytes_le(app.app_uuid))))?;
+            fw.modules.push(SepModule {
+                //the size of SEPOS includes the structs that are split off
+                size_fields: (i != 2).then_some((sep_info.sep_app_pos + 8, Some(sep_info.sep_app_pos + 24))),
+                ..SepModule::from_app64(if i == 2 { ModuleKind::Rootserver } else { ModuleKind::App }, i, &app, false, macho)?
+            });
+            let uuid = Uuid::from_bytes_le(app.app_uuid);
             writeln!(&mut outbuf, "{tail:-12} phys_text {:#08x}, virt {:#06x}, size_text {:#08x}, phys_data {:#x}, size_data {:#07x}, entry {:#x},\n             UUID {uuid}",
                 app.phys_text, app.virt, app.size_text, app.phys_data, app.size_data, app.ventry)?;
             sep_info.sep_app_pos += sepappsize;
@@ -424,11 +519,15 @@ fn split32(kernel: &[u8], outdir: &Path, mut sep_info: SEPinfo, mut outbuf: BufW
         if shlib != 0 {
             let max = n_apps + shlib + 2;
             while i < max {
-                app = cast_struct_args!(SEPApp64, &kernel[sep_info.sep_app_pos..], (4, false));
-                tail = strslice(&app.app_name);
-                let data_buf = &kernel[range_size(app.phys_data as usize, app.size_data as usize)].to_owned();
-                restore_file(i, &kernel[range_size(app.phys_text as usize, (app.size_text + app.size_data) as usize)], outdir, tail, Some(data_buf), Some(app.size_text as usize));
-                let uuid = Uuid::from_bytes_le(app.app_uuid).hyphenated().to_string();
+                app = cast_struct_args!(SEPApp64, slice_from(kernel, sep_info.sep_app_pos)?, (4, false))?;
+                tail = strslice(&app.app_name)?;
+                let data = range_size(app.phys_data as usize, app.size_data as usize);
+                let macho = restore_file(kernel, app.phys_text as usize, app.size_text.saturating_add(app.size_data) as usize, Some(data), Some(app.size_text as usize), fix.module(i, tail, Some(Uuid::from_bytes_le(app.app_uuid))))?;
+                fw.modules.push(SepModule {
+                    size_fields: Some((sep_info.sep_app_pos + 8, Some(sep_info.sep_app_pos + 24))),
+                    ..SepModule::from_app64(ModuleKind::SharedLib, i, &app, false, macho)?
+                });
+                let uuid = Uuid::from_bytes_le(app.app_uuid);
                 writeln!(&mut outbuf, "{tail:-12} phys_text {:#08x}, virt {:#06x}, size_text {:#08x}, phys_data {:#x}, size_data {:#07x}, entry {:#x},\n             UUID {uuid}",
                     app.phys_text, app.virt, app.size_text, app.phys_data, app.size_data, app.ventry)?;
                 sep_info.sep_app_pos += sepappsize;
@@ -436,154 +535,709 @@ fn split32(kernel: &[u8], outdir: &Path, mut sep_info: SEPinfo, mut outbuf: BufW
             }
         }
     } else { //older SEP
+        fw.format = SepFormat::Bits32 { old: true };
         /*
             preparation for loop, find offset of "SEPOS" string and 
             calculate size of structs based off "SEPD" string and previous string
         */
-        let tailoff = memmem::find(&kernel[sep_info.sep_app_pos..], b"SEPOS       ").unwrap_or_else(|| panic!("Could not find SEPOS string")); //offset of the name in the struct
-        sep_info.sepapp_size = memmem::find(&kernel[range_size(sep_info.sep_app_pos+tailoff, 128)], b"SEPD").unwrap_or_else(|| panic!("Could not find SEPD string")); 
+        let tailoff = memmem::find(slice_from(kernel, sep_info.sep_app_pos)?, b"SEPOS       ").ok_or(SepSplitError::MissingString("SEPOS"))?; //offset of the name in the struct
+        sep_info.sepapp_size = memmem::find(slice_size(kernel, sep_info.sep_app_pos+tailoff, 128)?, b"SEPD").ok_or(SepSplitError::MissingString("SEPD"))?; 
 
         for index in 2.. {
             let (tail, mut apps);
-            assert!(sep_info.sep_app_pos != 0, "SEPApp position is 0!");
-            apps = cast_struct!(SEPAppOld, &kernel[sep_info.sep_app_pos..]);
+            apps = cast_struct!(SEPAppOld, slice_from(kernel, sep_info.sep_app_pos)?)?;
             if apps.phys == 0 { //end of structs, nothing else to do
-                return outbuf.flush() 
+                break
             } else if index == 2 { //need SEPOS kernel's offset to dump structs
-                bootout = outdir.join("sepdump-extra_struct");
-                filewrite(&bootout, &kernel[range_size(apps.phys as usize, 0x1000)]); 
+                fw.crc = check_crc(kernel, sep_info.sepos_crc32, sep_info.sepos_crc32_off, Some((apps.phys, apps.phys + u64::from(apps.size))), &mut outbuf)?;
+                fw.modules.push(SepModule::raw(ModuleKind::Struct, None, "struct", apps.phys, raw_module(kernel, apps.phys as usize, 0x1000)?));
                 writeln!(&mut outbuf, "struct       size 0x1000")?;
                 apps.phys += 0x1000;
-                apps.size -= 0x1000;
+                apps.size = apps.size.checked_sub(0x1000).ok_or(SepSplitError::Truncated { offset: apps.phys as usize, needed: 0x1000 })?;
             }
-            tail = strslice(&kernel[range_size(sep_info.sep_app_pos + tailoff, 12)]);
-            let uuid = Uuid::from_bytes_le(kernel[range_size(sep_info.sep_app_pos + tailoff + 12, 16)].try_into().unwrap()).hyphenated().to_string();
+            tail = strslice(slice_size(kernel, sep_info.sep_app_pos + tailoff, 12)?)?;
+            let uuid = Uuid::from_bytes_le(slice_size(kernel, sep_info.sep_app_pos + tailoff + 12, 16)?.try_into().unwrap()); //infallable, slice of 16 bytes
             writeln!(&mut outbuf, "{tail:-12} phys {:#08x}, virt {:#x}, size {:#08x}, entry {:#x},\n             UUID {uuid}", 
                       apps.phys,  apps.virt,  apps.size,  apps.entry)?;
             sep_info.sep_app_pos += sep_info.sepapp_size;
-            restore_file(index, &kernel[range_size(apps.phys as usize, apps.size as usize)], outdir, tail, None, None);
+            fw.modules.push(SepModule {
+                uuid: Some(uuid),
+                virt: u64::from(apps.virt),
+                entry: u64::from(apps.entry),
+                ..SepModule::raw(if index == 2 { ModuleKind::Rootserver } else { ModuleKind::App }, Some(index), tail, apps.phys, restore_file(kernel, apps.phys as usize, apps.size as usize, None, None, fix.module(index, tail, Some(uuid)))?)
+            });
         }
     }
-    outbuf.flush()
+    outbuf.flush()?;
+    Ok(fw)
 }
 
 //gets the position of the SEPApp struct and a temporary SEPApp size, using structs in the SEP
-fn sep32_structs(krnl: &[u8]) -> SEPinfo {
-    let legionstr = cast_struct!(Legion32, &krnl[0x400..]);
-    let monitorstr = cast_struct!(SEPMonitorBootArgs, &krnl[legionstr.off as usize..]);
-    let krnlbastr = cast_struct!(SEPKernBootArgs, &krnl[monitorstr.args_off as usize..]);
-    SEPinfo {
+fn sep32_structs(krnl: &[u8]) -> Result<SEPinfo, SepSplitError> {
+    let legionstr = cast_struct!(Legion32, slice_from(krnl, 0x400)?)?;
+    let monitorstr = cast_struct!(SEPMonitorBootArgs, slice_from(krnl, legionstr.off as usize)?)?;
+    let krnlbastr = cast_struct!(SEPKernBootArgs, slice_from(krnl, monitorstr.args_off as usize)?)?;
+    Ok(SEPinfo {
         sep_app_pos: monitorstr.args_off as usize + KRNLBOOTARGS_SIZE, 
         sepapp_size: SEPAPP_SIZE.to_owned(),
         sepapps: krnlbastr.num_apps.lt(&0xFF).then_some(krnlbastr.num_apps as usize),
         shlibs: krnlbastr.num_shlibs.ne(&0).then_some(krnlbastr.num_shlibs as usize),
-    }
+        sepos_crc32: krnlbastr.sepos_crc32.val,
+        sepos_crc32_off: monitorstr.args_off as usize + krnlbastr.sepos_crc32.pos as usize,
+    })
 }
 
-//find the offset of the SEP HDR struct for 64-bit
-fn find_off(krnl: &[u8]) -> (u64, u8) { 
-    if &krnl[range_size(0x1004, 16)] == b"Built by legion2" { 
+//find the offset of the SEP HDR struct for 64-bit, the legion subversion and the UUID of the legion header (iOS 16 and later)
+fn find_off(krnl: &[u8]) -> Result<(u64, u8, Option<Uuid>), SepSplitError> { 
+    let legion_at = |off| krnl.get(range_size(off, 16)) == Some(b"Built by legion2");
+    if legion_at(0x1004) { 
         //iOS 15 and below
-        let hdr = cast_struct!(Legion64Old, &krnl[0x1000..]);
-        (if hdr.structoff != 0 { u64::from(hdr.structoff) } else { 0xFFFF }, hdr.subversion as u8)
-    } else if &krnl[range_size(0x103c, 16)] == b"Built by legion2" {
+        let hdr = cast_struct!(Legion64Old, slice_from(krnl, 0x1000)?)?;
+        Ok((if hdr.structoff != 0 { u64::from(hdr.structoff) } else { 0xFFFF }, hdr.subversion as u8, None))
+    } else if legion_at(0x103c) {
         //iOS 16
-        let hdr16 = cast_struct!(Legion64, &krnl[0x1000..]);
-        let uuid = Uuid::from_bytes_le(hdr16.uuid).hyphenated().to_string();
-        println!("HDR UUID: {uuid}");
-        (u64::from(hdr16.structoff), hdr16.subversion as u8)
-    } else if &krnl[range_size(0x408, 16)] == b"Built by legion2" {
-        let hdr = cast_struct!(Legion32, &krnl[0x400..]);
-        (u64::from(hdr.off), hdr.subversion as u8)
+        let hdr16 = cast_struct!(Legion64, slice_from(krnl, 0x1000)?)?;
+        Ok((u64::from(hdr16.structoff), hdr16.subversion as u8, Some(Uuid::from_bytes_le(hdr16.uuid))))
+    } else if legion_at(0x408) {
+        let hdr = cast_struct!(Legion32, slice_from(krnl, 0x400)?)?;
+        Ok((u64::from(hdr.off), hdr.subversion as u8, None))
     } else {
-        eprintln!("[!] Invalid or unknown kernel inputted, exiting.");
-        process::exit(1)
+        Err(SepSplitError::UnknownFormat)
     }
 }
 
-//test that the kernel is valid, find_off will verify other cases
-fn test_krnl(krnl: &[u8]) -> Option<Vec<u8>> {
-    if krnl[..2] == [0x30, 0x83] {
-        eprintln!("[!] IMG4 Header detected, please extract (and decrypt) the SEP firmware first. Exiting.");
-        process::exit(1)
-    } else if &krnl[8..16] == b"eGirBwRD" { //LZVN compression, "DRawBridGe"
-        use bindings::lzvn_decode;
-        let start = if krnl[range_size(0x10000, 4)] == [0,0,0,0] { 0x20000 } else { 0x10000 };
-        let startptr: *const c_void = krnl[start..].as_ptr().cast();
-        let startlen = krnl.len() - start;
-
-        let mut destlen: usize = u32::from_le_bytes(
-            krnl[range_size(0x18, 4)].try_into().unwrap() //infallable, taking slice of 4 bytes ad converting into array wih len 4
-        ).try_into().unwrap();
-        let mut destbuf: Vec<u8> = vec![0; destlen as usize];
+//decompresses an LZVN buffer, the output grows if the expected size is too small
+#[cfg(feature = "rust-lzvn")]
+pub(crate) fn lzvn_decode(src: &[u8], destlen: usize) -> Result<Vec<u8>, SepSplitError> {
+    lzvn::decode(src, destlen)
+}
+
+#[cfg(not(feature = "rust-lzvn"))]
+pub(crate) use c_lzvn_decode as lzvn_decode;
+
+//compresses a buffer with LZVN
+#[cfg(feature = "rust-lzvn")]
+#[allow(clippy::unnecessary_wraps)] //same signature as the C encoder
+pub(crate) fn lzvn_encode(src: &[u8]) -> Result<Vec<u8>, SepSplitError> {
+    Ok(lzvn::encode(src))
+}
+
+#[cfg(not(feature = "rust-lzvn"))]
+pub(crate) use c_lzvn_encode as lzvn_encode;
+
+//decompresses an LZVN buffer with the C decoder, growing the output buffer if the expected size is too small
+#[cfg(feature = "c-lzvn")]
+#[cfg_attr(feature = "rust-lzvn", allow(dead_code))] //only for testing the Rust codec against
+pub(crate) fn c_lzvn_decode(src: &[u8], mut destlen: usize) -> Result<Vec<u8>, SepSplitError> {
+    use core::ffi::c_void;
+
+    let startptr: *const c_void = src.as_ptr().cast();
+    let startlen = src.len();
+    let mut destbuf: Vec<u8> = vec![0; destlen];
+
+    loop {
         let destptr: *mut c_void = destbuf.as_mut_ptr().cast();
+        let complen = unsafe { 
+            bindings::lzvn_decode(destptr, destlen, startptr, startlen) 
+        };
+        if complen == 0 { return Err(SepSplitError::DecompressFailed) }
+
+        #[allow(clippy::comparison_chain)] //this is more confusing
+        if complen == destlen { break; } 
+        else if complen < destlen {
+            destbuf.truncate(complen);
+            break;
+        }
+        destlen *= 2; //the SEP firmware may have lied to us about the decompressed size
+        destbuf.resize(destlen, 0);
+    }
+    Ok(destbuf)
+}
+
+//compresses a buffer with the C encoder, growing the output buffer if the compressed data does not fit
+#[cfg(feature = "c-lzvn")]
+#[cfg_attr(feature = "rust-lzvn", allow(dead_code))] //only for testing the Rust codec against
+pub(crate) fn c_lzvn_encode(src: &[u8]) -> Result<Vec<u8>, SepSplitError> {
+    use core::ffi::c_void;
 
-        loop {
-            let complen = unsafe { 
-                lzvn_decode(destptr, destlen, startptr, startlen) 
-            };
-            assert_ne!(complen, 0, "Decompression failed (truncated input?)");
-
-            #[allow(clippy::comparison_chain)] //this is more confusing
-            if complen == destlen { break; } 
-            else if complen < destlen {
-                destbuf.truncate(complen as usize);
-                break;
+    let startptr: *const c_void = src.as_ptr().cast();
+    let mut work: Vec<u8> = vec![0; unsafe { bindings::lzvn_encode_work_size() }];
+    let mut destlen = src.len() + src.len() / 16 + 0x1000; //incompressible data grows a little
+    let mut destbuf: Vec<u8> = vec![0; destlen];
+
+    loop {
+        let destptr: *mut c_void = destbuf.as_mut_ptr().cast();
+        let complen = unsafe {
+            bindings::lzvn_encode(destptr, destlen, startptr, src.len(), work.as_mut_ptr().cast())
+        };
+        if complen != 0 {
+            destbuf.truncate(complen);
+            return Ok(destbuf)
+        }
+        if destlen > src.len() * 2 + 0x1000 { return Err(SepSplitError::CompressFailed) }
+        destlen *= 2;
+        destbuf.resize(destlen, 0);
+    }
+}
+
+//the LZVN container of older SEP firmwares, "DRawBridGe"
+pub(crate) fn is_lzvn_container(krnl: &[u8]) -> bool {
+    krnl.get(8..16) == Some(b"eGirBwRD")
+}
+
+//the LZVN stream starts at 0x10000, or at 0x20000 when the header takes more than 0x10000 bytes
+fn lzvn_payload_start(krnl: &[u8]) -> Result<usize, SepSplitError> {
+    Ok(if slice_size(krnl, 0x10000, 4)? == [0,0,0,0] { 0x20000 } else { 0x10000 })
+}
+
+//compresses the firmware into the same LZVN container as the original, keeping its header
+//the decompressed size at 0x18 is the only field that changes
+pub(crate) fn lzvn_container(original: &[u8], image: &[u8]) -> Result<Vec<u8>, SepSplitError> {
+    let start = lzvn_payload_start(original)?;
+    let destlen = u32::try_from(image.len()).map_err(|_| SepSplitError::CompressFailed)?;
+    let mut out = slice_size(original, 0, start)?.to_owned();
+    slice_size_mut(&mut out, 0x18, 4)?.copy_from_slice(&destlen.to_le_bytes());
+    out.extend_from_slice(&lzvn_encode(image)?);
+    Ok(out)
+}
+
+//test that the kernel is valid, find_off will verify other cases
+//returns the payload of an IMG4 container, or the decompressed kernel
+fn test_krnl<'a>(krnl: &'a [u8], key: Option<&DecryptKey>) -> Result<Cow<'a, [u8]>, SepSplitError> {
+    if img4::is_img4(krnl) {
+        let im4p = Im4p::parse(krnl)?;
+        let payload = if im4p.is_encrypted() {
+            let key = key.ok_or(SepSplitError::Img4Wrapped)?;
+            Cow::Owned(key.decrypt(im4p.payload))
+        } else {
+            Cow::Borrowed(im4p.payload)
+        };
+        if let Some(compression) = im4p.compression {
+            if compression.algorithm != 1 {
+                return Err(SepSplitError::BadCompression("unknown IM4P compression algorithm"))
             }
-            destlen *= 2; //the SEP firmware may have lied to us about the decompressed size
-            destbuf.resize(destlen as usize, 0);
+            return Ok(Cow::Owned(lzfse::decode(&payload, Some(compression.size as usize))?))
+        }
+        return match payload {
+            Cow::Borrowed(payload) => test_krnl(payload, None),
+            Cow::Owned(payload) => Ok(Cow::Owned(test_krnl(&payload, None)?.into_owned()))
+        }
+    } else if lzfse::is_lzfse(krnl) {
+        return Ok(Cow::Owned(lzfse::decode(krnl, None)?))
+    } else if is_lzvn_container(krnl) {
+        let destlen: usize = u32::from_le_bytes(
+            slice_size(krnl, 0x18, 4)?.try_into().unwrap() //infallable, taking slice of 4 bytes ad converting into array wih len 4
+        ).try_into().unwrap();
+        return Ok(Cow::Owned(lzvn_decode(slice_from(krnl, lzvn_payload_start(krnl)?)?, destlen)?));
+    }
+    Ok(Cow::Borrowed(krnl))
+}
+
+/// How the info of a split firmware is printed to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The table of modules, printed while splitting
+    #[default]
+    Text,
+    /// The manifest as JSON, printed after splitting
+    Json,
+}
+
+/// Options for splitting a SEP firmware
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+#[allow(clippy::struct_excessive_bools)] //independent switches, set with the with_ methods
+pub struct SplitOptions {
+    /// The IV and key to decrypt an encrypted IM4P payload with
+    pub key: Option<DecryptKey>,
+    /// The board to split the SEP firmware of when the input is an IPSW or OTA zip, like `n61` (all boards if not set)
+    pub device: Option<String>,
+    /// How the info of the split firmware is printed by `sepsplit`
+    pub format: OutputFormat,
+    /// Fail if the CRC32 stored in the firmware does not match
+    pub strict: bool,
+    /// What the output file names start with instead of `sepdump`
+    pub prefix: Option<String>,
+    /// The template of the output file names, `NameTemplate::DEFAULT` if not set
+    pub template: Option<NameTemplate>,
+    /// Keep the output files that already exist instead of overwriting them
+    pub no_clobber: bool,
+    /// Only reconstruct and write the modules matching one of these, every module if empty
+    pub modules: Vec<ModuleSelector>,
+    /// Write the files into an archive at the output path instead of a directory (`-` for a tar on stdout)
+    pub archive: Option<ArchiveFormat>,
+    /// Also hash the firmware and the modules with SHA-1, not only SHA-256
+    pub sha1: bool,
+    /// Move the modules to their virtual address in the SEP app table
+    pub rebase: bool,
+}
+
+impl SplitOptions {
+    /// Sets the IV and key to decrypt an encrypted IM4P payload with
+    #[must_use]
+    pub const fn with_key(mut self, key: DecryptKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Sets how the info of the split firmware is printed by `sepsplit`
+    #[must_use]
+    pub const fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets if the split fails when the CRC32 stored in the firmware does not match
+    #[must_use]
+    pub const fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets the board to split the SEP firmware of when the input is an IPSW or OTA zip
+    #[must_use]
+    pub fn with_device(mut self, device: &str) -> Self {
+        self.device = Some(device.to_owned());
+        self
+    }
+
+    /// Sets what the output file names start with instead of `sepdump`
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_owned());
+        self
+    }
+
+    /// Sets the template of the output file names
+    #[must_use]
+    pub fn with_template(mut self, template: NameTemplate) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    /// Sets the modules to reconstruct and write, every module if empty
+    #[must_use]
+    pub fn with_modules(mut self, modules: Vec<ModuleSelector>) -> Self {
+        self.modules = modules;
+        self
+    }
+
+    /// Sets if the output files that already exist are kept instead of overwritten
+    #[must_use]
+    pub const fn with_no_clobber(mut self, no_clobber: bool) -> Self {
+        self.no_clobber = no_clobber;
+        self
+    }
+
+    /// Sets if the firmware and the modules are also hashed with SHA-1
+    #[must_use]
+    pub const fn with_sha1(mut self, sha1: bool) -> Self {
+        self.sha1 = sha1;
+        self
+    }
+
+    /// Sets if the modules are moved to their virtual address in the SEP app table,
+    /// with their segments and the pointers dyld rebases, the original addresses are kept in the manifest
+    #[must_use]
+    pub const fn with_rebase(mut self, rebase: bool) -> Self {
+        self.rebase = rebase;
+        self
+    }
+
+    /// Sets the archive to write the files into instead of a directory
+    #[must_use]
+    pub const fn with_archive(mut self, archive: ArchiveFormat) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+}
+
+//splits the firmware in memory, printing the table of modules to outbuf
+//the modules borrow krnl unless it had to be decrypted or decompressed
+fn split_buf<'a>(krnl: &'a [u8], opts: &SplitOptions, outbuf: BufWriter<Box<dyn Write>>) -> Result<SepFirmware<'a>, SepSplitError> {
+    let mut fw = split_image(krnl, opts, outbuf, Fix::Selected(&opts.modules))?;
+    if !opts.modules.is_empty() {
+        fw.modules.retain(|m| opts.modules.iter().any(|s| s.matches(m)));
+        if fw.modules.is_empty() {
+            let names: Vec<_> = opts.modules.iter().map(ToString::to_string).collect();
+            return Err(SepSplitError::NoModule(names.join(", ")))
+        }
+    }
+    if opts.rebase {
+        fw.rebase()?;
+    }
+    fw.hash(krnl, opts.sha1);
+    Ok(fw)
+}
+
+//walks the structs of the firmware, only fixing the modules that fix selects
+fn split_image<'a>(krnl: &'a [u8], opts: &SplitOptions, mut outbuf: BufWriter<Box<dyn Write>>, fix: Fix) -> Result<SepFirmware<'a>, SepSplitError> {
+    let image = test_krnl(krnl, opts.key.as_ref())?;
+    let (hdr_offset, ver, hdr_uuid) = find_off(&image)?;
+    if let Some(uuid) = hdr_uuid {
+        writeln!(outbuf, "HDR UUID: {uuid}")?;
+    }
+
+    let fw = if ver == 1 { //32-bit SEP
+        let septype = sep32_structs(&image)?;
+        SepFirmware { hdr_offset, ..split32(&image, septype, outbuf, fix)? }
+    } else { //64-bit SEP
+        split64(hdr_offset as usize, &image, outbuf, ver, fix)?
+    };
+    let mut fw = SepFirmware { hdr_uuid, image, ..fw };
+    fw.read_load_commands();
+    opts.template.as_ref().map_or_else(Cow::default, Cow::Borrowed)
+        .name_modules(&mut fw.modules, opts.prefix.as_deref().unwrap_or("sepdump"));
+    match fw.crc {
+        Some(crc) if opts.strict && !crc.matches() => Err(SepSplitError::CrcMismatch { expected: crc.expected, actual: crc.actual }),
+        _ => Ok(fw)
+    }
+}
+
+/// Splits a SEP firmware in memory, without writing anything.
+/// # Arguments
+/// * `input` - The bytes of the SEP firmware, either raw or in an IM4P
+/// * `opts` - The options to split with
+/// # Returns
+/// * The split firmware, its modules borrow `input` unless it had to be decrypted or decompressed
+/// # Errors
+/// * The firmware is in an unknown format, truncated, or could not be decompressed
+/// * The firmware is in an encrypted IM4P and no key was given
+/// * A struct in the firmware could not be read
+pub fn split_bytes<'a>(input: &'a [u8], opts: &SplitOptions) -> Result<SepFirmware<'a>, SepSplitError> {
+    split_buf(input, opts, BufWriter::new(Box::new(std::io::sink())))
+}
+
+/// A SEP firmware read by `read_firmware`, which dereferences to its bytes
+pub enum FirmwareInput {
+    Mapped(memmap2::Mmap), // A raw or IM4P firmware, mapped into memory
+    Zipped(Vec<u8>),       // The SEP firmware read out of an IPSW or OTA zip
+}
+
+impl std::ops::Deref for FirmwareInput {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(map) => map,
+            Self::Zipped(buf) => buf,
         }
-        return Some(destbuf);
     }
-    None
+}
+
+/// Reads a single SEP firmware, to split it in memory with `split_bytes`.
+/// # Arguments
+/// * `filein` - The SEP firmware, raw or in an IM4P, or an IPSW or OTA zip
+/// * `opts` - The options to split with, `device` selects the board in a zip
+/// # Errors
+/// * Input file errors (permissions, not found, etc.)
+/// * The zip could not be read
+/// * The zip has no SEP firmware for the board, or has several and no board was selected
+pub fn read_firmware(filein: &Path, opts: &SplitOptions) -> Result<FirmwareInput, SepSplitError> {
+    if !is_zip(filein)? {
+        return Ok(FirmwareInput::Mapped(map_file(filein)?))
+    }
+    let mut seps = Vec::new();
+    for_each_sep(filein, opts, |name, _, sepfw| {
+        seps.push((name.to_owned(), sepfw));
+        Ok(())
+    })?;
+    if seps.len() > 1 {
+        return Err(SepSplitError::ManySepFirmwares(seps.into_iter().map(|(name, _)| name).collect()))
+    }
+    seps.pop().map(|(_, sepfw)| FirmwareInput::Zipped(sepfw)).ok_or(SepSplitError::NoSepFirmware)
 }
 
 /// The main logic of the program.
 /// # Arguments
-/// * `filein` - The input file to read from
-/// * `outdir` - The output directory to write to
-/// * `verbose` - The verbosity level (0 for no output, 1 for normal output)
+/// * `filein` - The input file to read from, an IPSW or OTA zip is split with `split_archive`
+/// * `outdir` - The output directory to write to, or the archive if `opts.archive` is set (`-` for a tar on stdout)
+/// * `verbose` - The verbosity level (0 for no output, 1 for normal output, 2 to also list the written files, 3 to also describe the input)
+/// * `opts` - The options to split with, `format` selects between the table and the JSON manifest on stdout
 /// # Errors
 /// * Input file errors (permissions, not found, etc.)
-/// * Errors while writing to the output directory
+/// * Any error from `split_bytes` or `split_archive`
+/// * Errors while writing to the output directory or archive
 /// * Errors while writing to stdout
-pub fn sepsplit(filein: &str, outdir: &Path, verbose: usize) -> Result<(), std::io::Error> {
-    let mut krnl: Vec<u8> = fs::read(filein)?;
-    if let Some(newkrnl) = test_krnl(&krnl) {
-        krnl = newkrnl;
+pub fn sepsplit(filein: &str, outdir: &Path, verbose: usize, opts: &SplitOptions) -> Result<(), SepSplitError> {
+    if is_zip(Path::new(filein))? {
+        return split_archive(Path::new(filein), outdir, verbose, opts).map(|_| ())
     }
-    let (hdr_offset, ver) = find_off(&krnl);
-    
-    
-    //fast stdout
-    let stdout = std::io::stdout();
-    let outlock = stdout.lock();
-    let outbuf: BufWriter<Box<dyn Write>> = std::io::BufWriter::new(
-        if verbose == 1 {
-            Box::new(outlock)
+    let krnl = map_file(Path::new(filein))?;
+    //stdout is the archive, so nothing else can be printed to it
+    let verbose_out = if to_stdout(outdir, opts) { 0 } else { verbose };
+    let firmware = split_buf(&krnl, opts, stdout_buf(verbose_out, opts.format == OutputFormat::Text))?;
+    print_warnings(&firmware, verbose);
+    if verbose >= 3 {
+        eprintln!("{filein}: {}, image of {:#x} bytes", describe_input(&krnl), firmware.image().len());
+    }
+    with_output(outdir, verbose, opts, |sink| write_firmware(&firmware, sink, outdir, verbose))?;
+    if verbose_out >= 1 && opts.format == OutputFormat::Json {
+        writeln!(std::io::stdout().lock(), "{}", firmware.to_json())?;
+    }
+    Ok(())
+}
+
+/// Prints the info of a SEP firmware (see `firmware_info`), without writing any files.
+/// # Arguments
+/// * `filein` - The input file to read from, either a SEP firmware or an IPSW or OTA zip
+/// * `verbose` - The verbosity level (0 for no output, 1 for normal output, 3 to also describe the input)
+/// * `opts` - The options to split with, `format` selects between the text report and JSON on stdout
+/// # Errors
+/// * Input file errors (permissions, not found, etc.)
+/// * Any error from `firmware_info`
+/// * Errors while writing to stdout
+pub fn sepinfo(filein: &str, verbose: usize, opts: &SplitOptions) -> Result<(), SepSplitError> {
+    let text = opts.format == OutputFormat::Text;
+    let mut stdout = stdout_buf(verbose, true);
+    if is_zip(Path::new(filein))? {
+        let mut infos = Vec::new();
+        for_each_sep(Path::new(filein), opts, |name, _, sepfw| {
+            let info = firmware_info(&sepfw, opts)?;
+            if text {
+                writeln!(stdout, "{name}:\n{info}")?;
+            }
+            infos.push(json!({ "source": name, "info": info }));
+            Ok(())
+        })?;
+        if !text {
+            writeln!(stdout, "{}", manifest::to_json(&infos))?;
+        }
+        return Ok(stdout.flush()?)
+    }
+
+    let krnl = map_file(Path::new(filein))?;
+    let info = firmware_info(&krnl, opts)?;
+    if verbose >= 3 {
+        eprintln!("{filein}: {}, image of {:#x} bytes", describe_input(&krnl), info.sizes.image);
+    }
+    if text {
+        write!(stdout, "{info}")?;
+    } else {
+        writeln!(stdout, "{}", info.to_json())?;
+    }
+    Ok(stdout.flush()?)
+}
+
+//prints what could not be fixed on stderr, unless quiet
+fn print_warnings(firmware: &SepFirmware, verbose: usize) {
+    if verbose >= 1 {
+        for warning in &firmware.warnings {
+            eprintln!("Warning: {warning}");
+        }
+    }
+}
+
+//writes the manifest and the modules, listing the files on stderr to keep stdout for the table or JSON
+fn write_firmware(firmware: &SepFirmware, sink: &mut dyn Sink, outdir: &Path, verbose: usize) -> Result<(), SepSplitError> {
+    let files = firmware.write_sink(sink)?;
+    if verbose >= 2 {
+        for (name, written) in files {
+            eprintln!("{} {}", if written { "wrote" } else { "kept existing" }, outdir.join(name).display());
+        }
+    }
+    Ok(())
+}
+
+fn to_stdout(out: &Path, opts: &SplitOptions) -> bool {
+    opts.archive.is_some() && out == Path::new("-")
+}
+
+//calls write with the directory, or the archive (finished after), that out is for
+fn with_output(out: &Path, verbose: usize, opts: &SplitOptions, write: impl FnOnce(&mut dyn Sink) -> Result<(), SepSplitError>) -> Result<(), SepSplitError> {
+    let clobber = !opts.no_clobber;
+    let Some(archive) = opts.archive else {
+        return write(&mut DirSink { dir: out, clobber })
+    };
+    if to_stdout(out, opts) {
+        if archive == ArchiveFormat::Zip {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "zip archives can not be written to stdout").into())
+        }
+        let mut tar = TarSink::new(BufWriter::new(std::io::stdout().lock()));
+        write(&mut tar)?;
+        tar.finish()?;
+        return Ok(())
+    }
+
+    if let Some(parent) = out.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let Some(file) = filecreate(out, clobber)? else {
+        if verbose >= 2 {
+            eprintln!("kept existing {}", out.display());
+        }
+        return Ok(())
+    };
+    match archive {
+        ArchiveFormat::Tar => {
+            let mut tar = TarSink::new(file);
+            write(&mut tar)?;
+            tar.finish()?;
+        },
+        ArchiveFormat::Zip => {
+            let mut zip = zip::ZipWriter::new(file);
+            write(&mut zip)?;
+            zip.finish()?;
+        }
+    }
+    Ok(())
+}
+
+//what the firmware was wrapped in, for verbose output
+fn describe_input(krnl: &[u8]) -> &'static str {
+    if img4::is_img4(krnl) {
+        "IM4P"
+    } else if lzfse::is_lzfse(krnl) {
+        "LZFSE stream"
+    } else if is_lzvn_container(krnl) {
+        "LZVN container"
+    } else {
+        "raw firmware"
+    }
+}
+
+//maps a firmware into memory instead of reading it
+fn map_file(path: &Path) -> Result<memmap2::Mmap, std::io::Error> {
+    let file = fs::File::open(path)?;
+    //the firmware is only read, and is not expected to change while it is split
+    unsafe { memmap2::Mmap::map(&file) }
+}
+
+//fast stdout, or a sink if not verbose
+fn stdout_buf(verbose: usize, text: bool) -> BufWriter<Box<dyn Write>> {
+    BufWriter::new(
+        if verbose >= 1 && text {
+            Box::new(std::io::stdout().lock())
         } else {
             Box::new(std::io::sink())
         }
-    );
+    )
+}
 
-    if ver == 1 { //32-bit SEP
-        let septype = sep32_structs(&krnl);
-        split32(&krnl, outdir, septype, outbuf)
-    } else { //64-bit SEP
-        split64(hdr_offset as usize, &krnl, outdir, outbuf, ver)
+//gets the board and variant from the path of a SEP firmware in an IPSW or OTA, like "Firmware/all_flash/sep-firmware.n61.RELEASE.im4p"
+fn sep_board(path: &str) -> Option<(&str, &str)> {
+    let fname = path.rsplit('/').next()?;
+    let mut parts = fname.strip_prefix("sep-firmware.")?.strip_suffix(".im4p")?.split('.');
+    Some((parts.next()?, parts.next().unwrap_or("RELEASE")))
+}
+
+//gets the build number from a BuildManifest.plist, which is XML in IPSWs and OTAs
+fn build_version(manifest: &[u8]) -> Option<&str> {
+    let key = memmem::find(manifest, b"<key>ProductBuildVersion</key>")?;
+    let rest = &manifest[key..];
+    let start = memmem::find(rest, b"<string>")? + b"<string>".len();
+    let end = memmem::find(&rest[start..], b"</string>")?;
+    str::from_utf8(&rest[start..start + end]).ok().map(str::trim)
+}
+
+//reads and decompresses an entry of a zip, the entry being cut short or failing its CRC32 is a bad zip
+fn read_entry(zip: &mut zip::ZipArchive<fs::File>, name: &str) -> Result<Vec<u8>, SepSplitError> {
+    let mut data = Vec::new();
+    zip.by_name(name)?.read_to_end(&mut data).map_err(|err| match err.kind() {
+        std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof => SepSplitError::BadZip(err.into()),
+        _ => SepSplitError::Io(err)
+    })?;
+    Ok(data)
+}
+
+//calls f with the path, the folder name (like "n61_16G192") and the contents of every SEP firmware in the archive,
+//only for the board in opts if there is one
+fn for_each_sep(archive: &Path, opts: &SplitOptions, mut f: impl FnMut(&str, &str, Vec<u8>) -> Result<(), SepSplitError>) -> Result<(), SepSplitError> {
+    let mut zip = zip::ZipArchive::new(fs::File::open(archive)?)?;
+    let names: Vec<String> = zip.file_names().map(str::to_owned).collect();
+
+    //IPSWs have the manifest in the root, OTAs have it in AssetData/boot
+    let manifest = names.iter()
+        .filter(|name| name.rsplit('/').next() == Some("BuildManifest.plist"))
+        .min_by_key(|name| name.len());
+    let manifest = manifest.map(|name| read_entry(&mut zip, name)).transpose()?;
+    let build = manifest.as_deref().and_then(build_version).unwrap_or("unknown").to_owned();
+
+    let device = opts.device.as_deref().map(str::to_lowercase);
+    let seps: Vec<_> = names.iter()
+        .filter(|name| sep_board(name).is_some_and(|(board, _)| device.as_deref().is_none_or(|device| {
+            let board = board.to_lowercase();
+            device == board || device.strip_suffix("ap") == Some(&board)
+        })))
+        .collect();
+    if seps.is_empty() {
+        return Err(SepSplitError::NoSepFirmware)
     }
+
+    let mut dirnames: Vec<String> = Vec::with_capacity(seps.len());
+    for name in seps {
+        let Some((board, variant)) = sep_board(name) else { continue };
+        let mut base = format!("{board}_{build}");
+        if variant != "RELEASE" {
+            base = format!("{base}_{}", variant.to_lowercase());
+        }
+        //the same board can be in multiple folders of an IPSW
+        let mut dirname = base.clone();
+        let mut n = 2;
+        while dirnames.contains(&dirname) {
+            dirname = format!("{base}_{n}");
+            n += 1;
+        }
+        f(name, &dirname, read_entry(&mut zip, name)?)?;
+        dirnames.push(dirname);
+    }
+    Ok(())
+}
+
+/// Splits every SEP firmware in an IPSW or OTA zip, each into its own subdirectory of `outdir`.
+///
+/// The subdirectories are named after the board and build, like `n61_16G192`,
+/// with the variant appended if it is not a release build.
+/// If `opts.archive` is set, every firmware goes into the same archive at `outdir`, in these subdirectories.
+/// # Arguments
+/// * `archive` - The path to the IPSW or OTA zip
+/// * `outdir` - The output directory to create the subdirectories in, or the archive (`-` for a tar on stdout)
+/// * `verbose` - The verbosity level (0 for no output, 1 for normal output)
+/// * `opts` - The options to split with, `device` selects a single board
+/// # Returns
+/// * The subdirectories that were written to
+/// # Errors
+/// * The zip could not be read
+/// * No SEP firmware was found in the zip, or none for the selected board
+/// * Any error from `split_bytes`
+/// * Errors while writing to the output directory
+pub fn split_archive(archive: &Path, outdir: &Path, verbose: usize, opts: &SplitOptions) -> Result<Vec<PathBuf>, SepSplitError> {
+    let text = opts.format == OutputFormat::Text;
+    let verbose_out = if to_stdout(outdir, opts) { 0 } else { verbose };
+    let mut outdirs: Vec<PathBuf> = Vec::new();
+    let mut manifests = Vec::new();
+    with_output(outdir, verbose, opts, |sink| for_each_sep(archive, opts, |name, dirname, sepfw| {
+        let subdir = outdir.join(dirname);
+        let mut outbuf = stdout_buf(verbose_out, text);
+        writeln!(outbuf, "{name} -> {}", subdir.display())?;
+        let firmware = split_buf(&sepfw, opts, outbuf)?;
+        print_warnings(&firmware, verbose);
+        write_firmware(&firmware, &mut SubdirSink { sink: &mut *sink, dir: dirname }, &subdir, verbose)?;
+        manifests.push(json!({
+            "source": name,
+            "directory": subdir.to_string_lossy(),
+            "manifest": firmware.manifest(),
+        }));
+        outdirs.push(subdir);
+        Ok(())
+    }))?;
+    if verbose_out >= 1 && !text {
+        writeln!(std::io::stdout().lock(), "{}", manifest::to_json(&manifests))?;
+    }
+    Ok(outdirs)
 }
 
 use core::ffi::{c_char, CStr};
 
 /// Calls the main logic of the program with FFI.
 /// # Arguments
-/// * `filein` - the path to the extracted SEP firmware
+/// * `filein` - the path to the SEP firmware, either raw or in an unencrypted IM4P, or an IPSW/OTA zip
 /// * `outdir` - the path to the output directory
 /// * `verbose` - the verbosity level (0 for no output, 1 for normal output)
 /// # Returns
 /// * 0 on success
-/// * 1 on failure
+/// * 1 if the arguments are not valid UTF-8
+/// * the exit code of the `SepSplitError` on failure
 /// # Safety
 /// * `filein` must be a null terminated char array with valid UTF-8 characters and also be a path to a file
 /// * `outdir` must be a null terminated char array with valid UTF-8 characters and also be a path to a already existing directory
@@ -592,8 +1246,124 @@ pub unsafe extern "C" fn split(filein: *const c_char, outdir: *const c_char, ver
     let Ok(filein) = unsafe { CStr::from_ptr(filein) }.to_str() else { return 1 };
     let Ok(outdir) = unsafe { CStr::from_ptr(outdir) }.to_str() else { return 1 };
     let outdir = Path::new(outdir);
-    match sepsplit(filein, outdir, verbose) {
+    match sepsplit(filein, outdir, verbose, &SplitOptions::default()) {
         Ok(()) => 0,
-        Err(_) => 1
+        Err(err) => err.exit_code() as isize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        io::{Cursor, Write},
+        path::{Path, PathBuf},
+        process,
+    };
+
+    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+    use super::{check_crc, for_each_sep, grow_region, read_firmware, stdout_buf, SepSplitError, SplitOptions};
+
+    const MANIFEST: &[u8] = b"<dict><key>ProductBuildVersion</key><string>16G192</string></dict>";
+    const N61: &str = "Firmware/all_flash/sep-firmware.n61.RELEASE.im4p";
+
+    fn zip(entries: &[(&str, &[u8])], method: CompressionMethod) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            zip.start_file(*name, SimpleFileOptions::default().compression_method(method)).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
     }
-}
\ No newline at end of file
+
+    //writes the zip to a temporary file, as only files are read
+    fn temp_zip(name: &str, zip: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("sepsplit-{}-{name}.zip", process::id()));
+        fs::write(&path, zip).unwrap();
+        path
+    }
+
+    fn seps(path: &Path, opts: &SplitOptions) -> Result<Vec<(String, String, Vec<u8>)>, SepSplitError> {
+        let mut seps = Vec::new();
+        for_each_sep(path, opts, |name, dirname, sepfw| {
+            seps.push((name.to_owned(), dirname.to_owned(), sepfw));
+            Ok(())
+        })?;
+        Ok(seps)
+    }
+
+    //the CRC32 of a 64-bit SEP covers every app from the lowest to the highest address, with their data segments
+    #[test]
+    fn test_crc_region() {
+        let mut image = vec![0; 0x100];
+        image[0x40..0x49].copy_from_slice(b"123456789");
+        let mut region = None;
+        grow_region(&mut region, 0x45, 0x49);
+        grow_region(&mut region, 0x40, 0x45);
+        assert_eq!(region, Some((0x40, 0x49)));
+        let crc = check_crc(&image, 0xcbf4_3926, 0x10, region, &mut stdout_buf(0, true)).unwrap().unwrap();
+        assert!(crc.matches());
+        assert_eq!((crc.start, crc.end, crc.offset), (0x40, 0x49, 0x10));
+
+        //the bytes between the apps are covered, and the data segment can come first
+        image[0x80..0x90].copy_from_slice(b"SEPOS text......");
+        image[0x20..0x28].copy_from_slice(b"SEPOS rw");
+        let mut region = None;
+        grow_region(&mut region, 0x80, 0x90);
+        grow_region(&mut region, 0x20, 0x28);
+        let crc = check_crc(&image, 0xf472_0ed9, 0x10, region, &mut stdout_buf(0, true)).unwrap().unwrap();
+        assert_eq!((crc.start, crc.end, crc.actual), (0x20, 0x90, 0xf472_0ed9));
+        assert!(!check_crc(&image, 0xcbf4_3926, 0x10, region, &mut stdout_buf(0, true)).unwrap().unwrap().matches());
+
+        //no CRC32 is stored, or no region
+        assert!(check_crc(&image, 0, 0x10, region, &mut stdout_buf(0, true)).unwrap().is_none());
+        assert!(check_crc(&image, 0xcbf4_3926, 0x10, None, &mut stdout_buf(0, true)).unwrap().is_none());
+        //a region past the end of the image
+        assert!(matches!(check_crc(&image, 1, 0x10, Some((0x80, 0x200)), &mut stdout_buf(0, true)), Err(SepSplitError::Truncated { .. })));
+    }
+
+    #[test]
+    fn test_for_each_sep() {
+        let path = temp_zip("ipsw", &zip(&[
+            ("BuildManifest.plist", MANIFEST),
+            (N61, b"n61"),
+            ("Firmware/all_flash/sep-firmware.n61.DEVELOPMENT.im4p", b"n61 dev"),
+            ("Firmware/other/sep-firmware.n61.RELEASE.im4p", b"n61 again"),
+            ("Firmware/all_flash/sep-firmware.d10.RELEASE.im4p", b"d10"),
+            ("Firmware/all_flash/LLB.n61.RELEASE.im4p", b"not SEP"),
+        ], CompressionMethod::Deflated));
+
+        let entry = |name: &str, dirname: &str, data: &[u8]| (name.to_owned(), dirname.to_owned(), data.to_vec());
+        assert_eq!(seps(&path, &SplitOptions::default()).unwrap(), [
+            entry(N61, "n61_16G192", b"n61"),
+            entry("Firmware/all_flash/sep-firmware.n61.DEVELOPMENT.im4p", "n61_16G192_development", b"n61 dev"),
+            entry("Firmware/other/sep-firmware.n61.RELEASE.im4p", "n61_16G192_2", b"n61 again"),
+            entry("Firmware/all_flash/sep-firmware.d10.RELEASE.im4p", "d10_16G192", b"d10"),
+        ]);
+        assert_eq!(seps(&path, &SplitOptions::default().with_device("D10AP")).unwrap(), [
+            entry("Firmware/all_flash/sep-firmware.d10.RELEASE.im4p", "d10_16G192", b"d10"),
+        ]);
+        assert!(matches!(seps(&path, &SplitOptions::default().with_device("j72")), Err(SepSplitError::NoSepFirmware)));
+        assert!(matches!(read_firmware(&path, &SplitOptions::default().with_device("n61")), Err(SepSplitError::ManySepFirmwares(names)) if names.len() == 3));
+        assert_eq!(&*read_firmware(&path, &SplitOptions::default().with_device("d10")).unwrap(), b"d10");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_bad_zip() {
+        let ipsw = zip(&[("BuildManifest.plist", MANIFEST), (N61, b"SEPOS")], CompressionMethod::Stored);
+        //a firmware that does not match its CRC32
+        let mut corrupt = ipsw.clone();
+        let data = corrupt.windows(5).position(|w| w == b"SEPOS").unwrap();
+        corrupt[data] = b's';
+        //every error reading the zip is a bad zip, and not an I/O error
+        for (name, zip) in [("corrupt", &corrupt[..]), ("truncated", &ipsw[..ipsw.len() - 10]), ("header", &ipsw[..4])] {
+            let path = temp_zip(name, zip);
+            let err = seps(&path, &SplitOptions::default()).unwrap_err();
+            assert!(matches!(err, SepSplitError::BadZip(_)), "{name}: {err}");
+            assert_eq!(err.exit_code(), 11);
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,547 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    LZFSE stream decoder, following Apple's reference implementation (lzfse_decode_base.c, lzfse_fse.h).
+    A stream is a list of blocks, each starting with a magic:
+        "bvx-" - uncompressed block
+        "bvx1" - LZFSE block with an uncompressed header
+        "bvx2" - LZFSE block with a compressed header
+        "bvxn" - LZVN block
+        "bvx$" - end of stream
+*/
+
+//the bit twiddling below mirrors the reference implementation, which mixes signed and unsigned values
+#![allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+
+use crate::{lzvn_decode, SepSplitError};
+
+const MAGIC_END:          &[u8; 4] = b"bvx$";
+const MAGIC_UNCOMPRESSED: &[u8; 4] = b"bvx-";
+const MAGIC_V1:           &[u8; 4] = b"bvx1";
+const MAGIC_V2:           &[u8; 4] = b"bvx2";
+const MAGIC_LZVN:         &[u8; 4] = b"bvxn";
+
+const L_SYMBOLS:       usize = 20;
+const M_SYMBOLS:       usize = 20;
+const D_SYMBOLS:       usize = 64;
+const LITERAL_SYMBOLS: usize = 256;
+const L_STATES:        usize = 64;
+const M_STATES:        usize = 64;
+const D_STATES:        usize = 256;
+const LITERAL_STATES:  usize = 1024;
+const MATCHES_PER_BLOCK:  u32 = 10000;
+const LITERALS_PER_BLOCK: u32 = 4 * MATCHES_PER_BLOCK;
+const V1_HEADER_SIZE:  usize = 770;
+const V2_HEADER_SIZE:  usize = 32; //without the frequency tables
+pub const MAX_PREALLOC_RATIO: usize = 64; //the sizes in the headers are not trusted to preallocate more than this
+
+static L_EXTRA_BITS: [u8; L_SYMBOLS] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 3, 5, 8];
+static L_BASE_VALUE: [u32; L_SYMBOLS] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 20, 28, 60];
+static M_EXTRA_BITS: [u8; M_SYMBOLS] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 5, 8, 11];
+static M_BASE_VALUE: [u32; M_SYMBOLS] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 24, 56, 312];
+static D_EXTRA_BITS: [u8; D_SYMBOLS] = [
+    0,  0,  0,  0,  1,  1,  1,  1,  2,  2,  2,  2,  3,  3,  3,  3,
+    4,  4,  4,  4,  5,  5,  5,  5,  6,  6,  6,  6,  7,  7,  7,  7,
+    8,  8,  8,  8,  9,  9,  9,  9,  10, 10, 10, 10, 11, 11, 11, 11,
+    12, 12, 12, 12, 13, 13, 13, 13, 14, 14, 14, 14, 15, 15, 15, 15
+];
+#[allow(clippy::unreadable_literal)] //same as the reference implementation
+static D_BASE_VALUE: [u32; D_SYMBOLS] = [
+    0,      1,      2,      3,     4,     6,     8,     10,    12,    16,
+    20,     24,     28,     36,    44,    52,    60,    76,    92,    108,
+    124,    156,    188,    220,   252,   316,   380,   444,   508,   636,
+    764,    892,    1020,   1276,  1532,  1788,  2044,  2556,  3068,  3580,
+    4092,   5116,   6140,   7164,  8188,  10236, 12284, 14332, 16380, 20476,
+    24572,  28668,  32764,  40956, 49148, 57340, 65532, 81916, 98300, 114684,
+    131068, 163836, 196604, 229372
+];
+
+const fn bad(reason: &'static str) -> SepSplitError {
+    SepSplitError::BadCompression(reason)
+}
+
+fn read_u32(buf: &[u8], off: usize) -> Result<u32, SepSplitError> {
+    buf.get(off..off + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(bad("block header is truncated"))
+}
+
+fn read_u64(buf: &[u8], off: usize) -> Result<u64, SepSplitError> {
+    Ok(u64::from(read_u32(buf, off)?) | u64::from(read_u32(buf, off + 4)?) << 32)
+}
+
+const fn field(v: u64, offset: u32, nbits: u32) -> u64 {
+    (v >> offset) & ((1 << nbits) - 1)
+}
+
+const fn mask_lsb(x: u64, nbits: u32) -> u64 {
+    if nbits == 0 { 0 } else { x & (u64::MAX >> (64 - nbits)) }
+}
+
+//the header of a compressed block, as the uncompressed v1 header
+struct BlockHeader {
+    n_raw_bytes: u32,
+    n_literals: u32,
+    n_matches: u32,
+    n_literal_payload_bytes: u32,
+    n_lmd_payload_bytes: u32,
+    literal_bits: i32,
+    literal_state: [u16; 4],
+    lmd_bits: i32,
+    l_state: u16,
+    m_state: u16,
+    d_state: u16,
+    l_freq: [u16; L_SYMBOLS],
+    m_freq: [u16; M_SYMBOLS],
+    d_freq: [u16; D_SYMBOLS],
+    literal_freq: [u16; LITERAL_SYMBOLS],
+}
+
+impl BlockHeader {
+    const fn empty() -> Self {
+        Self {
+            n_raw_bytes: 0, n_literals: 0, n_matches: 0, n_literal_payload_bytes: 0, n_lmd_payload_bytes: 0,
+            literal_bits: 0, literal_state: [0; 4], lmd_bits: 0, l_state: 0, m_state: 0, d_state: 0,
+            l_freq: [0; L_SYMBOLS], m_freq: [0; M_SYMBOLS], d_freq: [0; D_SYMBOLS], literal_freq: [0; LITERAL_SYMBOLS],
+        }
+    }
+
+    //all of the frequency tables, in the order they are stored
+    fn freqs_mut(&mut self) -> impl Iterator<Item = &mut u16> {
+        self.l_freq.iter_mut()
+            .chain(self.m_freq.iter_mut())
+            .chain(self.d_freq.iter_mut())
+            .chain(self.literal_freq.iter_mut())
+    }
+
+    //parses a v1 header, returns the header and its size
+    fn parse_v1(block: &[u8]) -> Result<(Self, usize), SepSplitError> {
+        let u16_at = |off: usize| block.get(off..off + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or(bad("block header is truncated"));
+        let mut hdr = Self {
+            n_raw_bytes: read_u32(block, 4)?,
+            n_literals: read_u32(block, 12)?,
+            n_matches: read_u32(block, 16)?,
+            n_literal_payload_bytes: read_u32(block, 20)?,
+            n_lmd_payload_bytes: read_u32(block, 24)?,
+            literal_bits: read_u32(block, 28)? as i32,
+            literal_state: [u16_at(32)?, u16_at(34)?, u16_at(36)?, u16_at(38)?],
+            lmd_bits: read_u32(block, 40)? as i32,
+            l_state: u16_at(44)?,
+            m_state: u16_at(46)?,
+            d_state: u16_at(48)?,
+            ..Self::empty()
+        };
+        //v2 can only store -7 to 0, and the bit streams need it to be in this range
+        if !(-7..=0).contains(&hdr.literal_bits) || !(-7..=0).contains(&hdr.lmd_bits) {
+            return Err(bad("invalid bit stream start"))
+        }
+        for (i, freq) in hdr.freqs_mut().enumerate() {
+            *freq = u16_at(50 + i * 2)?;
+        }
+        Ok((hdr, V1_HEADER_SIZE))
+    }
+
+    //parses a v2 header, which packs the fields and compresses the frequency tables
+    fn parse_v2(block: &[u8]) -> Result<(Self, usize), SepSplitError> {
+        let (v0, v1, v2) = (read_u64(block, 8)?, read_u64(block, 16)?, read_u64(block, 24)?);
+        let mut hdr = Self {
+            n_raw_bytes: read_u32(block, 4)?,
+            n_literals: field(v0, 0, 20) as u32,
+            n_literal_payload_bytes: field(v0, 20, 20) as u32,
+            n_matches: field(v0, 40, 20) as u32,
+            literal_bits: field(v0, 60, 3) as i32 - 7,
+            literal_state: [field(v1, 0, 10) as u16, field(v1, 10, 10) as u16, field(v1, 20, 10) as u16, field(v1, 30, 10) as u16],
+            n_lmd_payload_bytes: field(v1, 40, 20) as u32,
+            lmd_bits: field(v1, 60, 3) as i32 - 7,
+            l_state: field(v2, 32, 10) as u16,
+            m_state: field(v2, 42, 10) as u16,
+            d_state: field(v2, 52, 10) as u16,
+            ..Self::empty()
+        };
+
+        let header_size = field(v2, 0, 32) as usize;
+        let freq_bytes = block.get(V2_HEADER_SIZE..header_size).ok_or(bad("block header is truncated"))?;
+        if freq_bytes.is_empty() {
+            return Ok((hdr, header_size)) //the frequency tables were omitted
+        }
+
+        let mut src = freq_bytes.iter();
+        let mut accum: u32 = 0;
+        let mut accum_nbits = 0;
+        for freq in hdr.freqs_mut() {
+            //refill one byte at a time, until the end of the header or the accumulator is full
+            while accum_nbits + 8 <= 32 {
+                let Some(&byte) = src.next() else { break };
+                accum |= u32::from(byte) << accum_nbits;
+                accum_nbits += 8;
+            }
+            let (value, nbits) = decode_freq(accum);
+            if nbits > accum_nbits {
+                return Err(bad("frequency table is truncated"))
+            }
+            *freq = value;
+            accum >>= nbits;
+            accum_nbits -= nbits;
+        }
+        if accum_nbits >= 8 || src.next().is_some() {
+            return Err(bad("frequency table has trailing bytes"))
+        }
+        Ok((hdr, header_size))
+    }
+}
+
+//decodes one value of a compressed frequency table, returns the value and the number of bits used
+fn decode_freq(bits: u32) -> (u16, u32) {
+    static NBITS: [u8; 32] = [
+        2, 3, 2, 5, 2, 3, 2, 8, 2, 3, 2, 5, 2, 3, 2, 14,
+        2, 3, 2, 5, 2, 3, 2, 8, 2, 3, 2, 5, 2, 3, 2, 14
+    ];
+    static VALUE: [u8; 32] = [
+        0, 2, 1, 4, 0, 3, 1, 0, 0, 2, 1, 5, 0, 3, 1, 0,
+        0, 2, 1, 6, 0, 3, 1, 0, 0, 2, 1, 7, 0, 3, 1, 0
+    ];
+    let b = (bits & 31) as usize;
+    match NBITS[b] {
+        8  => (8 + ((bits >> 4) & 0xf) as u16, 8),
+        14 => (24 + ((bits >> 4) & 0x3ff) as u16, 14),
+        n  => (u16::from(VALUE[b]), u32::from(n)),
+    }
+}
+
+//an entry of the literal decoding table
+#[derive(Clone, Copy, Default)]
+struct Entry {
+    k: u32,      // number of bits to read for the next state
+    symbol: u8,
+    delta: i32,  // added to the bits read to get the next state
+}
+
+//an entry of the L, M and D decoding tables
+#[derive(Clone, Copy, Default)]
+struct ValueEntry {
+    total_bits: u32, // bits for the next state and the value
+    value_bits: u32, // bits for the value
+    delta: i32,
+    vbase: u32,
+}
+
+//builds the decoding table for the frequencies, calling make for each state
+fn build_table<T: Copy + Default>(nstates: usize, freqs: &[u16], make: impl Fn(usize, u32, i32) -> T) -> Result<Vec<T>, SepSplitError> {
+    let mut table = Vec::with_capacity(nstates);
+    let n_clz = (nstates as u32).leading_zeros();
+    for (symbol, &f) in freqs.iter().enumerate().filter(|(_, &f)| f != 0) {
+        let f = u32::from(f);
+        if table.len() + f as usize > nstates {
+            return Err(bad("frequencies are larger than the number of states"))
+        }
+        let k = f.leading_zeros() - n_clz; // shift needed to ensure N <= (F<<K) < 2*N
+        let j0 = ((2 * nstates as u32) >> k) - f;
+        for j in 0..f {
+            table.push(if j < j0 {
+                make(symbol, k, (((f + j) << k) as i32) - nstates as i32)
+            } else {
+                make(symbol, k - 1, ((j - j0) << (k - 1)) as i32)
+            });
+        }
+    }
+    table.resize(nstates, T::default());
+    Ok(table)
+}
+
+fn literal_table(freqs: &[u16]) -> Result<Vec<Entry>, SepSplitError> {
+    build_table(LITERAL_STATES, freqs, |symbol, k, delta| Entry { k, symbol: symbol as u8, delta })
+}
+
+fn value_table(nstates: usize, freqs: &[u16], extra_bits: &[u8], base_value: &[u32]) -> Result<Vec<ValueEntry>, SepSplitError> {
+    build_table(nstates, freqs, |symbol, k, delta| ValueEntry {
+        total_bits: k + u32::from(extra_bits[symbol]),
+        value_bits: u32::from(extra_bits[symbol]),
+        delta,
+        vbase: base_value[symbol],
+    })
+}
+
+//bit stream that is read backwards from the end of the buffer, holding 56 to 63 bits
+struct InStream<'a> {
+    buf: &'a [u8], // the bytes that are not read yet
+    accum: u64,
+    accum_nbits: u32,
+}
+
+impl<'a> InStream<'a> {
+    fn new(buf: &'a [u8], n: i32) -> Result<Self, SepSplitError> {
+        //load 7 bytes if there are no extra bits, so that there are never 64 bits in the accumulator
+        let nbytes = if n == 0 { 7 } else { 8 };
+        let split = buf.len().checked_sub(nbytes).ok_or(bad("payload is truncated"))?;
+        let (rest, init) = buf.split_at(split);
+        let accum = init.iter().rev().fold(0, |acc, &b| acc << 8 | u64::from(b));
+        let accum_nbits = (nbytes as i32 * 8 + n) as u32;
+        if !(56..64).contains(&accum_nbits) || accum >> accum_nbits != 0 {
+            return Err(bad("invalid bit stream start"))
+        }
+        Ok(Self { buf: rest, accum, accum_nbits })
+    }
+
+    //refill the accumulator to 56 to 63 bits
+    fn flush(&mut self) -> Result<(), SepSplitError> {
+        let nbits = (63 - self.accum_nbits) & !7;
+        let nbytes = (nbits >> 3) as usize;
+        let split = self.buf.len().checked_sub(nbytes).ok_or(bad("payload is truncated"))?;
+        let (rest, incoming) = self.buf.split_at(split);
+        let incoming = incoming.iter().rev().fold(0, |acc, &b| acc << 8 | u64::from(b));
+        self.buf = rest;
+        self.accum = if nbits == 0 { self.accum } else { (self.accum << nbits) | incoming };
+        self.accum_nbits += nbits;
+        Ok(())
+    }
+
+    fn pull(&mut self, n: u32) -> Result<u64, SepSplitError> {
+        self.accum_nbits = self.accum_nbits.checked_sub(n).ok_or(bad("bit stream is exhausted"))?;
+        let result = self.accum >> self.accum_nbits;
+        self.accum = mask_lsb(self.accum, self.accum_nbits);
+        Ok(result)
+    }
+
+    fn decode(&mut self, state: &mut usize, table: &[Entry]) -> Result<u8, SepSplitError> {
+        let e = table.get(*state).ok_or(bad("invalid state"))?;
+        *state = usize::try_from(i64::from(e.delta) + self.pull(e.k)? as i64).map_err(|_| bad("invalid state"))?;
+        Ok(e.symbol)
+    }
+
+    fn decode_value(&mut self, state: &mut usize, table: &[ValueEntry]) -> Result<u32, SepSplitError> {
+        let e = table.get(*state).ok_or(bad("invalid state"))?;
+        let bits = self.pull(e.total_bits)?;
+        *state = usize::try_from(i64::from(e.delta) + (bits >> e.value_bits) as i64).map_err(|_| bad("invalid state"))?;
+        Ok(e.vbase + mask_lsb(bits, e.value_bits) as u32)
+    }
+}
+
+//decodes a compressed LZFSE block, appending to out
+fn decode_block(hdr: &BlockHeader, payload: &[u8], out: &mut Vec<u8>) -> Result<(), SepSplitError> {
+    if hdr.n_literals > LITERALS_PER_BLOCK || hdr.n_matches > MATCHES_PER_BLOCK || !hdr.n_literals.is_multiple_of(4) {
+        return Err(bad("too many literals or matches in block"))
+    }
+    let literal_end = hdr.n_literal_payload_bytes as usize;
+    let lmd_end = literal_end + hdr.n_lmd_payload_bytes as usize;
+    let literal_payload = payload.get(..literal_end).ok_or(bad("payload is truncated"))?;
+    let lmd_payload = payload.get(literal_end..lmd_end).ok_or(bad("payload is truncated"))?;
+
+    //literals are interleaved in 4 streams sharing one table
+    let literal_decoder = literal_table(&hdr.literal_freq)?;
+    let mut literals = Vec::with_capacity(hdr.n_literals as usize);
+    let mut states = hdr.literal_state.map(usize::from);
+    let mut input = InStream::new(literal_payload, hdr.literal_bits)?;
+    for _ in 0..hdr.n_literals / 4 {
+        input.flush()?;
+        for state in &mut states {
+            literals.push(input.decode(state, &literal_decoder)?);
+        }
+    }
+
+    let l_decoder = value_table(L_STATES, &hdr.l_freq, &L_EXTRA_BITS, &L_BASE_VALUE)?;
+    let m_decoder = value_table(M_STATES, &hdr.m_freq, &M_EXTRA_BITS, &M_BASE_VALUE)?;
+    let d_decoder = value_table(D_STATES, &hdr.d_freq, &D_EXTRA_BITS, &D_BASE_VALUE)?;
+    let (mut l_state, mut m_state, mut d_state) = (usize::from(hdr.l_state), usize::from(hdr.m_state), usize::from(hdr.d_state));
+    let mut input = InStream::new(lmd_payload, hdr.lmd_bits)?;
+
+    let block_start = out.len();
+    let mut lit = literals.as_slice();
+    let mut d: Option<usize> = None;
+    for _ in 0..hdr.n_matches {
+        input.flush()?;
+        let l = input.decode_value(&mut l_state, &l_decoder)? as usize;
+        let m = input.decode_value(&mut m_state, &m_decoder)? as usize;
+        let new_d = input.decode_value(&mut d_state, &d_decoder)? as usize;
+        if new_d != 0 { d = Some(new_d); } //0 means the previous distance is used again
+
+        if l > lit.len() {
+            return Err(bad("not enough literals"))
+        }
+        let (now, rest) = lit.split_at(l);
+        out.extend_from_slice(now);
+        lit = rest;
+
+        if m != 0 {
+            let dist = d.filter(|&d| d <= out.len()).ok_or(bad("match distance is out of range"))?;
+            //the match may overlap with itself, so copy byte by byte
+            let from = out.len() - dist;
+            for i in 0..m {
+                out.push(out[from + i]);
+            }
+        }
+    }
+
+    if out.len() - block_start != hdr.n_raw_bytes as usize {
+        return Err(bad("block decompressed to the wrong size"))
+    }
+    Ok(())
+}
+
+/// Checks if the buffer starts with an LZFSE block
+#[must_use]
+pub fn is_lzfse(buf: &[u8]) -> bool {
+    buf.get(..4).is_some_and(|magic| [MAGIC_UNCOMPRESSED, MAGIC_V1, MAGIC_V2, MAGIC_LZVN].iter().any(|m| magic == *m))
+}
+
+/// Decompresses an LZFSE stream
+/// # Arguments
+/// * `src` - The LZFSE stream, starting with a block magic
+/// * `size_hint` - The expected decompressed size, if known, only preallocated up to 64 times the size of `src`
+/// # Errors
+/// * The stream is corrupted or truncated
+pub fn decode(src: &[u8], size_hint: Option<usize>) -> Result<Vec<u8>, SepSplitError> {
+    let mut out = Vec::with_capacity(size_hint.unwrap_or_else(|| src.len().saturating_mul(4)).min(src.len().saturating_mul(MAX_PREALLOC_RATIO)));
+    let mut block = src;
+    loop {
+        let magic = block.get(..4).ok_or(bad("missing end of stream block"))?;
+        let consumed = match magic {
+            m if m == MAGIC_END => return Ok(out),
+            m if m == MAGIC_UNCOMPRESSED => {
+                let n_raw_bytes = read_u32(block, 4)? as usize;
+                out.extend_from_slice(block.get(8..8 + n_raw_bytes).ok_or(bad("uncompressed block is truncated"))?);
+                8 + n_raw_bytes
+            },
+            m if m == MAGIC_LZVN => {
+                let n_raw_bytes = read_u32(block, 4)? as usize;
+                let n_payload_bytes = read_u32(block, 8)? as usize;
+                let payload = block.get(12..12 + n_payload_bytes).ok_or(bad("LZVN block is truncated"))?;
+                let decoded = lzvn_decode(payload, n_raw_bytes.min(payload.len().saturating_mul(MAX_PREALLOC_RATIO)))?;
+                if decoded.len() != n_raw_bytes {
+                    return Err(bad("block decompressed to the wrong size"))
+                }
+                out.extend_from_slice(&decoded);
+                12 + n_payload_bytes
+            },
+            m if m == MAGIC_V1 || m == MAGIC_V2 => {
+                let (hdr, header_size) = if m == MAGIC_V1 { BlockHeader::parse_v1(block)? } else { BlockHeader::parse_v2(block)? };
+                let payload_size = hdr.n_literal_payload_bytes as usize + hdr.n_lmd_payload_bytes as usize;
+                let payload = block.get(header_size..header_size + payload_size).ok_or(bad("LZFSE block is truncated"))?;
+                decode_block(&hdr, payload, &mut out)?;
+                header_size + payload_size
+            },
+            _ => return Err(bad("unknown block magic"))
+        };
+        block = &block[consumed..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, BlockHeader, V1_HEADER_SIZE};
+
+    const TEXT: &[u8] = b"sepsplit sepsplit sepsplit splits sepos";
+
+    //TEXT as a bvx2 block with a compressed header, 3 and 5 bits of padding in the literal and L, M, D streams
+    const BVX2: [u8; 171] = [
+        0x62, 0x76, 0x78, 0x32, 0x27, 0x00, 0x00, 0x00, 0x0c, 0x00, 0xd0, 0x00, 0x00, 0x04, 0x00, 0x40,
+        0x7d, 0x8e, 0xa2, 0x22, 0xbb, 0x0d, 0x00, 0x20, 0x91, 0x00, 0x00, 0x00, 0x31, 0xd8, 0x00, 0x07,
+        0x87, 0x87, 0x87, 0x00, 0x70, 0x08, 0x00, 0x00, 0x87, 0xc0, 0x23, 0x00, 0x00, 0x00, 0x1c, 0x02,
+        0x8f, 0x02, 0x00, 0x8f, 0x02, 0xc0, 0xa3, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3d, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7c,
+        0x0f, 0xc0, 0xf7, 0x00, 0xdf, 0x03, 0x7c, 0x0f, 0x2f, 0x09, 0x3c, 0x50, 0xdf, 0x03, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0xfe, 0x1e, 0x09, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x32, 0x04,
+    ];
+
+    //"sks sks sks" as a bvxn block: 4 literals, a match of 7 at distance 4 and the end of stream
+    const BVXN: [u8; 27] = [
+        0x62, 0x76, 0x78, 0x6e, 0x0b, 0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00,
+        0xe4, b's', b'k', b's', b' ', 0x20, 0x04, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    //the same block with an uncompressed v1 header
+    fn bvx1() -> Vec<u8> {
+        let (hdr, header_size) = BlockHeader::parse_v2(&BVX2).unwrap();
+        let mut block = b"bvx1".to_vec();
+        for field in [hdr.n_raw_bytes, hdr.n_literal_payload_bytes + hdr.n_lmd_payload_bytes, hdr.n_literals, hdr.n_matches,
+                      hdr.n_literal_payload_bytes, hdr.n_lmd_payload_bytes, hdr.literal_bits as u32] {
+            block.extend_from_slice(&field.to_le_bytes());
+        }
+        hdr.literal_state.iter().for_each(|state| block.extend_from_slice(&state.to_le_bytes()));
+        block.extend_from_slice(&hdr.lmd_bits.to_le_bytes());
+        let freqs = hdr.l_freq.iter().chain(&hdr.m_freq).chain(&hdr.d_freq).chain(&hdr.literal_freq);
+        for value in [hdr.l_state, hdr.m_state, hdr.d_state].iter().chain(freqs) {
+            block.extend_from_slice(&value.to_le_bytes());
+        }
+        assert_eq!(block.len(), V1_HEADER_SIZE);
+        block.extend_from_slice(&BVX2[header_size..]);
+        block
+    }
+
+    //an uncompressed block, the LZVN block, the v1 and v2 blocks and the end of stream
+    fn chained() -> Vec<u8> {
+        let mut stream = b"bvx-\x03\x00\x00\x00SEP".to_vec();
+        stream.extend_from_slice(&BVXN);
+        stream.extend_from_slice(&bvx1());
+        stream.extend_from_slice(&BVX2);
+        stream.extend_from_slice(b"bvx$");
+        stream
+    }
+
+    #[test]
+    fn test_decode_blocks() {
+        assert_eq!(decode(&[&BVX2[..], b"bvx$"].concat(), None).unwrap(), TEXT);
+        assert_eq!(decode(&[&bvx1()[..], b"bvx$"].concat(), Some(TEXT.len())).unwrap(), TEXT);
+        assert_eq!(decode(&[&BVXN[..], b"bvx$"].concat(), None).unwrap(), b"sks sks sks");
+        assert_eq!(decode(b"bvx-\x03\x00\x00\x00SEPbvx$", None).unwrap(), b"SEP");
+        assert_eq!(decode(b"bvx$", None).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_decode_chained() {
+        let expected = [&b"SEPsks sks sks"[..], TEXT, TEXT].concat();
+        assert_eq!(decode(&chained(), None).unwrap(), expected);
+        //the size hint is only a hint
+        assert_eq!(decode(&chained(), Some(1)).unwrap(), expected);
+        assert_eq!(decode(&chained(), Some(usize::MAX)).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let stream = chained();
+        for len in 0..stream.len() {
+            assert!(decode(&stream[..len], None).is_err(), "truncated to {len} bytes");
+        }
+    }
+
+    #[test]
+    fn test_decode_garbage() {
+        assert!(decode(b"bvx?\x00\x00\x00\x00bvx$", None).is_err());
+        //a v1 header with 5 bits of padding
+        let mut block = bvx1();
+        block[28] = 5;
+        assert!(decode(&[&block[..], b"bvx$"].concat(), None).is_err());
+        //an LZVN block claiming 4 GiB
+        let mut block = BVXN;
+        block[4..8].fill(0xff);
+        assert!(decode(&[&block[..], b"bvx$"].concat(), None).is_err());
+        //no byte changed to any value may panic
+        let stream = chained();
+        for i in 0..stream.len() {
+            for value in [0x00, 0x01, 0x7f, 0x80, 0xff, stream[i] ^ 0x10] {
+                let mut corrupted = stream.clone();
+                corrupted[i] = value;
+                let _ = decode(&corrupted, None);
+            }
+        }
+    }
+}
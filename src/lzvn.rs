@@ -0,0 +1,344 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    LZVN codec, following Apple's reference implementation (lzvn_decode_base.c).
+    A stream is a list of opcodes, each with L literal bytes following the opcode,
+    then a match of M bytes at distance D back in the output (D is kept from the previous match if not given):
+        sml_d  LLMMMDDD DDDDDDDD                    L 0-3, M 3-10, D < 0x800
+        med_d  101LLMMM DDDDDDMM DDDDDDDD           L 0-3, M 3-34, D < 0x4000
+        lrg_d  LLMMM111 DDDDDDDD DDDDDDDD           L 0-3, M 3-10, D < 0x10000
+        pre_d  LLMMM110                             L 0-3, M 3-10, previous D
+        sml_m  1111MMMM                             M 1-15, previous D
+        lrg_m  11110000 MMMMMMMM                    M 16-271, previous D
+        sml_l  1110LLLL                             L 1-15
+        lrg_l  11100000 LLLLLLLL                    L 16-271
+        nop    00001110 or 00010110
+        eos    00000110 and 7 zero bytes
+    The other opcodes (0x1e-0x3e with the low 3 bits 110, 0x70-0x7f, 0xd0-0xdf) are invalid.
+    The encoder is a plain greedy one, the SEP only needs the stream to be valid.
+*/
+
+//the encoder packs lengths and distances into bytes after checking their range
+#![allow(clippy::cast_possible_truncation)]
+
+use crate::{lzfse::MAX_PREALLOC_RATIO, SepSplitError};
+
+const EOS: u8 = 0x06;
+const MAX_DISTANCE: usize = 0xffff;
+const HASH_BITS: u32 = 14;
+
+//the kinds of opcodes, indexed by the opcode byte
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op { SmlD, MedD, LrgD, PreD, SmlM, LrgM, SmlL, LrgL, Nop, Eos, Udef }
+
+const fn op(opc: u8) -> Op {
+    match opc {
+        0x06 => Op::Eos,
+        0x0e | 0x16 => Op::Nop,
+        0x70..=0x7f | 0xd0..=0xdf => Op::Udef,
+        0xa0..=0xbf => Op::MedD,
+        0xe0 => Op::LrgL,
+        0xe1..=0xef => Op::SmlL,
+        0xf0 => Op::LrgM,
+        0xf1..=0xff => Op::SmlM,
+        _ if opc & 7 == 7 => Op::LrgD,
+        _ if opc & 7 == 6 && opc < 0x40 => Op::Udef,
+        _ if opc & 7 == 6 => Op::PreD,
+        _ => Op::SmlD,
+    }
+}
+
+//copies a match, which may overlap with itself
+fn copy_match(out: &mut Vec<u8>, dist: usize, len: usize) -> Result<(), SepSplitError> {
+    if dist == 0 || dist > out.len() {
+        return Err(SepSplitError::DecompressFailed)
+    }
+    let from = out.len() - dist;
+    if dist >= len {
+        out.extend_from_within(from..from + len);
+    } else {
+        for i in 0..len {
+            out.push(out[from + i]);
+        }
+    }
+    Ok(())
+}
+
+/// Decompresses an LZVN stream
+/// # Arguments
+/// * `src` - The LZVN stream, which ends at the end of stream opcode or at the end of `src`
+/// * `size_hint` - The expected decompressed size, the output grows past it if needed, and at most 64 times `src` is preallocated
+/// # Errors
+/// * The stream has an invalid opcode, a match before the start of the output, or is truncated in the middle of an opcode
+pub fn decode(src: &[u8], size_hint: usize) -> Result<Vec<u8>, SepSplitError> {
+    let mut out = Vec::with_capacity(size_hint.min(src.len().saturating_mul(MAX_PREALLOC_RATIO)));
+    let mut pos = 0;
+    let mut dist = 0;
+    let byte = |i: usize| src.get(i).copied().map(usize::from).ok_or(SepSplitError::DecompressFailed);
+
+    while let Some(&opc) = src.get(pos) {
+        let o = usize::from(opc);
+        //(opcode length, literals, match length), dist is updated in place
+        let (len, lits, mlen) = match op(opc) {
+            Op::SmlD => {
+                dist = (o & 7) << 8 | byte(pos + 1)?;
+                (2, o >> 6, (o >> 3 & 7) + 3)
+            },
+            Op::MedD => {
+                let opc23 = byte(pos + 1)? | byte(pos + 2)? << 8;
+                dist = opc23 >> 2;
+                (3, o >> 3 & 3, ((o & 7) << 2 | opc23 & 3) + 3)
+            },
+            Op::LrgD => {
+                dist = byte(pos + 1)? | byte(pos + 2)? << 8;
+                (3, o >> 6, (o >> 3 & 7) + 3)
+            },
+            Op::PreD => (1, o >> 6, (o >> 3 & 7) + 3),
+            Op::SmlM => (1, 0, o & 0xf),
+            Op::LrgM => (2, 0, byte(pos + 1)? + 16),
+            Op::SmlL => (1, o & 0xf, 0),
+            Op::LrgL => (2, byte(pos + 1)? + 16, 0),
+            Op::Nop => (1, 0, 0),
+            Op::Eos => break,
+            Op::Udef => return Err(SepSplitError::DecompressFailed),
+        };
+        pos += len;
+        if lits != 0 {
+            let lits = src.get(pos..pos + lits).ok_or(SepSplitError::DecompressFailed)?;
+            out.extend_from_slice(lits);
+            pos += lits.len();
+        }
+        if mlen != 0 {
+            copy_match(&mut out, dist, mlen)?;
+        }
+    }
+    Ok(out)
+}
+
+fn emit_literals(out: &mut Vec<u8>, mut lits: &[u8]) {
+    while !lits.is_empty() {
+        let n = lits.len().min(271);
+        if n < 16 {
+            out.push(0xe0 | n as u8);
+        } else {
+            out.extend_from_slice(&[0xe0, (n - 16) as u8]);
+        }
+        out.extend_from_slice(&lits[..n]);
+        lits = &lits[n..];
+    }
+}
+
+//the first part of a match sets the distance, the rest reuses it
+fn emit_match(out: &mut Vec<u8>, dist: usize, len: usize) {
+    let first = if dist < 0x4000 {
+        let m = len.min(34) - 3;
+        out.extend_from_slice(&[0xa0 | (m >> 2) as u8, ((dist << 2) as u8) | (m & 3) as u8, (dist >> 6) as u8]);
+        m + 3
+    } else {
+        let m = len.min(10) - 3;
+        out.extend_from_slice(&[(m << 3) as u8 | 7, dist as u8, (dist >> 8) as u8]);
+        m + 3
+    };
+    let mut left = len - first;
+    while left != 0 {
+        let n = left.min(271);
+        if n < 16 {
+            out.push(0xf0 | n as u8);
+        } else {
+            out.extend_from_slice(&[0xf0, (n - 16) as u8]);
+        }
+        left -= n;
+    }
+}
+
+const fn hash(word: u32) -> usize {
+    (word.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compresses a buffer into an LZVN stream, ended by the end of stream opcode
+#[must_use]
+pub fn encode(src: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(src.len() / 2 + 16);
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut pos = 0;
+    let mut lit_start = 0;
+
+    while pos + 4 <= src.len() {
+        let word = u32::from_le_bytes([src[pos], src[pos + 1], src[pos + 2], src[pos + 3]]);
+        let h = hash(word);
+        let cand = table[h];
+        table[h] = pos;
+
+        if cand != usize::MAX && pos - cand <= MAX_DISTANCE && src[cand..cand + 4] == src[pos..pos + 4] {
+            let len = 4 + src[pos + 4..].iter().zip(&src[cand + 4..]).take_while(|(a, b)| a == b).count();
+            emit_literals(&mut out, &src[lit_start..pos]);
+            emit_match(&mut out, pos - cand, len);
+            pos += len;
+            lit_start = pos;
+        } else {
+            pos += 1;
+        }
+    }
+    emit_literals(&mut out, &src[lit_start..]);
+    out.push(EOS);
+    out.extend_from_slice(&[0; 7]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::{decode, encode};
+
+    //pseudo-random bytes, the same on every run
+    fn noise(seed: u32, len: usize) -> Vec<u8> {
+        let mut seed = seed;
+        (0..len).map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u8
+        }).collect()
+    }
+
+    //compressible data, with literals, short and long matches at small and large distances
+    fn sample() -> Vec<u8> {
+        let mut seed: u32 = 0x1234_5678;
+        let mut data = Vec::new();
+        while data.len() < 0x40000 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let r = (seed >> 8) as usize;
+            if r.is_multiple_of(3) || data.len() < 0x100 {
+                data.extend((0..r % 40).map(|i| (seed >> (i % 24)) as u8));
+            } else {
+                let dist = 1 + r % data.len().min(0x12000);
+                let from = data.len() - dist;
+                for i in 0..(r >> 4) % 300 {
+                    data.push(data[from + i]);
+                }
+            }
+        }
+        data
+    }
+
+    //noise repeated at distances around the limits of the sml_d, med_d and lrg_d opcodes, and past the largest one
+    fn distances() -> Vec<u8> {
+        let mut data = noise(1, 0x11000);
+        for dist in [1, 2, 0x7ff, 0x800, 0x801, 0x3fff, 0x4000, 0x4001, 0xffff, 0x10000] {
+            for _ in 0..40 {
+                data.push(data[data.len() - dist]);
+            }
+            data.extend(noise(dist as u32, 7));
+        }
+        data
+    }
+
+    fn inputs() -> Vec<Vec<u8>> {
+        let testfwp = Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+        let mut inputs = vec![
+            sample(), distances(), noise(2, 0x10000), vec![0; 0x10000], vec![0xff; 300], b"sepsplit".to_vec(), b"abc".to_vec(), Vec::new(),
+            [&[0; 5000][..], &noise(3, 20), &[7; 3000]].concat(),
+        ];
+        if let Ok(dir) = fs::read_dir(testfwp) {
+            inputs.extend(dir.filter_map(|entry| fs::read(entry.ok()?.path()).ok()));
+        }
+        inputs
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for input in inputs() {
+            let compressed = encode(&input);
+            assert!(compressed.ends_with(&[0x06, 0, 0, 0, 0, 0, 0, 0]));
+            assert_eq!(decode(&compressed, input.len()).unwrap(), input);
+            assert_eq!(decode(&compressed, 0).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn test_decode_opcodes() {
+        let stream = [
+            &[0xe3, b'a', b'b', b'c'][..],    // sml_l, 3 literals
+            &[0x07, 0x03, 0x00],              // lrg_d, 3 bytes at distance 3
+            &[0x4e, b'd'],                    // pre_d, 1 literal and 4 bytes at distance 3
+            &[0xf2],                          // sml_m, 2 bytes at distance 3
+            &[0x0e, 0x16],                    // nop
+            &[0xa9, 0x05, 0x00, b'x'],        // med_d, 1 literal and 8 bytes at distance 1
+            &[0x80, 0x02, b'y', b'z'],        // sml_d, 2 literals and 3 bytes at distance 2
+            &[0xf0, 0x00],                    // lrg_m, 16 bytes at distance 2
+            &[0xe0, 0x00],                    // lrg_l, 16 literals
+            &(0..16).collect::<Vec<u8>>(),
+            &[0x06, 0, 0, 0, 0, 0, 0, 0],     // eos
+            b"ignored",
+        ].concat();
+        let expected = [&b"abcabcdbcdbcdxxxxxxxxxyzyzyzyzyzyzyzyzyzyzy"[..], &(0..16).collect::<Vec<u8>>()].concat();
+        assert_eq!(decode(&stream, 0).unwrap(), expected);
+        //a stream may also end without the end of stream opcode
+        assert_eq!(decode(&[0xe3, b'a', b'b', b'c'], 3).unwrap(), b"abc");
+        //the size comes from the firmware, a huge one is not preallocated
+        assert_eq!(decode(&[0xe3, b'a', b'b', b'c'], usize::MAX).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        //a distance of 0
+        assert!(decode(&[0xe1, b'a', 0x00, 0x00], 4).is_err());
+        //a distance before the start of the output
+        assert!(decode(&[0xe1, b'a', 0x07, 0x02, 0x00], 4).is_err());
+        //a match before any distance was set
+        assert!(decode(&[0xe1, b'a', 0xf3], 4).is_err());
+        //the undefined opcodes
+        for opc in (0x1e..=0x3e).step_by(8).chain(0x70..=0x7f).chain(0xd0..=0xdf) {
+            assert!(decode(&[0xe1, b'a', opc, 0, 0, 0], 8).is_err(), "opcode {opc:#x}");
+        }
+        //truncated in the middle of an opcode or of its literals
+        for stream in [&[0xe3, b'a'][..], &[0x07, 0x03], &[0xa9, 0x05], &[0xe0], &[0xf0], &[0x80]] {
+            assert!(decode(stream, 4).is_err(), "{stream:x?}");
+        }
+        //every prefix either fails or gives a prefix of the input
+        let input = sample();
+        let compressed = encode(&input[..0x2000]);
+        for len in 0..compressed.len() {
+            if let Ok(out) = decode(&compressed[..len], 0) {
+                assert!(input.starts_with(&out));
+            }
+        }
+    }
+
+    //the C codec is the reference, it also uses every kind of opcode, unlike our encoder
+    #[cfg(feature = "c-lzvn")]
+    #[test]
+    fn test_decode_matches_c() {
+        use crate::c_lzvn_encode;
+
+        for input in inputs() {
+            let compressed = c_lzvn_encode(&input).unwrap();
+            assert_eq!(decode(&compressed, input.len()).unwrap(), input);
+            assert_eq!(decode(&compressed, 1).unwrap(), crate::c_lzvn_decode(&compressed, input.len()).unwrap());
+        }
+    }
+
+    #[cfg(feature = "c-lzvn")]
+    #[test]
+    fn test_encode_matches_c() {
+        for input in inputs() {
+            let compressed = encode(&input);
+            assert_eq!(crate::c_lzvn_decode(&compressed, input.len()).unwrap(), input);
+        }
+    }
+}
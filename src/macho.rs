@@ -0,0 +1,832 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    The header and load commands of a Mach-O, as in Apple's loader.h, little endian only:
+        header          magic, cputype, cpusubtype, filetype, ncmds, sizeofcmds, flags (and reserved for 64-bit)
+        load commands   ncmds of them, each is cmd and cmdsize then the fields of the command
+    The fields that are 32 or 64-bit (segment and section addresses and sizes) are u64 in the model.
+    Commands that are not modelled are kept as their raw bytes, as are the bytes after the fields of one
+    that are still in its cmdsize, so writing a parsed Mach-O gives back the same bytes.
+    Only the header and the load commands are parsed, the rest of the file is left to the caller.
+*/
+
+use uuid::Uuid;
+
+use crate::{SepSplitError, SrcVer};
+
+pub const MH_MAGIC:               u32 = 0xfeed_face;
+pub const MH_MAGIC_64:            u32 = 0xfeed_facf;
+pub const LC_SEGMENT:             u32 = 0x1;
+pub const LC_SYMTAB:              u32 = 0x2;
+pub const LC_THREAD:              u32 = 0x4;
+pub const LC_UNIXTHREAD:          u32 = 0x5;
+pub const LC_DYSYMTAB:            u32 = 0xb;
+pub const LC_SEGMENT_64:          u32 = 0x19;
+pub const LC_UUID:                u32 = 0x1b;
+pub const LC_CODE_SIGNATURE:      u32 = 0x1d;
+pub const LC_SEGMENT_SPLIT_INFO:  u32 = 0x1e;
+pub const LC_DYLD_INFO:           u32 = 0x22;
+pub const LC_FUNCTION_STARTS:     u32 = 0x26;
+pub const LC_DATA_IN_CODE:        u32 = 0x29;
+pub const LC_SOURCE_VERSION:      u32 = 0x2a;
+pub const LC_BUILD_VERSION:       u32 = 0x32;
+pub const LC_DYLD_INFO_ONLY:      u32 = 0x8000_0022;
+pub const LC_MAIN:                u32 = 0x8000_0028;
+pub const LC_DYLD_EXPORTS_TRIE:   u32 = 0x8000_0033;
+pub const LC_DYLD_CHAINED_FIXUPS: u32 = 0x8000_0034;
+
+pub const SEG_PAGEZERO: &str = "__PAGEZERO";
+pub const SEG_DATA:     &str = "__DATA";
+pub const SEG_LINKEDIT: &str = "__LINKEDIT";
+
+const HEADER_SIZE: usize = 28;
+const CMD_HEADER_SIZE: usize = 8;
+
+/// The Mach-O header, without `ncmds` and `sizeofcmds` which come from the load commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachHeader {
+    pub magic: u32, // MH_MAGIC or MH_MAGIC_64
+    pub cputype: i32,
+    pub cpusubtype: i32,
+    pub filetype: u32,
+    pub flags: u32,
+    pub reserved: u32, // Only in 64-bit Mach-Os
+}
+
+/// A section of a segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub sectname: [u8; 16],
+    pub segname: [u8; 16],
+    pub addr: u64,
+    pub size: u64,
+    pub offset: u32,
+    pub align: u32,
+    pub reloff: u32,
+    pub nreloc: u32,
+    pub flags: u32,
+    pub reserved1: u32,
+    pub reserved2: u32,
+    pub reserved3: u32, // Only in 64-bit Mach-Os
+}
+
+/// `LC_SEGMENT` or `LC_SEGMENT_64`, depending on the header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub segname: [u8; 16],
+    pub vmaddr: u64,
+    pub vmsize: u64,
+    pub fileoff: u64,
+    pub filesize: u64,
+    pub maxprot: i32,
+    pub initprot: i32,
+    pub flags: u32,
+    pub sections: Vec<Section>,
+}
+
+/// `LC_SYMTAB`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SymTab {
+    pub symoff: u32,
+    pub nsyms: u32,
+    pub stroff: u32,
+    pub strsize: u32,
+}
+
+/// `LC_DYSYMTAB`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DySymTab {
+    pub ilocalsym: u32,
+    pub nlocalsym: u32,
+    pub iextdefsym: u32,
+    pub nextdefsym: u32,
+    pub iundefsym: u32,
+    pub nundefsym: u32,
+    pub tocoff: u32,
+    pub ntoc: u32,
+    pub modtaboff: u32,
+    pub nmodtab: u32,
+    pub extrefsymoff: u32,
+    pub nextrefsyms: u32,
+    pub indirectsymoff: u32,
+    pub nindirectsyms: u32,
+    pub extreloff: u32,
+    pub nextrel: u32,
+    pub locreloff: u32,
+    pub nlocrel: u32,
+}
+
+/// `LC_DYLD_INFO` or `LC_DYLD_INFO_ONLY`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DyldInfo {
+    pub rebase_off: u32,
+    pub rebase_size: u32,
+    pub bind_off: u32,
+    pub bind_size: u32,
+    pub weak_bind_off: u32,
+    pub weak_bind_size: u32,
+    pub lazy_bind_off: u32,
+    pub lazy_bind_size: u32,
+    pub export_off: u32,
+    pub export_size: u32,
+}
+
+/// `LC_BUILD_VERSION`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildVersion {
+    pub platform: u32,
+    pub minos: u32, // X.Y.Z packed as xxxx.yy.zz
+    pub sdk: u32,   // X.Y.Z packed as xxxx.yy.zz
+    pub tools: Vec<(u32, u32)>, // The tool and its version
+}
+
+/// What a load command holds, by its `cmd`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Segment(Segment),
+    SymTab(SymTab),
+    DySymTab(DySymTab),
+    Uuid(Uuid),
+    SourceVersion(SrcVer),
+    /// `LC_MAIN`, the entry point as an offset in the file and the stack size
+    Main { entryoff: u64, stacksize: u64 },
+    /// `LC_THREAD` or `LC_UNIXTHREAD`, the flavor, count and state of each thread, see `MachO::thread_pc`
+    Thread(Vec<u8>),
+    /// A command that points to data in `__LINKEDIT`, like `LC_FUNCTION_STARTS` or `LC_CODE_SIGNATURE`
+    LinkeditData { dataoff: u32, datasize: u32 },
+    DyldInfo(DyldInfo),
+    BuildVersion(BuildVersion),
+    /// Any other command, as the bytes after cmdsize
+    Other(Vec<u8>),
+}
+
+/// A load command, `padding` is what is after the fields in its cmdsize
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadCommand {
+    pub cmd: u32,
+    pub command: Command,
+    pub padding: Vec<u8>,
+}
+
+/// The header and load commands of a Mach-O
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachO {
+    pub header: MachHeader,
+    pub commands: Vec<LoadCommand>,
+}
+
+const fn bad(reason: &'static str) -> SepSplitError {
+    SepSplitError::BadMachO(reason)
+}
+
+//reads little endian fields one after another, without going past the end (which is a bad Mach-O, not a truncated firmware)
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], SepSplitError> {
+        let mut out = [0; N];
+        out.copy_from_slice(self.buf.get(self.pos..).and_then(|rest| rest.get(..N)).ok_or(bad("Mach-O is truncated"))?);
+        self.pos += N;
+        Ok(out)
+    }
+
+    fn u32(&mut self) -> Result<u32, SepSplitError> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, SepSplitError> {
+        self.bytes().map(i32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, SepSplitError> {
+        self.bytes().map(u64::from_le_bytes)
+    }
+
+    //a field that is 64-bit in a 64-bit Mach-O
+    fn word(&mut self, is64: bool) -> Result<u64, SepSplitError> {
+        if is64 { self.u64() } else { self.u32().map(u64::from) }
+    }
+
+    fn rest(&mut self) -> Vec<u8> {
+        let rest = self.buf.get(self.pos..).unwrap_or_default().to_vec();
+        self.pos = self.buf.len();
+        rest
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_word(out: &mut Vec<u8>, value: u64, is64: bool) -> Result<(), SepSplitError> {
+    if is64 {
+        out.extend_from_slice(&value.to_le_bytes());
+    } else {
+        put_u32(out, u32::try_from(value).map_err(|_| bad("a 32-bit Mach-O field is over 4 GB"))?);
+    }
+    Ok(())
+}
+
+impl Section {
+    fn read(r: &mut Reader, is64: bool) -> Result<Self, SepSplitError> {
+        Ok(Self {
+            sectname: r.bytes()?,
+            segname: r.bytes()?,
+            addr: r.word(is64)?,
+            size: r.word(is64)?,
+            offset: r.u32()?,
+            align: r.u32()?,
+            reloff: r.u32()?,
+            nreloc: r.u32()?,
+            flags: r.u32()?,
+            reserved1: r.u32()?,
+            reserved2: r.u32()?,
+            reserved3: if is64 { r.u32()? } else { 0 },
+        })
+    }
+
+    fn write(&self, out: &mut Vec<u8>, is64: bool) -> Result<(), SepSplitError> {
+        out.extend_from_slice(&self.sectname);
+        out.extend_from_slice(&self.segname);
+        put_word(out, self.addr, is64)?;
+        put_word(out, self.size, is64)?;
+        for field in [self.offset, self.align, self.reloff, self.nreloc, self.flags, self.reserved1, self.reserved2] {
+            put_u32(out, field);
+        }
+        if is64 {
+            put_u32(out, self.reserved3);
+        }
+        Ok(())
+    }
+
+    /// The name of the section, like `__text`
+    #[must_use]
+    pub fn name(&self) -> &str {
+        fixed_str(&self.sectname)
+    }
+}
+
+//a name padded with zeros, empty if it is not UTF-8
+fn fixed_str(name: &[u8; 16]) -> &str {
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    std::str::from_utf8(&name[..len]).unwrap_or_default()
+}
+
+impl Segment {
+    fn read(r: &mut Reader, is64: bool) -> Result<Self, SepSplitError> {
+        let segname = r.bytes()?;
+        let (vmaddr, vmsize, fileoff, filesize) = (r.word(is64)?, r.word(is64)?, r.word(is64)?, r.word(is64)?);
+        let (maxprot, initprot, nsects, flags) = (r.i32()?, r.i32()?, r.u32()?, r.u32()?);
+        let sections = (0..nsects).map(|_| Section::read(r, is64)).collect::<Result<_, _>>()?;
+        Ok(Self { segname, vmaddr, vmsize, fileoff, filesize, maxprot, initprot, flags, sections })
+    }
+
+    fn write(&self, out: &mut Vec<u8>, is64: bool) -> Result<(), SepSplitError> {
+        out.extend_from_slice(&self.segname);
+        for field in [self.vmaddr, self.vmsize, self.fileoff, self.filesize] {
+            put_word(out, field, is64)?;
+        }
+        out.extend_from_slice(&self.maxprot.to_le_bytes());
+        out.extend_from_slice(&self.initprot.to_le_bytes());
+        put_u32(out, u32::try_from(self.sections.len()).map_err(|_| bad("too many sections"))?);
+        put_u32(out, self.flags);
+        for section in &self.sections {
+            section.write(out, is64)?;
+        }
+        Ok(())
+    }
+
+    /// The name of the segment, like `__TEXT`
+    #[must_use]
+    pub fn name(&self) -> &str {
+        fixed_str(&self.segname)
+    }
+}
+
+impl DySymTab {
+    const fn fields(&self) -> [u32; 18] {
+        [
+            self.ilocalsym, self.nlocalsym, self.iextdefsym, self.nextdefsym, self.iundefsym, self.nundefsym,
+            self.tocoff, self.ntoc, self.modtaboff, self.nmodtab, self.extrefsymoff, self.nextrefsyms,
+            self.indirectsymoff, self.nindirectsyms, self.extreloff, self.nextrel, self.locreloff, self.nlocrel,
+        ]
+    }
+
+    const fn from_fields(f: [u32; 18]) -> Self {
+        Self {
+            ilocalsym: f[0], nlocalsym: f[1], iextdefsym: f[2], nextdefsym: f[3], iundefsym: f[4], nundefsym: f[5],
+            tocoff: f[6], ntoc: f[7], modtaboff: f[8], nmodtab: f[9], extrefsymoff: f[10], nextrefsyms: f[11],
+            indirectsymoff: f[12], nindirectsyms: f[13], extreloff: f[14], nextrel: f[15], locreloff: f[16], nlocrel: f[17],
+        }
+    }
+}
+
+impl DyldInfo {
+    const fn fields(&self) -> [u32; 10] {
+        [
+            self.rebase_off, self.rebase_size, self.bind_off, self.bind_size, self.weak_bind_off,
+            self.weak_bind_size, self.lazy_bind_off, self.lazy_bind_size, self.export_off, self.export_size,
+        ]
+    }
+
+    const fn from_fields(f: [u32; 10]) -> Self {
+        Self {
+            rebase_off: f[0], rebase_size: f[1], bind_off: f[2], bind_size: f[3], weak_bind_off: f[4],
+            weak_bind_size: f[5], lazy_bind_off: f[6], lazy_bind_size: f[7], export_off: f[8], export_size: f[9],
+        }
+    }
+}
+
+fn read_u32s<const N: usize>(r: &mut Reader) -> Result<[u32; N], SepSplitError> {
+    let mut fields = [0; N];
+    for field in &mut fields {
+        *field = r.u32()?;
+    }
+    Ok(fields)
+}
+
+impl LoadCommand {
+    //buf is the whole command, cmdsize bytes long
+    fn parse(buf: &[u8], is64: bool) -> Result<Self, SepSplitError> {
+        let mut r = Reader { buf, pos: CMD_HEADER_SIZE };
+        let cmd = u32::from_le_bytes(buf[..4].try_into().map_err(|_| bad("load command is truncated"))?);
+        let command = match cmd {
+            LC_SEGMENT if !is64 => Command::Segment(Segment::read(&mut r, false)?),
+            LC_SEGMENT_64 if is64 => Command::Segment(Segment::read(&mut r, true)?),
+            LC_SYMTAB => {
+                let [symoff, nsyms, stroff, strsize] = read_u32s(&mut r)?;
+                Command::SymTab(SymTab { symoff, nsyms, stroff, strsize })
+            },
+            LC_DYSYMTAB => Command::DySymTab(DySymTab::from_fields(read_u32s(&mut r)?)),
+            LC_UUID => Command::Uuid(Uuid::from_bytes(r.bytes()?)),
+            LC_SOURCE_VERSION => Command::SourceVersion(SrcVer::from_bytes(r.bytes()?)),
+            LC_MAIN => Command::Main { entryoff: r.u64()?, stacksize: r.u64()? },
+            LC_THREAD | LC_UNIXTHREAD => Command::Thread(r.rest()),
+            LC_CODE_SIGNATURE | LC_SEGMENT_SPLIT_INFO | LC_FUNCTION_STARTS | LC_DATA_IN_CODE
+            | LC_DYLD_EXPORTS_TRIE | LC_DYLD_CHAINED_FIXUPS => Command::LinkeditData { dataoff: r.u32()?, datasize: r.u32()? },
+            LC_DYLD_INFO | LC_DYLD_INFO_ONLY => Command::DyldInfo(DyldInfo::from_fields(read_u32s(&mut r)?)),
+            LC_BUILD_VERSION => {
+                let [platform, minos, sdk, ntools] = read_u32s(&mut r)?;
+                let tools = (0..ntools).map(|_| Ok((r.u32()?, r.u32()?))).collect::<Result<_, SepSplitError>>()?;
+                Command::BuildVersion(BuildVersion { platform, minos, sdk, tools })
+            },
+            _ => Command::Other(r.rest()),
+        };
+        Ok(Self { cmd, command, padding: r.rest() })
+    }
+
+    fn write(&self, out: &mut Vec<u8>, is64: bool) -> Result<(), SepSplitError> {
+        let mut body = Vec::new();
+        match &self.command {
+            Command::Segment(segment) => segment.write(&mut body, is64)?,
+            Command::SymTab(symtab) => {
+                for field in [symtab.symoff, symtab.nsyms, symtab.stroff, symtab.strsize] {
+                    put_u32(&mut body, field);
+                }
+            },
+            Command::DySymTab(dysymtab) => dysymtab.fields().into_iter().for_each(|field| put_u32(&mut body, field)),
+            Command::Uuid(uuid) => body.extend_from_slice(uuid.as_bytes()),
+            Command::SourceVersion(srcver) => body.extend_from_slice(&srcver.into_bytes()),
+            Command::Main { entryoff, stacksize } => {
+                body.extend_from_slice(&entryoff.to_le_bytes());
+                body.extend_from_slice(&stacksize.to_le_bytes());
+            },
+            Command::LinkeditData { dataoff, datasize } => {
+                put_u32(&mut body, *dataoff);
+                put_u32(&mut body, *datasize);
+            },
+            Command::DyldInfo(info) => info.fields().into_iter().for_each(|field| put_u32(&mut body, field)),
+            Command::BuildVersion(build) => {
+                for field in [build.platform, build.minos, build.sdk, u32::try_from(build.tools.len()).map_err(|_| bad("too many tools"))?] {
+                    put_u32(&mut body, field);
+                }
+                for &(tool, version) in &build.tools {
+                    put_u32(&mut body, tool);
+                    put_u32(&mut body, version);
+                }
+            },
+            Command::Thread(data) | Command::Other(data) => body.extend_from_slice(data),
+        }
+        let cmdsize = u32::try_from(CMD_HEADER_SIZE + body.len() + self.padding.len()).map_err(|_| bad("load command is too large"))?;
+        put_u32(out, self.cmd);
+        put_u32(out, cmdsize);
+        out.extend_from_slice(&body);
+        out.extend_from_slice(&self.padding);
+        Ok(())
+    }
+}
+
+impl MachO {
+    /// Checks if the buffer starts with a 32 or 64-bit Mach-O magic
+    #[must_use]
+    pub fn is_macho(buf: &[u8]) -> bool {
+        buf.get(..4).is_some_and(|magic| matches!(u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]), MH_MAGIC | MH_MAGIC_64))
+    }
+
+    /// Parses the header and the load commands at the start of `buf`
+    /// # Errors
+    /// * `buf` is not a Mach-O
+    /// * The load commands are truncated, or do not add up to `sizeofcmds`
+    pub fn parse(buf: &[u8]) -> Result<Self, SepSplitError> {
+        if !Self::is_macho(buf) {
+            return Err(bad("Not macho"))
+        }
+        let mut r = Reader { buf, pos: 0 };
+        let (magic, cputype, cpusubtype, filetype) = (r.u32()?, r.i32()?, r.i32()?, r.u32()?);
+        let (ncmds, sizeofcmds, flags) = (r.u32()?, r.u32()? as usize, r.u32()?);
+        let is64 = magic == MH_MAGIC_64;
+        let reserved = if is64 { r.u32()? } else { 0 };
+
+        let end = r.pos.saturating_add(sizeofcmds);
+        let mut commands = Vec::new();
+        let mut pos = r.pos;
+        for _ in 0..ncmds {
+            let cmdsize = Reader { buf, pos: pos + 4 }.u32()? as usize;
+            if cmdsize < CMD_HEADER_SIZE {
+                return Err(bad("load command is smaller than its header"))
+            }
+            if pos + cmdsize > end {
+                return Err(bad("load commands are larger than sizeofcmds"))
+            }
+            commands.push(LoadCommand::parse(buf.get(pos..pos + cmdsize).ok_or(bad("load command is truncated"))?, is64)?);
+            pos += cmdsize;
+        }
+        if pos != end {
+            return Err(bad("load commands are smaller than sizeofcmds"))
+        }
+        Ok(Self { header: MachHeader { magic, cputype, cpusubtype, filetype, flags, reserved }, commands })
+    }
+
+    /// Writes the header and the load commands, with `ncmds` and `sizeofcmds` from the load commands
+    /// # Errors
+    /// * A field does not fit in a 32-bit Mach-O, or there are too many load commands
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SepSplitError> {
+        let is64 = self.is64();
+        let mut cmds = Vec::new();
+        for command in &self.commands {
+            command.write(&mut cmds, is64)?;
+        }
+        let mut out = Vec::with_capacity(self.header_size() + cmds.len());
+        out.extend_from_slice(&self.header.magic.to_le_bytes());
+        out.extend_from_slice(&self.header.cputype.to_le_bytes());
+        out.extend_from_slice(&self.header.cpusubtype.to_le_bytes());
+        put_u32(&mut out, self.header.filetype);
+        put_u32(&mut out, u32::try_from(self.commands.len()).map_err(|_| bad("too many load commands"))?);
+        put_u32(&mut out, u32::try_from(cmds.len()).map_err(|_| bad("load commands are too large"))?);
+        put_u32(&mut out, self.header.flags);
+        if is64 {
+            put_u32(&mut out, self.header.reserved);
+        }
+        out.extend_from_slice(&cmds);
+        Ok(out)
+    }
+
+    #[must_use]
+    pub const fn is64(&self) -> bool {
+        self.header.magic == MH_MAGIC_64
+    }
+
+    /// The size of the header, 28 bytes or 32 for 64-bit
+    #[must_use]
+    pub const fn header_size(&self) -> usize {
+        HEADER_SIZE + if self.is64() { 4 } else { 0 }
+    }
+
+    /// The size of the header and the load commands, from the header at the start of `buf`
+    #[must_use]
+    pub fn load_commands_size(buf: &[u8]) -> Option<usize> {
+        let sizeofcmds = u32::from_le_bytes(buf.get(20..24)?.try_into().ok()?) as usize;
+        Self::is_macho(buf).then(|| HEADER_SIZE + if buf[0] & 1 == 1 { 4 } else { 0 } + sizeofcmds)
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = &Segment> {
+        self.commands.iter().filter_map(|lc| match &lc.command {
+            Command::Segment(segment) => Some(segment),
+            _ => None,
+        })
+    }
+
+    pub fn segments_mut(&mut self) -> impl Iterator<Item = &mut Segment> {
+        self.commands.iter_mut().filter_map(|lc| match &mut lc.command {
+            Command::Segment(segment) => Some(segment),
+            _ => None,
+        })
+    }
+
+    /// The first segment with this name
+    #[must_use]
+    pub fn segment(&self, name: &str) -> Option<&Segment> {
+        self.segments().find(|segment| segment.name() == name)
+    }
+
+    /// Where the last segment ends in the file
+    #[must_use]
+    pub fn file_end(&self) -> u64 {
+        self.segments().map(|segment| segment.fileoff.saturating_add(segment.filesize)).max().unwrap_or(0)
+    }
+
+    /// Where the last segment ends in the file, for a Mach-O that `parse` rejects
+    ///
+    /// Only the segments are read, until the first load command that is truncated.
+    #[must_use]
+    pub fn scan_file_end(buf: &[u8]) -> u64 {
+        let is64 = buf.get(..4) == Some(&MH_MAGIC_64.to_le_bytes()[..]);
+        let Ok(ncmds) = (Reader { buf, pos: 16 }).u32() else { return 0 };
+        let mut pos = HEADER_SIZE + if is64 { 4 } else { 0 };
+        let mut end = 0;
+        for _ in 0..ncmds {
+            let mut r = Reader { buf, pos };
+            let (Ok(cmd), Ok(cmdsize)) = (r.u32(), r.u32()) else { break };
+            if matches!((cmd, is64), (LC_SEGMENT, false) | (LC_SEGMENT_64, true)) {
+                let Ok(segment) = Segment::read(&mut r, is64) else { break };
+                end = end.max(segment.fileoff.saturating_add(segment.filesize));
+            }
+            if (cmdsize as usize) < CMD_HEADER_SIZE {
+                break
+            }
+            pos = pos.saturating_add(cmdsize as usize);
+        }
+        end
+    }
+
+    /// The UUID of `LC_UUID`
+    #[must_use]
+    pub fn uuid(&self) -> Option<Uuid> {
+        self.commands.iter().find_map(|lc| match lc.command {
+            Command::Uuid(uuid) => Some(uuid),
+            _ => None,
+        })
+    }
+
+    /// The source version of `LC_SOURCE_VERSION`
+    #[must_use]
+    pub fn source_version(&self) -> Option<SrcVer> {
+        self.commands.iter().find_map(|lc| match lc.command {
+            Command::SourceVersion(srcver) => Some(srcver),
+            _ => None,
+        })
+    }
+
+    /// The pc of the first ARM thread state of `LC_THREAD`/`LC_UNIXTHREAD`, the entry point of binaries without `LC_MAIN`
+    #[must_use]
+    pub fn thread_pc(&self) -> Option<u64> {
+        self.commands.iter().find_map(|lc| match &lc.command {
+            Command::Thread(data) => thread_pc_field(data).map(|(off, len)| {
+                data[off..off + len].iter().rev().fold(0, |pc, &b| pc << 8 | u64::from(b))
+            }),
+            _ => None,
+        })
+    }
+
+    /// The lowest vmaddr of the segments, without `__PAGEZERO`
+    #[must_use]
+    pub fn base(&self) -> Option<u64> {
+        self.segments().filter(|segment| segment.name() != SEG_PAGEZERO).map(|segment| segment.vmaddr).min()
+    }
+
+    /// Moves the segments (but `__PAGEZERO`), their sections and the thread pc by `slide`, wrapping around
+    pub fn rebase(&mut self, slide: u64) {
+        for segment in self.segments_mut().filter(|segment| segment.name() != SEG_PAGEZERO) {
+            segment.vmaddr = segment.vmaddr.wrapping_add(slide);
+            for section in &mut segment.sections {
+                section.addr = section.addr.wrapping_add(slide);
+            }
+        }
+        for lc in &mut self.commands {
+            if let Command::Thread(data) = &mut lc.command {
+                if let Some((off, len)) = thread_pc_field(data) {
+                    let pc = data[off..off + len].iter().rev().fold(0, |pc: u64, &b| pc << 8 | u64::from(b)).wrapping_add(slide);
+                    data[off..off + len].copy_from_slice(&pc.to_le_bytes()[..len]);
+                }
+            }
+        }
+    }
+}
+
+//where the pc is in the first ARM thread state of LC_THREAD/LC_UNIXTHREAD, and its size
+fn thread_pc_field(data: &[u8]) -> Option<(usize, usize)> {
+    //after the flavor and count, ARM_THREAD_STATE has r0-r12, sp, lr, pc and ARM_THREAD_STATE64 has x0-x28, fp, lr, sp, pc
+    let (off, len) = match data.get(..4)? {
+        [1, 0, 0, 0] => (8 + 15 * 4, 4),
+        [6, 0, 0, 0] => (8 + 32 * 8, 8),
+        _ => return None,
+    };
+    (data.len() >= off + len).then_some((off, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{
+        Command, MachO, Section, Segment, SymTab, LC_BUILD_VERSION, LC_DYLD_INFO_ONLY, LC_DYSYMTAB, LC_FUNCTION_STARTS, LC_MAIN,
+        LC_SEGMENT, LC_SEGMENT_64, LC_SOURCE_VERSION, LC_SYMTAB, LC_THREAD, LC_UNIXTHREAD, LC_UUID, MH_MAGIC, MH_MAGIC_64,
+        SEG_LINKEDIT, SEG_PAGEZERO,
+    };
+    use crate::SepSplitError;
+
+    fn name(s: &str) -> [u8; 16] {
+        let mut out = [0; 16];
+        out[..s.len()].copy_from_slice(s.as_bytes());
+        out
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn words(values: &[u64], is64: bool) -> Vec<u8> {
+        values.iter().flat_map(|&v| if is64 { v.to_le_bytes().to_vec() } else { (v as u32).to_le_bytes().to_vec() }).collect()
+    }
+
+    fn cmd(cmd: u32, body: &[u8]) -> Vec<u8> {
+        [u32s(&[cmd, (body.len() + 8) as u32]), body.to_vec()].concat()
+    }
+
+    fn segment(segname: &str, vmaddr: u64, sects: &[(&str, u64)], is64: bool) -> Vec<u8> {
+        let mut body = [name(segname).to_vec(), words(&[vmaddr, 0x4000, 0, 0x4000], is64), u32s(&[5, 5, sects.len() as u32, 0])].concat();
+        for &(sectname, addr) in sects {
+            body.extend([name(sectname).to_vec(), name(segname).to_vec(), words(&[addr, 0x100], is64), u32s(&[0x100, 4, 0, 0, 0x8000_0400, 0, 0])].concat());
+            if is64 {
+                body.extend(u32s(&[0]));
+            }
+        }
+        cmd(if is64 { LC_SEGMENT_64 } else { LC_SEGMENT }, &body)
+    }
+
+    fn macho(cmds: &[Vec<u8>], is64: bool) -> Vec<u8> {
+        let sizeofcmds = cmds.iter().map(Vec::len).sum::<usize>() as u32;
+        let magic = if is64 { MH_MAGIC_64 } else { MH_MAGIC };
+        let mut out = u32s(&[magic, 0x0100_000c, 2, 2, cmds.len() as u32, sizeofcmds, 0x0020_0085]);
+        if is64 {
+            out.extend(u32s(&[0]));
+        }
+        [out, cmds.concat()].concat()
+    }
+
+    const UUID: [u8; 16] = [0x5e, 0x9d, 0x1b, 0x33, 0xc2, 0x27, 0x3b, 0x71, 0x8a, 0x8d, 0x6b, 0x5c, 0x2a, 0x7e, 0x90, 0x01];
+
+    //a 64-bit Mach-O with every modelled load command, an unknown one and one with padding
+    fn macho64() -> Vec<u8> {
+        let mut thread = u32s(&[6, 68]);
+        thread.extend((0..33u64).flat_map(|i| (if i == 32 { 0xffff_fff0_0000_4100 } else { i }).to_le_bytes()));
+        thread.extend(u32s(&[0x6000_0000, 0]));
+        macho(&[
+            segment(SEG_PAGEZERO, 0, &[], true),
+            segment("__TEXT", 0xffff_fff0_0000_4000, &[("__text", 0xffff_fff0_0000_4100), ("__const", 0xffff_fff0_0000_4200)], true),
+            segment(SEG_LINKEDIT, 0xffff_fff0_0000_8000, &[], true),
+            cmd(LC_SYMTAB, &u32s(&[0x8000, 10, 0x8100, 0x40])),
+            cmd(LC_DYSYMTAB, &u32s(&(1..=18).collect::<Vec<_>>())),
+            cmd(LC_UUID, &UUID),
+            cmd(LC_SOURCE_VERSION, &((2044u64 << 40) | (1 << 30) | (2 << 20)).to_le_bytes()),
+            cmd(LC_UNIXTHREAD, &thread),
+            cmd(LC_FUNCTION_STARTS, &u32s(&[0x8140, 8])),
+            cmd(LC_DYLD_INFO_ONLY, &u32s(&[0x8000, 0x10, 0, 0, 0, 0, 0, 0, 0x8010, 0x20])),
+            cmd(LC_BUILD_VERSION, &u32s(&[1, 0x0011_0000, 0x0011_0400, 2, 3, 0x0300_0100, 4, 0x0400_0200])),
+            cmd(LC_MAIN, &[0x00, 0x41, 0, 0, 0, 0, 0, 0, 0, 0x40, 0, 0, 0, 0, 0, 0, 0xaa, 0xbb, 0xcc, 0xdd]),
+            cmd(0x2f, b"SEPOS\0\0\0"),
+        ], true)
+    }
+
+    //a 32-bit Mach-O like the SEP apps before 64-bit SEP
+    fn macho32() -> Vec<u8> {
+        let mut thread = u32s(&[1, 17]);
+        thread.extend(u32s(&(0..17).map(|i| if i == 15 { 0x1100 } else { i }).collect::<Vec<_>>()));
+        macho(&[
+            segment("__TEXT", 0x1000, &[("__text", 0x1100)], false),
+            segment("__DATA", 0x5000, &[("__data", 0x5000), ("__bss", 0x5100)], false),
+            cmd(LC_UUID, &UUID),
+            cmd(LC_THREAD, &thread),
+        ], false)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for (bytes, is64) in [(macho64(), true), (macho32(), false)] {
+            let macho = MachO::parse(&bytes).unwrap();
+            assert_eq!(macho.is64(), is64);
+            assert_eq!(MachO::load_commands_size(&bytes), Some(bytes.len()));
+            assert!(!macho.commands.iter().any(|lc| matches!(lc.command, Command::Other(_)) && lc.cmd != 0x2f));
+            assert_eq!(macho.to_bytes().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let macho = MachO::parse(&macho64()).unwrap();
+        assert_eq!(macho.header_size(), 32);
+        assert_eq!(macho.segments().map(Segment::name).collect::<Vec<_>>(), [SEG_PAGEZERO, "__TEXT", SEG_LINKEDIT]);
+        let text = macho.segment("__TEXT").unwrap();
+        assert_eq!(text.sections.iter().map(Section::name).collect::<Vec<_>>(), ["__text", "__const"]);
+        assert_eq!(text.sections[1].addr, 0xffff_fff0_0000_4200);
+        assert_eq!(macho.base(), Some(0xffff_fff0_0000_4000));
+        assert_eq!(macho.file_end(), 0x4000);
+        assert_eq!(macho.uuid(), Some(Uuid::from_bytes(UUID)));
+        assert_eq!(macho.source_version().unwrap().to_string(), "2044.1.2.0.0");
+        assert_eq!(macho.thread_pc(), Some(0xffff_fff0_0000_4100));
+        let command = |cmd| &macho.commands.iter().find(|lc| lc.cmd == cmd).unwrap().command;
+        assert_eq!(*command(LC_SYMTAB), Command::SymTab(SymTab { symoff: 0x8000, nsyms: 10, stroff: 0x8100, strsize: 0x40 }));
+        assert!(matches!(command(LC_DYSYMTAB), Command::DySymTab(d) if d.ilocalsym == 1 && d.nlocrel == 18));
+        assert_eq!(*command(LC_FUNCTION_STARTS), Command::LinkeditData { dataoff: 0x8140, datasize: 8 });
+        assert!(matches!(command(LC_DYLD_INFO_ONLY), Command::DyldInfo(i) if i.rebase_size == 0x10 && i.export_off == 0x8010));
+        assert!(matches!(command(LC_BUILD_VERSION), Command::BuildVersion(b) if b.tools == [(3, 0x0300_0100), (4, 0x0400_0200)]));
+        assert_eq!(*command(0x2f), Command::Other(b"SEPOS\0\0\0".to_vec()));
+        let main = macho.commands.iter().find(|lc| lc.cmd == LC_MAIN).unwrap();
+        assert_eq!(main.command, Command::Main { entryoff: 0x4100, stacksize: 0x4000 });
+        assert_eq!(main.padding, [0xaa, 0xbb, 0xcc, 0xdd]);
+
+        let macho = MachO::parse(&macho32()).unwrap();
+        assert_eq!(macho.header_size(), 28);
+        assert_eq!(macho.base(), Some(0x1000));
+        assert_eq!(macho.thread_pc(), Some(0x1100));
+    }
+
+    #[test]
+    fn test_rebase() {
+        let mut macho = MachO::parse(&macho64()).unwrap();
+        macho.rebase(0x10_0000);
+        assert_eq!(macho.segment(SEG_PAGEZERO).unwrap().vmaddr, 0);
+        assert_eq!(macho.base(), Some(0xffff_fff0_0010_4000));
+        assert_eq!(macho.segment("__TEXT").unwrap().sections[0].addr, 0xffff_fff0_0010_4100);
+        assert_eq!(macho.thread_pc(), Some(0xffff_fff0_0010_4100));
+        //the addresses wrap around
+        macho.rebase(0u64.wrapping_sub(0x10_0000));
+        assert_eq!(macho.to_bytes().unwrap(), macho64());
+
+        let mut macho = MachO::parse(&macho32()).unwrap();
+        macho.rebase(0x4000);
+        assert_eq!(macho.thread_pc(), Some(0x5100));
+        assert_eq!(macho.segment("__DATA").unwrap().sections[1].addr, 0x9100);
+        //a 32-bit Mach-O can not be moved past 4 GB
+        macho.rebase(0xffff_ffff);
+        assert!(matches!(macho.to_bytes(), Err(SepSplitError::BadMachO("a 32-bit Mach-O field is over 4 GB"))));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let bytes = macho64();
+        //every truncation is an error, never a panic
+        for len in 0..bytes.len() {
+            assert!(MachO::parse(&bytes[..len]).is_err(), "{len}");
+        }
+        assert!(!MachO::is_macho(b"\xcf\xfa\xed"));
+        assert!(matches!(MachO::parse(b"\xce\xfa\xed\xfe"), Err(SepSplitError::BadMachO("Mach-O is truncated"))));
+        assert!(matches!(MachO::parse(&[0; 32]), Err(SepSplitError::BadMachO("Not macho"))));
+
+        let with_sizeofcmds = |sizeofcmds: u32| {
+            let mut bytes = macho32();
+            bytes[20..24].copy_from_slice(&sizeofcmds.to_le_bytes());
+            bytes.extend([0; 16]);
+            MachO::parse(&bytes)
+        };
+        let sizeofcmds = (macho32().len() - 28) as u32;
+        assert!(with_sizeofcmds(sizeofcmds).is_ok());
+        assert!(matches!(with_sizeofcmds(sizeofcmds - 4), Err(SepSplitError::BadMachO("load commands are larger than sizeofcmds"))));
+        assert!(matches!(with_sizeofcmds(sizeofcmds + 8), Err(SepSplitError::BadMachO("load commands are smaller than sizeofcmds"))));
+
+        let small = macho(&[u32s(&[LC_UUID, 4])], false);
+        assert!(matches!(MachO::parse(&small), Err(SepSplitError::BadMachO("load command is smaller than its header"))));
+        //a command too small for its fields
+        let short_uuid = macho(&[cmd(LC_UUID, &UUID[..8])], false);
+        assert!(matches!(MachO::parse(&short_uuid), Err(SepSplitError::BadMachO("Mach-O is truncated"))));
+        let many_sections = macho(&[cmd(LC_SEGMENT, &[name("__TEXT").to_vec(), u32s(&[0, 0, 0, 0, 0, 0, 0xffff_ffff, 0])].concat())], false);
+        assert!(matches!(MachO::parse(&many_sections), Err(SepSplitError::BadMachO("Mach-O is truncated"))));
+        //every error is a bad Mach-O, which only skips the fixups of that module
+        for len in 0..bytes.len() {
+            assert!(matches!(MachO::parse(&bytes[..len]), Err(SepSplitError::BadMachO(_))), "{len}");
+        }
+    }
+
+    #[test]
+    fn test_scan_file_end() {
+        for bytes in [macho32(), macho64()] {
+            assert_eq!(MachO::scan_file_end(&bytes), MachO::parse(&bytes).unwrap().file_end());
+        }
+        //the segments are still read when a load command after them is bad
+        let text = segment("__TEXT", 0x1000, &[("__text", 0x1100)], false);
+        let end = MachO::parse(&macho(std::slice::from_ref(&text), false)).unwrap().file_end();
+        let bytes = macho(&[text, cmd(LC_UUID, &UUID[..8])], false);
+        assert!(MachO::parse(&bytes).is_err());
+        assert_eq!(MachO::scan_file_end(&bytes), end);
+        assert_eq!(MachO::scan_file_end(&bytes[..20]), 0);
+    }
+}
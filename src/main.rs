@@ -17,33 +17,529 @@
 */
 
 use std::{
-    fs, 
-    path::PathBuf, 
-    env, 
-    process, 
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    env,
+    process,
+};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use sepsplit_rs::{
+    filecreate, firmware_diff, read_firmware, repack_file, sepinfo, sepsplit, split_bytes, split_dir,
+    ArchiveFormat, DecryptKey, ModuleSelector, NameTemplate, OutputFormat, SepFirmware, SepSplitError, SplitOptions,
 };
-use sepsplit_rs::sepsplit;
 
 #[cfg(test)]
 mod tests;
 
-fn main() -> Result<(), std::io::Error> {
-    //why I don't use a crate for parsing arguments? idk, I'm more used to C
-    let argv: Vec<String> = std::env::args().collect();
-    let arglen = argv.len();
+/// sepsplit-rs - tool to split SEPOS firmware into its individual modules, by @plzdonthaxme
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true, arg_required_else_help = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    //`sepsplit-rs <input> [output folder]` is the same as `split`
+    #[command(flatten)]
+    split: SplitArgs,
+}
+
+#[derive(Args)]
+struct SplitArgs {
+    /// The SEP firmware, raw or in an IM4P, or an IPSW or OTA zip
+    #[arg(required = true)]
+    input: Option<String>,
+    /// The folder to write the modules to (the current folder if not set), or the archive with --archive (- for stdout)
+    outdir: Option<PathBuf>,
+    #[command(flatten)]
+    verbosity: Verbosity,
+    #[command(flatten)]
+    format: FormatArg,
+    #[command(flatten)]
+    input_args: InputArgs,
+    #[command(flatten)]
+    names: NameArgs,
+    #[command(flatten)]
+    rebase: RebaseArg,
+    #[command(flatten)]
+    modules: ModuleArgs,
+    #[command(flatten)]
+    clobber: ClobberArgs,
+    #[command(flatten)]
+    archive: ArchiveArg,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Split a SEP firmware into its modules (the default)
+    Split(SplitArgs),
+    /// Print the format, UUIDs, source versions and sizes of a SEP firmware without writing any files
+    Info {
+        /// The SEP firmware, raw or in an IM4P, or an IPSW or OTA zip
+        input: String,
+        #[command(flatten)]
+        verbosity: Verbosity,
+        #[command(flatten)]
+        format: FormatArg,
+        #[command(flatten)]
+        input_args: InputArgs,
+    },
+    /// List the modules of a SEP firmware
+    List {
+        /// The SEP firmware, raw or in an IM4P, or an IPSW or OTA zip
+        input: PathBuf,
+        #[command(flatten)]
+        verbosity: Verbosity,
+        #[command(flatten)]
+        format: FormatArg,
+        #[command(flatten)]
+        input_args: InputArgs,
+        #[command(flatten)]
+        names: NameArgs,
+        #[command(flatten)]
+        rebase: RebaseArg,
+        #[command(flatten)]
+        modules: ModuleArgs,
+    },
+    /// Extract a single module by its name (like `sks`) or file name (like `sepdump03_sks`)
+    Extract {
+        /// The SEP firmware, raw or in an IM4P, or an IPSW or OTA zip
+        input: PathBuf,
+        /// The name of the module
+        name: String,
+        /// The file to write the module to (its file name in the current folder if not set)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        verbosity: Verbosity,
+        #[command(flatten)]
+        input_args: InputArgs,
+        #[command(flatten)]
+        names: NameArgs,
+        #[command(flatten)]
+        rebase: RebaseArg,
+        #[command(flatten)]
+        clobber: ClobberArgs,
+    },
+    /// Compare two SEP firmwares: added and removed apps, UUID, source version and size changes, and SEP HDR changes
+    Diff {
+        /// The old SEP firmware, raw or in an IM4P, or an IPSW or OTA zip
+        old: PathBuf,
+        /// The new SEP firmware, raw or in an IM4P, or an IPSW or OTA zip
+        new: PathBuf,
+        #[command(flatten)]
+        verbosity: Verbosity,
+        #[command(flatten)]
+        format: FormatArg,
+        #[command(flatten)]
+        input_args: InputArgs,
+    },
+    /// Map the regions of the decompressed image by physical address, with the overlaps and the bytes no module covers
+    Regions {
+        /// The SEP firmware, raw or in an IM4P, or an IPSW or OTA zip
+        input: PathBuf,
+        /// Also write the flat decompressed image to this file
+        #[arg(long)]
+        image: Option<PathBuf>,
+        #[command(flatten)]
+        verbosity: Verbosity,
+        #[command(flatten)]
+        format: FormatArg,
+        #[command(flatten)]
+        input_args: InputArgs,
+    },
+    /// Check the CRC32 stored in a SEP firmware
+    Verify {
+        /// The SEP firmware, raw or in an IM4P, or an IPSW or OTA zip
+        input: PathBuf,
+        #[command(flatten)]
+        verbosity: Verbosity,
+        #[command(flatten)]
+        format: FormatArg,
+        #[command(flatten)]
+        input_args: InputArgs,
+    },
+    /// Put modified modules back into a SEP firmware
+    Repack {
+        /// The original SEP firmware, raw or in an IM4P
+        input: PathBuf,
+        /// The folder with the modules, named like they were split
+        moddir: PathBuf,
+        /// The file to write the new firmware to
+        output: PathBuf,
+        #[command(flatten)]
+        verbosity: Verbosity,
+        #[command(flatten)]
+        input_args: InputArgs,
+        #[command(flatten)]
+        names: NameArgs,
+        #[command(flatten)]
+        clobber: ClobberArgs,
+    },
+    /// Split every sepfw.<board>.<build>.bin firmware of a folder in parallel
+    Batch {
+        /// The folder with the firmwares
+        indir: PathBuf,
+        /// The folder to create a <board>.<build> folder (or archive with --archive) in for each firmware
+        outdir: PathBuf,
+        #[command(flatten)]
+        verbosity: Verbosity,
+        #[command(flatten)]
+        format: FormatArg,
+        #[command(flatten)]
+        names: NameArgs,
+        #[command(flatten)]
+        rebase: RebaseArg,
+        #[command(flatten)]
+        modules: ModuleArgs,
+        #[command(flatten)]
+        clobber: ClobberArgs,
+        #[command(flatten)]
+        archive: ArchiveArg,
+    },
+    /// Print the completions for a shell
+    Completions {
+        shell: Shell,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Archive {
+    Tar,
+    Zip,
+}
+
+//each subcommand only takes the groups of flags it uses, which set its SplitOptions
+trait ApplyArgs {
+    fn apply(&self, opts: SplitOptions) -> Result<SplitOptions, SepSplitError>;
+}
+
+fn split_options(args: &[&dyn ApplyArgs]) -> Result<SplitOptions, SepSplitError> {
+    args.iter().try_fold(SplitOptions::default(), |opts, args| args.apply(opts))
+}
+
+#[derive(Args)]
+struct Verbosity {
+    /// Only print errors
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Also list the written files, or also describe the input with -vv
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+impl Verbosity {
+    //0 with --quiet, 1 by default, 2 with -v and 3 with -vv
+    fn level(&self) -> usize {
+        if self.quiet { 0 } else { 1 + usize::from(self.verbose) }
+    }
+}
+
+#[derive(Args)]
+struct FormatArg {
+    /// How the info is printed
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+impl ApplyArgs for FormatArg {
+    fn apply(&self, opts: SplitOptions) -> Result<SplitOptions, SepSplitError> {
+        Ok(opts.with_format(match self.format {
+            Format::Text => OutputFormat::Text,
+            Format::Json => OutputFormat::Json,
+        }))
+    }
+}
+
+#[derive(Args)]
+struct InputArgs {
+    /// Only read the SEP firmware of this board in an IPSW or OTA zip, like n61
+    #[arg(long)]
+    device: Option<String>,
+    /// The IV and key to decrypt an encrypted IM4P with, as hex (IV then key, like img4lib)
+    #[arg(long, conflicts_with_all = ["iv", "key"])]
+    ivkey: Option<String>,
+    /// The IV to decrypt an encrypted IM4P with, as hex
+    #[arg(long, requires = "key")]
+    iv: Option<String>,
+    /// The key to decrypt an encrypted IM4P with, as hex
+    #[arg(long, requires = "iv")]
+    key: Option<String>,
+}
+
+impl ApplyArgs for InputArgs {
+    fn apply(&self, mut opts: SplitOptions) -> Result<SplitOptions, SepSplitError> {
+        let key = match (&self.ivkey, &self.iv, &self.key) {
+            (Some(ivkey), _, _) => Some(DecryptKey::from_hex(ivkey)?),
+            (None, Some(iv), Some(key)) => Some(DecryptKey::from_hex_parts(iv, key)?),
+            _ => None,
+        };
+        if let Some(key) = key {
+            opts = opts.with_key(key);
+        }
+        if let Some(device) = &self.device {
+            opts = opts.with_device(device);
+        }
+        Ok(opts)
+    }
+}
+
+//the subcommands that split modules check the CRC32 and name the modules
+#[derive(Args)]
+struct NameArgs {
+    /// Fail if the CRC32 stored in the firmware does not match
+    #[arg(long)]
+    strict: bool,
+    /// What the output file names start with instead of sepdump
+    #[arg(long)]
+    prefix: Option<String>,
+    /// The template of the output file names, with {prefix}, {index}, {name}, {kind}, {uuid} and {srcver} [default: {prefix}{index}_{name}]
+    #[arg(long)]
+    template: Option<NameTemplate>,
+}
+
+impl ApplyArgs for NameArgs {
+    fn apply(&self, mut opts: SplitOptions) -> Result<SplitOptions, SepSplitError> {
+        opts = opts.with_strict(self.strict);
+        if let Some(prefix) = &self.prefix {
+            opts = opts.with_prefix(prefix);
+        }
+        if let Some(template) = &self.template {
+            opts = opts.with_template(template.clone());
+        }
+        Ok(opts)
+    }
+}
+
+#[derive(Args)]
+struct RebaseArg {
+    /// Move the modules to their virtual address in the SEP app table, the original addresses are in the manifest
+    #[arg(long)]
+    rebase: bool,
+}
+
+impl ApplyArgs for RebaseArg {
+    fn apply(&self, opts: SplitOptions) -> Result<SplitOptions, SepSplitError> {
+        Ok(opts.with_rebase(self.rebase))
+    }
+}
+
+#[derive(Args)]
+struct ModuleArgs {
+    /// Only split the modules with this name (a glob like "sk*"), index or UUID, can be repeated
+    #[arg(short, long = "module", value_name = "SELECTOR")]
+    modules: Vec<ModuleSelector>,
+    /// Also hash the firmware and the modules with SHA-1 in the manifest, not only SHA-256
+    #[arg(long)]
+    sha1: bool,
+}
+
+impl ApplyArgs for ModuleArgs {
+    fn apply(&self, opts: SplitOptions) -> Result<SplitOptions, SepSplitError> {
+        Ok(opts.with_modules(self.modules.clone()).with_sha1(self.sha1))
+    }
+}
+
+#[derive(Args)]
+struct ClobberArgs {
+    /// Overwrite the output files that already exist (the default)
+    #[arg(long, overrides_with = "no_clobber")]
+    overwrite: bool,
+    /// Keep the output files that already exist
+    #[arg(long, overrides_with = "overwrite")]
+    no_clobber: bool,
+}
+
+impl ApplyArgs for ClobberArgs {
+    fn apply(&self, opts: SplitOptions) -> Result<SplitOptions, SepSplitError> {
+        Ok(opts.with_no_clobber(self.no_clobber))
+    }
+}
+
+#[derive(Args)]
+struct ArchiveArg {
+    /// Write the modules and the manifest into an archive instead of a folder
+    #[arg(long, value_enum)]
+    archive: Option<Archive>,
+}
+
+impl ApplyArgs for ArchiveArg {
+    fn apply(&self, opts: SplitOptions) -> Result<SplitOptions, SepSplitError> {
+        Ok(match self.archive {
+            Some(Archive::Tar) => opts.with_archive(ArchiveFormat::Tar),
+            Some(Archive::Zip) => opts.with_archive(ArchiveFormat::Zip),
+            None => opts,
+        })
+    }
+}
+
+//prints the error and exits with its exit code
+fn fail(err: &SepSplitError) -> ! {
+    eprintln!("[!] {err}, exiting.");
+    process::exit(err.exit_code())
+}
+
+//prints what could not be fixed in the firmware, unless quiet
+fn warn(firmware: &SepFirmware, verbose: usize) {
+    if verbose >= 1 {
+        for warning in &firmware.warnings {
+            eprintln!("Warning: {warning}");
+        }
+    }
+}
+
+fn split(args: SplitArgs) -> Result<(), SepSplitError> {
+    let opts = split_options(&[&args.format, &args.input_args, &args.names, &args.rebase, &args.modules, &args.clobber, &args.archive])?;
+    let input = args.input.unwrap_or_default();
+    let outdir = match (args.outdir, opts.archive) {
+        (Some(outdir), _) => outdir,
+        (None, None) => env::current_dir()?,
+        //the archive is named after the input, in the current folder
+        (None, Some(archive)) => {
+            let stem = Path::new(&input).file_stem().map_or_else(|| "sepdump".into(), |s| s.to_string_lossy());
+            PathBuf::from(format!("{stem}.{}", if archive == ArchiveFormat::Tar { "tar" } else { "zip" }))
+        }
+    };
+    sepsplit(&input, &outdir, args.verbosity.level(), &opts)
+}
+
+fn list(input: &Path, verbose: usize, opts: &SplitOptions) -> Result<(), SepSplitError> {
+    let krnl = read_firmware(input, opts)?;
+    let firmware = split_bytes(&krnl, opts)?;
+    warn(&firmware, verbose);
+    if verbose >= 1 {
+        match opts.format {
+            OutputFormat::Text => write!(io::stdout().lock(), "{}", firmware.module_table())?,
+            OutputFormat::Json => writeln!(io::stdout().lock(), "{}", firmware.modules_to_json())?,
+        }
+    }
+    Ok(())
+}
 
-    if arglen < 2 {
-        eprintln!("[!] Not enough arguments\n\
-                   sepsplit-rs - tool to split SEPOS firmware into its individual modules, by @plzdonthaxme\n\
-                   Usage: {prog} <SEPOS.bin> [output folder]", prog=&argv[0]);
-        process::exit(1)
+fn diff(old: &Path, new: &Path, verbose: usize, opts: &SplitOptions) -> Result<(), SepSplitError> {
+    let diff = firmware_diff(&read_firmware(old, opts)?, &read_firmware(new, opts)?, opts)?;
+    if verbose >= 1 {
+        match opts.format {
+            OutputFormat::Text => write!(io::stdout().lock(), "{diff}")?,
+            OutputFormat::Json => writeln!(io::stdout().lock(), "{}", diff.to_json())?,
+        }
     }
+    Ok(())
+}
 
-    let outdir = &if arglen > 2 {
-        PathBuf::from(&argv[2])
-    } else {
-        env::current_dir().unwrap_or_else(|e| panic!("Cannot get current dir: {e}")) //if output dir is specified, use it
+fn extract(input: &Path, name: &str, output: Option<PathBuf>, verbose: usize, opts: &SplitOptions) -> Result<(), SepSplitError> {
+    let krnl = read_firmware(input, opts)?;
+    let firmware = split_bytes(&krnl, opts)?;
+    warn(&firmware, verbose);
+    let module = firmware.module(name).ok_or_else(|| SepSplitError::NoModule(name.to_owned()))?;
+    let output = output.unwrap_or_else(|| PathBuf::from(&module.file_name));
+    let Some(mut file) = filecreate(&output, !opts.no_clobber)? else {
+        if verbose >= 1 {
+            eprintln!("kept existing {}", output.display());
+        }
+        return Ok(())
     };
-    fs::create_dir_all(outdir)?;
-    sepsplit(&argv[1], outdir, 1)
-}
\ No newline at end of file
+    firmware.write_module(module, &mut file)?;
+    file.flush()?;
+    if verbose >= 1 {
+        println!("{} ({:#x} bytes) -> {}", module.name, module.size(), output.display());
+    }
+    Ok(())
+}
+
+fn regions(input: &Path, image: Option<&Path>, verbose: usize, opts: &SplitOptions) -> Result<(), SepSplitError> {
+    let krnl = read_firmware(input, opts)?;
+    let firmware = split_bytes(&krnl, opts)?;
+    warn(&firmware, verbose);
+    if let Some(image) = image {
+        fs::write(image, firmware.image())?;
+    }
+    let map = firmware.region_map();
+    if verbose >= 1 {
+        match opts.format {
+            OutputFormat::Text => write!(io::stdout().lock(), "{map}")?,
+            OutputFormat::Json => writeln!(io::stdout().lock(), "{}", map.to_json())?,
+        }
+    }
+    Ok(())
+}
+
+//always strict, a mismatch exits with its error code
+fn verify(input: &Path, verbose: usize, opts: &SplitOptions) -> Result<(), SepSplitError> {
+    let krnl = read_firmware(input, opts)?;
+    let firmware = split_bytes(&krnl, &opts.clone().with_strict(false))?;
+    warn(&firmware, verbose);
+    if verbose >= 1 {
+        match (firmware.crc, opts.format) {
+            (Some(crc), OutputFormat::Text) => println!("{crc}"),
+            (Some(crc), OutputFormat::Json) => println!("{}", crc.to_json()),
+            (None, OutputFormat::Text) => println!("No CRC32 stored in this firmware"),
+            (None, OutputFormat::Json) => println!("null"),
+        }
+    }
+    match firmware.crc {
+        Some(crc) if !crc.matches() => Err(SepSplitError::CrcMismatch { expected: crc.expected, actual: crc.actual }),
+        _ => Ok(())
+    }
+}
+
+fn batch(indir: &Path, outdir: &Path, verbose: usize, opts: &SplitOptions) -> Result<(), SepSplitError> {
+    //the batch goes on after a failure, but still exits with the error of the first one
+    let entries = split_dir(indir, outdir, verbose, opts)?;
+    if let Some(Err(err)) = entries.iter().map(|e| &e.result).find(|r| r.is_err()) {
+        process::exit(err.exit_code())
+    }
+    Ok(())
+}
+
+fn run(command: Command) -> Result<(), SepSplitError> {
+    match command {
+        Command::Split(args) => split(args),
+        Command::Info { input, verbosity, format, input_args } => {
+            sepinfo(&input, verbosity.level(), &split_options(&[&format, &input_args])?)
+        },
+        Command::List { input, verbosity, format, input_args, names, rebase, modules } => {
+            list(&input, verbosity.level(), &split_options(&[&format, &input_args, &names, &rebase, &modules])?)
+        },
+        Command::Extract { input, name, output, verbosity, input_args, names, rebase, clobber } => {
+            extract(&input, &name, output, verbosity.level(), &split_options(&[&input_args, &names, &rebase, &clobber])?)
+        },
+        Command::Diff { old, new, verbosity, format, input_args } => {
+            diff(&old, &new, verbosity.level(), &split_options(&[&format, &input_args])?)
+        },
+        Command::Regions { input, image, verbosity, format, input_args } => {
+            regions(&input, image.as_deref(), verbosity.level(), &split_options(&[&format, &input_args])?)
+        },
+        Command::Verify { input, verbosity, format, input_args } => {
+            verify(&input, verbosity.level(), &split_options(&[&format, &input_args])?)
+        },
+        Command::Repack { input, moddir, output, verbosity, input_args, names, clobber } => {
+            repack_file(&input, &moddir, &output, verbosity.level(), &split_options(&[&input_args, &names, &clobber])?)
+        },
+        Command::Batch { indir, outdir, verbosity, format, names, rebase, modules, clobber, archive } => {
+            batch(&indir, &outdir, verbosity.level(), &split_options(&[&format, &names, &rebase, &modules, &clobber, &archive])?)
+        },
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "sepsplit-rs", &mut io::stdout());
+            Ok(())
+        }
+    }
+}
+
+fn main() {
+    //usage errors exit with 1, as 2 is for I/O errors
+    let cli = Cli::try_parse().unwrap_or_else(|err| {
+        let _ = err.print();
+        process::exit(i32::from(err.use_stderr()))
+    });
+    if let Err(err) = run(cli.command.unwrap_or(Command::Split(cli.split))) {
+        fail(&err)
+    }
+}
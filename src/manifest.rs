@@ -0,0 +1,142 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//JSON manifest of a split firmware
+
+use serde::{Serialize, Serializer};
+use uuid::Uuid;
+
+use crate::{
+    firmware::{CrcCheck, SepFirmware, SepFormat, SepHeader, SepModule},
+    hash::FirmwareHashes,
+};
+
+//the firmware as in the manifest, without its image and warnings
+#[derive(Serialize)]
+pub struct Manifest<'a> {
+    format: SepFormat,
+    hdr_offset: u64,
+    hdr_uuid: Option<Uuid>,
+    header: Option<SepHeader>,
+    crc32_check: Option<CrcCheck>,
+    hashes: Option<FirmwareHashes>,
+    #[serde(serialize_with = "modules")]
+    modules: &'a [SepModule],
+}
+
+//a module as in the manifest, with its size and what does not match the SEP app table
+#[derive(Serialize)]
+struct ModuleEntry<'a> {
+    #[serde(flatten)]
+    module: &'a SepModule,
+    size: usize,
+    mismatch: Vec<&'static str>,
+}
+
+//the modules as in the manifest, also used by info and diff
+pub fn modules<S: Serializer>(modules: &[SepModule], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(modules.iter().map(|module| ModuleEntry { module, size: module.size(), mismatch: module.mismatches() }))
+}
+
+//pretty prints with 2 spaces of indentation
+pub fn to_json(value: &impl Serialize) -> String {
+    //every key is a string and nothing fails to serialize, so this can not fail
+    serde_json::to_string_pretty(value).expect("JSON serialization failed")
+}
+
+impl SepFirmware<'_> {
+    pub(crate) fn manifest(&self) -> Manifest<'_> {
+        Manifest {
+            format: self.format,
+            hdr_offset: self.hdr_offset,
+            hdr_uuid: self.hdr_uuid,
+            header: self.header,
+            crc32_check: self.crc,
+            hashes: self.hashes,
+            modules: &self.modules,
+        }
+    }
+
+    /// The manifest of the firmware as pretty printed JSON: the detected format,
+    /// the SEP HDR fields (for 64-bit SEP) and every module's info and file name
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        to_json(&self.manifest())
+    }
+
+    /// The modules of the manifest as a pretty printed JSON array
+    #[must_use]
+    pub fn modules_to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Modules<'a>(#[serde(serialize_with = "modules")] &'a [SepModule]);
+        to_json(&Modules(&self.modules))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    use crate::{
+        firmware::{CrcCheck, ModuleBytes, ModuleKind, SepFirmware, SepFormat, SepModule},
+        hash::{Digests, ModuleHashes},
+    };
+
+    #[test]
+    fn test_manifest() {
+        let mut fw = SepFirmware::new(SepFormat::Bits64 { subversion: 4, old: false });
+        fw.hdr_offset = 0x1000;
+        fw.crc = Some(CrcCheck { expected: 1, actual: 2, start: 0x4000, end: 0x8000, offset: 0x1100 });
+        let mut sks = SepModule::raw(ModuleKind::SharedLib, Some(3), "libSEPOS", 0x400, ModuleBytes::new(0x400..0x600));
+        sks.file_name = "sepdump03_libSEPOS".to_owned();
+        sks.uuid = Some(Uuid::from_u128(1));
+        sks.lc_uuid = Some(Uuid::from_u128(2));
+        sks.compact_ver = Some((1, 0xFFFF_FFFF));
+        sks.hashes = Some(ModuleHashes { raw: Digests { sha256: [0xab; 32], sha1: None }, fixed: Digests::of(b"", true) });
+        fw.modules = vec![sks];
+
+        let manifest: Value = serde_json::from_str(&fw.to_json()).unwrap();
+        assert_eq!(manifest["format"], json!({ "generation": "64-bit ver4", "bits": 64, "subversion": 4, "old": false }));
+        assert_eq!(manifest["hdr_offset"], 0x1000);
+        assert_eq!(manifest["hdr_uuid"], Value::Null);
+        assert_eq!(manifest["crc32_check"], json!({ "expected": 1, "actual": 2, "start": 0x4000, "end": 0x8000, "offset": 0x1100, "matches": false }));
+        let module = &manifest["modules"][0];
+        assert_eq!(module["kind"], "shlib");
+        assert_eq!(module["index"], 3);
+        assert_eq!(module["file"], "sepdump03_libSEPOS");
+        assert_eq!(module["size"], 0x200);
+        assert_eq!(module["uuid"], "00000000-0000-0000-0000-000000000001");
+        assert_eq!(module["mismatch"], json!(["uuid"]));
+        assert_eq!(module["compact_ver"], json!({ "start": 1, "end": 0xFFFF_FFFFu32 }));
+        assert_eq!(module["hashes"]["raw"], json!({ "sha256": "ab".repeat(32), "sha1": null }));
+        assert_eq!(module["hashes"]["fixed"]["sha1"], "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert!(module.get("size_fields").is_none() && module.get("bytes").is_none());
+
+        //the modules keep the same fields without the rest of the manifest
+        let modules: Value = serde_json::from_str(&fw.modules_to_json()).unwrap();
+        assert_eq!(&modules[0], module);
+    }
+
+    #[test]
+    fn test_pretty_print() {
+        let fw = SepFirmware::new(SepFormat::Bits32 { old: true });
+        assert!(fw.to_json().starts_with("{\n  \"format\": {\n    \"generation\": \"32-bit (old)\",\n    \"bits\": 32,\n    \"subversion\": null,\n"));
+        assert!(fw.to_json().ends_with("\"modules\": []\n}"));
+    }
+}
@@ -0,0 +1,231 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Output file names, from a template with these placeholders:
+        {prefix}  "sepdump", or the prefix in the options
+        {index}   the index of the module, 2 digits like 03
+        {name}    the name of the module, like sks
+        {kind}    boot, kernel, rootserver, app or shlib
+        {uuid}    the UUID of the module, "unknown" if it has none
+        {srcver}  the source version of the module, "unknown" if it has none
+    The default, "{prefix}{index}_{name}", gives names like sepdump03_sks.
+    The struct dump has no index and is always named "{prefix}-struct.extra".
+    Names that are used twice (apps can share their 12 or 16 byte name) get "_2", "_3", etc. before the extension.
+*/
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+    firmware::{ModuleKind, SepModule},
+    SepSplitError,
+};
+
+const PLACEHOLDERS: [&str; 6] = ["prefix", "index", "name", "kind", "uuid", "srcver"];
+
+/// A template for the names of the module files, see `NameTemplate::new`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate(String);
+
+impl NameTemplate {
+    /// The template of the default names, like `sepdump03_sks`
+    pub const DEFAULT: &'static str = "{prefix}{index}_{name}";
+
+    /// Checks a template for the names of the module files.
+    ///
+    /// The placeholders are `{prefix}` (`sepdump` by default), `{index}` (2 digits), `{name}`, `{kind}`, `{uuid}` and `{srcver}`.
+    /// # Errors
+    /// * The template has an unknown placeholder or an unclosed brace, contains a path separator, or is empty
+    pub fn new(template: &str) -> Result<Self, SepSplitError> {
+        let bad = |reason| Err(SepSplitError::BadTemplate { template: template.to_owned(), reason });
+        if template.is_empty() {
+            return bad("empty template")
+        }
+        if template.contains(['/', '\\']) {
+            return bad("file names can not contain path separators")
+        }
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return bad("unopened brace")
+            }
+            let Some(len) = rest[start..].find('}') else { return bad("unclosed brace") };
+            if !PLACEHOLDERS.contains(&&rest[start + 1..start + len]) {
+                return bad("unknown placeholder")
+            }
+            rest = &rest[start + len + 1..];
+        }
+        Ok(Self(template.to_owned()))
+    }
+
+    //the file name of a module, without handling collisions
+    fn render(&self, module: &SepModule, prefix: &str) -> String {
+        //the prefix can have path separators too
+        if module.kind == ModuleKind::Struct {
+            return format!("{prefix}-struct.extra").replace(['/', '\\'], "_")
+        }
+        let unknown = || String::from("unknown");
+        let mut name = String::new();
+        let mut rest = self.0.as_str();
+        //new checked that every brace is closed
+        while let Some((before, after)) = rest.split_once('{') {
+            let (placeholder, after) = after.split_once('}').unwrap_or((after, ""));
+            name.push_str(before);
+            name.push_str(&match placeholder {
+                "prefix" => prefix.to_owned(),
+                "index" => module.index.map_or_else(unknown, |i| format!("{i:02}")),
+                "name" => module.name.clone(),
+                "kind" => module.kind.as_str().to_owned(),
+                "uuid" => module.uuid.map_or_else(unknown, |uuid| uuid.to_string()),
+                _ => module.srcver.map_or_else(unknown, |srcver| srcver.to_string()),
+            });
+            rest = after;
+        }
+        name.push_str(rest);
+        //the names come from the firmware
+        name.replace(['/', '\\'], "_")
+    }
+
+    //the extension after the last placeholder, which numbers are put before on collisions
+    fn extension(&self) -> &str {
+        let tail = self.0.rfind('}').map_or(self.0.as_str(), |i| &self.0[i + 1..]);
+        tail.rfind('.').map_or("", |i| &tail[i..])
+    }
+
+    //sets the file name of every module, names that are already taken get a number
+    //names are compared ignoring case, as they often are by the file system
+    pub(crate) fn name_modules(&self, modules: &mut [SepModule], prefix: &str) {
+        let ext = self.extension();
+        let mut taken: Vec<String> = Vec::with_capacity(modules.len());
+        for module in modules {
+            let name = self.render(module, prefix);
+            let (stem, ext) = if module.kind != ModuleKind::Struct && name.len() > ext.len() && name.ends_with(ext) {
+                name.split_at(name.len() - ext.len())
+            } else {
+                (name.as_str(), "")
+            };
+            let mut file_name = name.clone();
+            let mut n = 2;
+            while taken.contains(&file_name.to_lowercase()) {
+                file_name = format!("{stem}_{n}{ext}");
+                n += 1;
+            }
+            taken.push(file_name.to_lowercase());
+            module.file_name = file_name;
+        }
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self(Self::DEFAULT.to_owned())
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = SepSplitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl fmt::Display for NameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::NameTemplate;
+    use crate::{
+        firmware::{ModuleBytes, ModuleKind, SepModule},
+        SepSplitError,
+    };
+
+    fn module(kind: ModuleKind, index: Option<usize>, name: &str) -> SepModule {
+        SepModule::raw(kind, index, name, 0, ModuleBytes::new(0..0))
+    }
+
+    fn names(template: &str, modules: &mut [SepModule]) -> Vec<String> {
+        NameTemplate::new(template).unwrap().name_modules(modules, "sepdump");
+        modules.iter().map(|module| module.file_name.clone()).collect()
+    }
+
+    #[test]
+    fn test_new() {
+        for template in [NameTemplate::DEFAULT, "{kind}-{name}.bin", "{uuid}", "{prefix}_{srcver}_{index}", "plain"] {
+            assert_eq!(NameTemplate::new(template).unwrap().to_string(), template);
+        }
+        for (template, reason) in [
+            ("", "empty template"),
+            ("{name}/{index}", "file names can not contain path separators"),
+            ("{name}\\{index}", "file names can not contain path separators"),
+            ("{name", "unclosed brace"),
+            ("name}", "unopened brace"),
+            ("{index}}", "unopened brace"),
+            ("{size}", "unknown placeholder"),
+            ("{}", "unknown placeholder"),
+            ("{{name}}", "unknown placeholder"),
+        ] {
+            assert!(matches!(NameTemplate::new(template), Err(SepSplitError::BadTemplate { reason: r, .. }) if r == reason), "{template}");
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let mut sks = module(ModuleKind::App, Some(3), "sks");
+        sks.uuid = Some(Uuid::from_u128(0x0011_2233_4455_6677_8899_aabb_ccdd_eeff));
+        let mut modules = [
+            module(ModuleKind::Boot, Some(0), "boot"),
+            module(ModuleKind::Struct, None, "struct"),
+            sks,
+            module(ModuleKind::SharedLib, Some(4), "lib/a\\b"),
+        ];
+        assert_eq!(names(NameTemplate::DEFAULT, &mut modules), ["sepdump00_boot", "sepdump-struct.extra", "sepdump03_sks", "sepdump04_lib_a_b"]);
+        assert_eq!(names("{kind}_{uuid}_{srcver}.bin", &mut modules), [
+            "boot_unknown_unknown.bin",
+            "sepdump-struct.extra",
+            "app_00112233-4455-6677-8899-aabbccddeeff_unknown.bin",
+            "shlib_unknown_unknown.bin",
+        ]);
+        //so does a prefix with path separators, in the struct dump too
+        NameTemplate::new(NameTemplate::DEFAULT).unwrap().name_modules(&mut modules[..2], "a/b\\c");
+        assert_eq!([&modules[0].file_name, &modules[1].file_name], ["a_b_c00_boot", "a_b_c-struct.extra"]);
+    }
+
+    #[test]
+    fn test_collisions() {
+        let mut modules = [
+            module(ModuleKind::App, Some(1), "sks"),
+            module(ModuleKind::App, Some(2), "SKS"),
+            module(ModuleKind::App, Some(3), "sks"),
+            module(ModuleKind::App, Some(4), "sks_2"),
+            module(ModuleKind::App, Some(5), "ARTM"),
+        ];
+        //compared ignoring case, the number goes before the extension after the last placeholder
+        assert_eq!(names("{name}.bin", &mut modules), ["sks.bin", "SKS_2.bin", "sks_3.bin", "sks_2_2.bin", "ARTM.bin"]);
+        assert_eq!(names("{name}", &mut modules), ["sks", "SKS_2", "sks_3", "sks_2_2", "ARTM"]);
+        assert_eq!(names("{name}.{kind}", &mut modules), ["sks.app", "SKS.app_2", "sks.app_3", "sks_2.app", "ARTM.app"]);
+        //the default names have the index, so they never collide
+        assert_eq!(names(NameTemplate::DEFAULT, &mut modules), ["sepdump01_sks", "sepdump02_SKS", "sepdump03_sks", "sepdump04_sks_2", "sepdump05_ARTM"]);
+    }
+}
@@ -0,0 +1,463 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Moves the modules to the virtual address the SEP app table gives them (virt), so they can be loaded
+    into a disassembler alongside the kernel. The slide is from the lowest segment (but __PAGEZERO) to virt,
+    it is added to the segments, their sections, the thread pc and the pointers dyld would rebase:
+        LC_DYLD_INFO            the rebase opcodes, as in Apple's loader.h
+        LC_DYLD_CHAINED_FIXUPS  the chain of every page, as in Apple's fixup-chains.h, only for the
+                                pointer formats whose rebase targets are vmaddrs (ARM64E, 64 and 32),
+                                the other formats have offsets from the start of the module, which do not move
+    Their info is in __LINKEDIT, read from where fix_linkedit moved it.
+*/
+
+use serde::Serialize;
+
+use crate::{
+    firmware::{Patch, SepFirmware},
+    macho::{Command, MachO, Segment, SEG_LINKEDIT},
+    utils::slice_size,
+    SepSplitError,
+};
+
+/// A segment that was moved, with its address before and after
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RebasedSegment {
+    pub name: String,
+    pub orig_vmaddr: u64, // The vmaddr the linker set
+    pub vmaddr: u64,      // The vmaddr in the SEP
+}
+
+/// How a module was moved to its virtual address, see `SplitOptions::with_rebase`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Rebase {
+    pub orig_base: u64, // The lowest vmaddr of the segments (but __PAGEZERO) as the linker set it
+    pub base: u64,      // The virtual address of the module in the SEP app table
+    pub segments: Vec<RebasedSegment>,
+    pub pointers: usize, // The number of pointers that were rebased
+}
+
+const fn bad(reason: &'static str) -> SepSplitError {
+    SepSplitError::BadMachO(reason)
+}
+
+const REBASE_TYPE_POINTER: u8         = 1;
+const REBASE_TYPE_TEXT_ABSOLUTE32: u8 = 2;
+
+const CHAINED_PTR_ARM64E: u16         = 1;
+const CHAINED_PTR_64: u16             = 2;
+const CHAINED_PTR_32: u16             = 3;
+const CHAINED_PTR_64_OFFSET: u16      = 6;
+const CHAINED_PTR_ARM64E_USERLAND: u16 = 9;
+const CHAINED_PTR_ARM64E_USERLAND24: u16 = 12;
+const CHAINED_PTR_START_NONE: u16     = 0xFFFF;
+const CHAINED_PTR_START_MULTI: u16    = 0x8000;
+
+fn read(data: &[u8], at: u64, len: usize) -> Result<u64, SepSplitError> {
+    let at = usize::try_from(at).map_err(|_| bad("rebase is outside of the module"))?;
+    Ok(slice_size(data, at, len)?.iter().rev().fold(0, |value, &b| value << 8 | u64::from(b)))
+}
+
+fn write(data: &mut [u8], at: u64, len: usize, value: u64) {
+    //only called after read checked the range
+    let at = at as usize;
+    data[at..at + len].copy_from_slice(&value.to_le_bytes()[..len]);
+}
+
+fn uleb128(info: &[u8], pos: &mut usize) -> Result<u64, SepSplitError> {
+    let (mut value, mut shift) = (0u64, 0);
+    loop {
+        let byte = *info.get(*pos).ok_or(bad("rebase info is truncated"))?;
+        *pos += 1;
+        if shift < 64 {
+            value |= u64::from(byte & 0x7f) << shift;
+        }
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value)
+        }
+    }
+}
+
+//the file offsets and sizes of the pointers the rebase opcodes rebase
+fn rebase_opcodes(info: &[u8], segments: &[&Segment], ptr_size: u64) -> Result<Vec<(u64, usize)>, SepSplitError> {
+    let mut pointers = Vec::new();
+    let (mut kind, mut segment, mut off) = (0, None, 0u64);
+    let mut rebase = |segment: Option<&Segment>, off: u64, kind: u8| -> Result<u64, SepSplitError> {
+        let segment: &Segment = segment.ok_or(bad("rebase before its segment is set"))?;
+        if off.saturating_add(ptr_size) > segment.filesize {
+            return Err(bad("rebase is outside of its segment"))
+        }
+        match kind {
+            REBASE_TYPE_POINTER => pointers.push((segment.fileoff + off, ptr_size as usize)),
+            REBASE_TYPE_TEXT_ABSOLUTE32 => pointers.push((segment.fileoff + off, 4)),
+            _ => () //pc relative, it moves with the code
+        }
+        Ok(off + ptr_size)
+    };
+
+    let mut pos = 0;
+    while let Some(&byte) = info.get(pos) {
+        pos += 1;
+        let imm = byte & 0xf;
+        match byte & 0xf0 {
+            0x00 => break, //REBASE_OPCODE_DONE
+            0x10 => kind = imm, //REBASE_OPCODE_SET_TYPE_IMM
+            0x20 => { //REBASE_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB
+                segment = Some(*segments.get(usize::from(imm)).ok_or(bad("rebase in a segment that does not exist"))?);
+                off = uleb128(info, &mut pos)?;
+            },
+            0x30 => off = off.wrapping_add(uleb128(info, &mut pos)?), //REBASE_OPCODE_ADD_ADDR_ULEB
+            0x40 => off = off.wrapping_add(u64::from(imm) * ptr_size), //REBASE_OPCODE_ADD_ADDR_IMM_SCALED
+            0x50 => for _ in 0..imm { //REBASE_OPCODE_DO_REBASE_IMM_TIMES
+                off = rebase(segment, off, kind)?;
+            },
+            0x60 => for _ in 0..uleb128(info, &mut pos)? { //REBASE_OPCODE_DO_REBASE_ULEB_TIMES
+                off = rebase(segment, off, kind)?;
+            },
+            0x70 => { //REBASE_OPCODE_DO_REBASE_ADD_ADDR_ULEB
+                off = rebase(segment, off, kind)?.wrapping_add(uleb128(info, &mut pos)?);
+            },
+            0x80 => { //REBASE_OPCODE_DO_REBASE_ULEB_TIMES_SKIPPING_ULEB
+                let (count, skip) = (uleb128(info, &mut pos)?, uleb128(info, &mut pos)?);
+                for _ in 0..count {
+                    off = rebase(segment, off, kind)?.wrapping_add(skip);
+                }
+            },
+            _ => return Err(bad("unknown rebase opcode")),
+        }
+    }
+    Ok(pointers)
+}
+
+//a chained pointer format: its stride, size, where next is, and the bits of a rebase target that is a vmaddr
+struct ChainFormat {
+    stride: u64,
+    size: usize,
+    next: (u32, u64),  // shift and mask
+    target_bits: u32,
+    is_rebase: fn(u64) -> bool, // not a bind, and for ARM64E not authenticated (those are offsets)
+}
+
+impl ChainFormat {
+    fn new(format: u16) -> Result<Option<Self>, SepSplitError> {
+        Ok(Some(match format {
+            CHAINED_PTR_ARM64E => Self { stride: 8, size: 8, next: (51, 0x7ff), target_bits: 43, is_rebase: |ptr| ptr >> 62 == 0 },
+            CHAINED_PTR_64 => Self { stride: 4, size: 8, next: (51, 0xfff), target_bits: 36, is_rebase: |ptr| ptr >> 63 == 0 },
+            CHAINED_PTR_32 => Self { stride: 4, size: 4, next: (26, 0x1f), target_bits: 26, is_rebase: |ptr| ptr >> 31 == 0 },
+            CHAINED_PTR_64_OFFSET | CHAINED_PTR_ARM64E_USERLAND | CHAINED_PTR_ARM64E_USERLAND24 => return Ok(None),
+            _ => return Err(bad("unknown chained fixups pointer format")),
+        }))
+    }
+}
+
+//slides the rebase targets of the chained fixups at fixups, returns how many
+fn chained_fixups(data: &mut [u8], fixups: u64, segments: &[&Segment], slide: u64) -> Result<usize, SepSplitError> {
+    let starts = fixups + read(data, fixups + 4, 4)?; //dyld_chained_starts_in_image
+    let seg_count = read(data, starts, 4)?;
+    let mut pointers = 0;
+    for i in 0..seg_count {
+        let seg_info = read(data, starts + 4 + i * 4, 4)?;
+        if seg_info == 0 {
+            continue
+        }
+        let seg_starts = starts + seg_info; //dyld_chained_starts_in_segment
+        let segment = segments.get(i as usize).ok_or(bad("chained fixups in a segment that does not exist"))?;
+        let page_size = read(data, seg_starts + 4, 2)?;
+        let Some(format) = ChainFormat::new(read(data, seg_starts + 6, 2)? as u16)? else { continue };
+        let page_count = read(data, seg_starts + 20, 2)?;
+        for page in 0..page_count {
+            let start = read(data, seg_starts + 22 + page * 2, 2)? as u16;
+            if start == CHAINED_PTR_START_NONE {
+                continue
+            }
+            if start & CHAINED_PTR_START_MULTI != 0 {
+                return Err(bad("chained fixups with more than one chain in a page"))
+            }
+            let mut at = segment.fileoff + page * page_size + u64::from(start);
+            loop {
+                let ptr = read(data, at, format.size)?;
+                if (format.is_rebase)(ptr) {
+                    let mask = (1 << format.target_bits) - 1;
+                    let target = (ptr & mask).wrapping_add(slide) & if format.size == 4 { u64::from(u32::MAX) } else { u64::MAX };
+                    if target > mask {
+                        return Err(bad("a rebased chained pointer does not fit"))
+                    }
+                    write(data, at, format.size, ptr & !mask | target);
+                    pointers += 1;
+                }
+                let next = ptr >> format.next.0 & format.next.1;
+                if next == 0 {
+                    break
+                }
+                at += next * format.stride;
+            }
+        }
+    }
+    Ok(pointers)
+}
+
+//slides the pointers dyld rebases in the fixed module, linkedit_delta is how far fix_linkedit moved __LINKEDIT
+fn rebase_pointers(data: &mut [u8], macho: &MachO, linkedit_delta: u64, slide: u64) -> Result<usize, SepSplitError> {
+    let segments: Vec<_> = macho.segments().collect();
+    let ptr_size = if macho.is64() { 8 } else { 4 };
+    let mut pointers = 0;
+    for lc in &macho.commands {
+        match &lc.command {
+            Command::DyldInfo(info) if info.rebase_size != 0 => {
+                let at = u64::from(info.rebase_off).wrapping_add(linkedit_delta);
+                let opcodes = slice_size(data, at as usize, info.rebase_size as usize)?;
+                for (at, size) in rebase_opcodes(opcodes, &segments, ptr_size)? {
+                    let value = read(data, at, size)?.wrapping_add(slide);
+                    write(data, at, size, value);
+                    pointers += 1;
+                }
+            },
+            Command::LinkeditData { dataoff, .. } if lc.cmd == crate::macho::LC_DYLD_CHAINED_FIXUPS => {
+                pointers += chained_fixups(data, u64::from(*dataoff).wrapping_add(linkedit_delta), &segments, slide)?;
+            },
+            _ => ()
+        }
+    }
+    Ok(pointers)
+}
+
+impl SepFirmware<'_> {
+    //moves every module with a virtual address in the SEP app table to it, see Rebase
+    pub(crate) fn rebase(&mut self) -> Result<(), SepSplitError> {
+        for module in &mut self.modules {
+            if module.virt == 0 {
+                continue
+            }
+            let mut data = module.bytes.to_vec(&self.image);
+            let Ok(mut macho) = MachO::parse(&data) else { continue };
+            let Some(orig_base) = macho.base() else { continue };
+            let slide = module.virt.wrapping_sub(orig_base);
+            if slide == 0 {
+                continue
+            }
+
+            let linkedit_delta = MachO::parse(&self.image[module.bytes.range.clone()]).ok()
+                .and_then(|orig| Some((macho.segment(SEG_LINKEDIT)?.fileoff, orig.segment(SEG_LINKEDIT)?.fileoff)))
+                .map_or(0, |(fixed, orig)| fixed.wrapping_sub(orig));
+            //the pointers are only changed if all of them can be
+            let mut moved = data.clone();
+            let pointers = match rebase_pointers(&mut moved, &macho, linkedit_delta, slide) {
+                Ok(pointers) => {
+                    data = moved;
+                    pointers
+                },
+                Err(err @ (SepSplitError::BadMachO(_) | SepSplitError::Truncated { .. })) => {
+                    self.warnings.push(format!("the pointers of {} could not be rebased: {err}", module.name));
+                    0
+                },
+                Err(err) => return Err(err)
+            };
+
+            let orig = macho.clone();
+            macho.rebase(slide);
+            let lcmds = macho.to_bytes()?;
+            let segments = orig.segments().zip(macho.segments())
+                .map(|(orig, segment)| RebasedSegment { name: orig.name().to_owned(), orig_vmaddr: orig.vmaddr, vmaddr: segment.vmaddr })
+                .collect();
+            if pointers == 0 {
+                module.bytes.patch(&self.image, 0, Patch::Bytes(lcmds));
+            } else {
+                data[..lcmds.len()].copy_from_slice(&lcmds);
+                module.bytes.patch(&self.image, 0, Patch::Bytes(data));
+            }
+            module.rebase = Some(Rebase { orig_base, base: module.virt, segments, pointers });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chained_fixups, rebase_opcodes, uleb128, CHAINED_PTR_32, CHAINED_PTR_64, CHAINED_PTR_64_OFFSET, CHAINED_PTR_ARM64E};
+    use crate::{macho::Segment, SepSplitError};
+
+    fn segment(name: &str, fileoff: u64, filesize: u64) -> Segment {
+        let mut segname = [0; 16];
+        segname[..name.len()].copy_from_slice(name.as_bytes());
+        Segment { segname, vmaddr: fileoff, vmsize: filesize, fileoff, filesize, maxprot: 3, initprot: 3, flags: 0, sections: Vec::new() }
+    }
+
+    fn fails_with<T>(result: &Result<T, SepSplitError>, reason: &str) -> bool {
+        matches!(result, Err(SepSplitError::BadMachO(r)) if *r == reason)
+    }
+
+    #[test]
+    fn test_uleb128() {
+        let mut pos = 0;
+        let info = [0x00, 0x7f, 0x80, 0x04, 0xe5, 0x8e, 0x26, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        for expected in [0, 0x7f, 0x200, 624_485, u64::MAX] {
+            assert_eq!(uleb128(&info, &mut pos).unwrap(), expected);
+        }
+        assert_eq!(pos, info.len());
+        assert!(fails_with(&uleb128(&[0x80, 0x80], &mut 0), "rebase info is truncated"));
+    }
+
+    #[test]
+    fn test_rebase_opcodes() {
+        let (text, data) = (segment("__TEXT", 0, 0x4000), segment("__DATA", 0x4000, 0x100));
+        let segments = [&text, &data];
+        let info = [
+            0x11,                   //REBASE_TYPE_POINTER
+            0x20, 0x80, 0x04, 0x51, //__TEXT+0x200, once
+            0x21, 0x10, 0x52,       //__DATA+0x10, twice
+            0x30, 0x08, 0x41,       //skip 8 bytes and a pointer
+            0x60, 0x02,             //twice
+            0x70, 0x10,             //once, then skip 0x10 bytes
+            0x80, 0x02, 0x08,       //twice, skipping 8 bytes after each
+            0x12, 0x51,             //REBASE_TYPE_TEXT_ABSOLUTE32, once
+            0x13, 0x51,             //REBASE_TYPE_TEXT_PCREL32, once, it moves with the code
+            0x12, 0x51,
+            0x00, 0xff,             //REBASE_OPCODE_DONE, nothing after it is read
+        ];
+        assert_eq!(rebase_opcodes(&info, &segments, 8).unwrap(), [
+            (0x200, 8), (0x4010, 8), (0x4018, 8), (0x4030, 8), (0x4038, 8), (0x4040, 8), (0x4058, 8), (0x4068, 8), (0x4078, 4), (0x4088, 4),
+        ]);
+        //32-bit pointers
+        assert_eq!(rebase_opcodes(&[0x11, 0x21, 0x10, 0x53, 0x41, 0x51], &segments, 4).unwrap(), [
+            (0x4010, 4), (0x4014, 4), (0x4018, 4), (0x4020, 4),
+        ]);
+        assert!(rebase_opcodes(&[], &segments, 8).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rebase_opcodes_invalid() {
+        let (text, data) = (segment("__TEXT", 0, 0x4000), segment("__DATA", 0x4000, 0x100));
+        let segments = [&text, &data];
+        for (info, reason) in [
+            (&[0x11, 0x51][..], "rebase before its segment is set"),
+            (&[0x11, 0x22, 0x00, 0x51], "rebase in a segment that does not exist"),
+            (&[0x11, 0x21, 0xfc, 0x01, 0x51], "rebase is outside of its segment"),
+            (&[0x11, 0x21, 0xf9, 0x01, 0x51], "rebase is outside of its segment"),
+            //a count that would never end stops at the end of the segment
+            (&[0x11, 0x21, 0x00, 0x60, 0xff, 0xff, 0xff, 0xff, 0x0f], "rebase is outside of its segment"),
+            (&[0x11, 0x21, 0x00, 0x30, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0x51], "rebase is outside of its segment"),
+            (&[0x11, 0x21], "rebase info is truncated"),
+            (&[0x11, 0x21, 0x00, 0x80, 0x02], "rebase info is truncated"),
+            (&[0x11, 0x21, 0x00, 0x90], "unknown rebase opcode"),
+        ] {
+            assert!(fails_with(&rebase_opcodes(info, &segments, 8), reason), "{info:02x?}");
+        }
+    }
+
+    const FIXUPS: u64 = 0x300;
+
+    //a module with __TEXT and a __DATA of 2 pages of 0x100 bytes, and its chained fixups at FIXUPS
+    fn chained(format: u16, page_starts: [u16; 2], pointers: &[(usize, u64)]) -> Vec<u8> {
+        let mut data = vec![0; 0x400];
+        let mut put = |at: usize, bytes: &[u8]| data[at..at + bytes.len()].copy_from_slice(bytes);
+        put(0x304, &0x20u32.to_le_bytes()); //starts_offset
+        put(0x320, &2u32.to_le_bytes()); //seg_count
+        put(0x324, &0u32.to_le_bytes()); //__TEXT has no fixups
+        put(0x328, &0x10u32.to_le_bytes());
+        put(0x334, &0x100u16.to_le_bytes()); //page_size
+        put(0x336, &format.to_le_bytes());
+        put(0x344, &2u16.to_le_bytes()); //page_count
+        put(0x346, &page_starts[0].to_le_bytes());
+        put(0x348, &page_starts[1].to_le_bytes());
+        let size = if format == CHAINED_PTR_32 { 4 } else { 8 };
+        for &(at, ptr) in pointers {
+            put(at, &ptr.to_le_bytes()[..size]);
+        }
+        data
+    }
+
+    fn pointer(data: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_chained_fixups_64() {
+        let (text, data_seg) = (segment("__TEXT", 0, 0x100), segment("__DATA", 0x100, 0x200));
+        let segments = [&text, &data_seg];
+        let pointers = [
+            (0x110, 2 << 51 | 0x4000),           //a rebase, the next one is 8 bytes after it
+            (0x118, 1 << 63 | 2 << 51 | 0x0007), //a bind
+            (0x120, 0x80 << 36 | 0x8000),        //a rebase with the high 8 bits, the end of the chain
+            (0x210, 0x1234),                     //a page with no chain
+        ];
+        let mut data = chained(CHAINED_PTR_64, [0x10, 0xffff], &pointers);
+        assert_eq!(chained_fixups(&mut data, FIXUPS, &segments, 0x10_0000).unwrap(), 2);
+        assert_eq!(pointer(&data, 0x110), 2 << 51 | 0x10_4000);
+        assert_eq!(pointer(&data, 0x118), 1 << 63 | 2 << 51 | 0x0007);
+        assert_eq!(pointer(&data, 0x120), 0x80 << 36 | 0x10_8000);
+        assert_eq!(pointer(&data, 0x210), 0x1234);
+
+        //the targets have 36 bits
+        let mut data = chained(CHAINED_PTR_64, [0x10, 0xffff], &[(0x110, 0xf_ffff_0000)]);
+        assert!(fails_with(&chained_fixups(&mut data, FIXUPS, &segments, 0x10_0000), "a rebased chained pointer does not fit"));
+        //pointers that are offsets from the start of the module do not move
+        let mut data = chained(CHAINED_PTR_64_OFFSET, [0x10, 0xffff], &pointers);
+        let orig = data.clone();
+        assert_eq!(chained_fixups(&mut data, FIXUPS, &segments, 0x10_0000).unwrap(), 0);
+        assert_eq!(data, orig);
+    }
+
+    #[test]
+    fn test_chained_fixups_arm64e_32() {
+        let (text, data_seg) = (segment("__TEXT", 0, 0x100), segment("__DATA", 0x100, 0x200));
+        let segments = [&text, &data_seg];
+        let pointers = [
+            (0x100, 1 << 51 | 0x4000),              //a rebase, the next one is 8 bytes after it
+            (0x108, 1 << 63 | 1 << 51 | 0x4000),    //an authenticated rebase, its target is an offset
+            (0x110, 3 << 62 | 0x0003),              //an authenticated bind, the end of the chain
+            (0x208, 0x40 << 43 | 0x4000),           //the second page, with the high 8 bits
+        ];
+        let mut data = chained(CHAINED_PTR_ARM64E, [0x00, 0x08], &pointers);
+        assert_eq!(chained_fixups(&mut data, FIXUPS, &segments, 0x20_0000).unwrap(), 2);
+        assert_eq!(pointer(&data, 0x100), 1 << 51 | 0x20_4000);
+        assert_eq!(pointer(&data, 0x108), 1 << 63 | 1 << 51 | 0x4000);
+        assert_eq!(pointer(&data, 0x110), 3 << 62 | 0x0003);
+        assert_eq!(pointer(&data, 0x208), 0x40 << 43 | 0x20_4000);
+
+        let pointers = [(0x100, 1 << 26 | 0x1000), (0x104, 1 << 31 | 0x0002)];
+        let mut data = chained(CHAINED_PTR_32, [0x00, 0xffff], &pointers);
+        assert_eq!(chained_fixups(&mut data, FIXUPS, &segments, 0x3000).unwrap(), 1);
+        assert_eq!(pointer(&data, 0x100) as u32, 1 << 26 | 0x4000);
+        assert_eq!(pointer(&data, 0x104) as u32, 1 << 31 | 0x0002);
+        //the targets are 32-bit and wrap around, so sliding down works
+        let mut data = chained(CHAINED_PTR_32, [0x00, 0xffff], &pointers);
+        assert_eq!(chained_fixups(&mut data, FIXUPS, &segments, 0u64.wrapping_sub(0x800)).unwrap(), 1);
+        assert_eq!(pointer(&data, 0x100) as u32, 1 << 26 | 0x0800);
+    }
+
+    #[test]
+    fn test_chained_fixups_invalid() {
+        let (text, data_seg) = (segment("__TEXT", 0, 0x100), segment("__DATA", 0x100, 0x200));
+        let segments = [&text, &data_seg];
+        let mut data = chained(CHAINED_PTR_64, [0x8010, 0xffff], &[]);
+        assert!(fails_with(&chained_fixups(&mut data, FIXUPS, &segments, 0x1000), "chained fixups with more than one chain in a page"));
+        let mut data = chained(5, [0x10, 0xffff], &[]);
+        assert!(fails_with(&chained_fixups(&mut data, FIXUPS, &segments, 0x1000), "unknown chained fixups pointer format"));
+        let mut data = chained(CHAINED_PTR_64, [0x10, 0xffff], &[]);
+        assert!(fails_with(&chained_fixups(&mut data, FIXUPS, &segments[..1], 0x1000), "chained fixups in a segment that does not exist"));
+        //a chain that runs off the end of the module
+        let mut data = chained(CHAINED_PTR_64, [0x10, 0xffff], &[(0x110, 0xfff << 51)]);
+        assert!(matches!(chained_fixups(&mut data, FIXUPS, &segments, 0x1000), Err(SepSplitError::Truncated { .. })));
+        let mut data = chained(CHAINED_PTR_64, [0x10, 0xffff], &[]);
+        assert!(matches!(chained_fixups(&mut data, 0x3fe, &segments, 0x1000), Err(SepSplitError::Truncated { .. })));
+        data.truncate(0x340);
+        assert!(matches!(chained_fixups(&mut data, FIXUPS, &segments, 0x1000), Err(SepSplitError::Truncated { .. })));
+    }
+}
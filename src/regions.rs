@@ -0,0 +1,371 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    The map of the decompressed image by physical address, which is the offset in the image:
+        boot, kernel    the modules before the apps
+        struct          the struct dump
+        text, data      the Mach-O and the rw segments of SEPOS, the apps and the shared libraries
+        stack           the stack of SEPOS (stack_base_paddr), usually after the image
+        shm             the shared memory region of the SEP HDR, usually after the image
+        gap             the bytes of the image no other region covers, with how many of them are not zero
+    Regions that overlap are listed in pairs, as are gaps with bytes that are not zero,
+    both usually mean the splitter is reading the structs of this firmware wrong.
+*/
+
+use std::{
+    fmt,
+    io::BufWriter,
+};
+
+use prettytable::{format, row, Table};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use crate::{
+    firmware::{ModuleKind, SepFirmware},
+    manifest::to_json,
+    split_image, Fix, SepSplitError, SplitOptions,
+};
+
+/// What is in a region of the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionKind {
+    Boot,
+    Kernel,
+    Struct,
+    Text,
+    Data,
+    Stack,
+    Shm,
+    Gap,
+}
+
+/// A range of physical addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String, // The module the region is part of, or what it is for stack, shm and gaps
+    pub kind: RegionKind,
+    pub start: u64,
+    pub end: u64,     // Exclusive
+    pub nonzero: u64, // For gaps, how many of its bytes are not zero
+}
+
+/// Two regions that share some addresses
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Overlap {
+    pub first: String,
+    pub second: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// The regions of a SEP firmware image by physical address, see `firmware_regions`
+#[derive(Debug, Clone, Serialize)]
+pub struct RegionMap {
+    pub image_size: u64,
+    pub regions: Vec<Region>, // Sorted by start, with the gaps
+    pub overlaps: Vec<Overlap>,
+}
+
+impl RegionKind {
+    /// The name of the kind, as used in the JSON
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Boot => "boot",
+            Self::Kernel => "kernel",
+            Self::Struct => "struct",
+            Self::Text => "text",
+            Self::Data => "data",
+            Self::Stack => "stack",
+            Self::Shm => "shm",
+            Self::Gap => "gap",
+        }
+    }
+}
+
+impl Region {
+    fn new(name: &str, kind: RegionKind, start: u64, size: u64) -> Self {
+        Self { name: name.to_owned(), kind, start, end: start.saturating_add(size), nonzero: 0 }
+    }
+
+    #[must_use]
+    pub const fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+impl Serialize for Region {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut region = serializer.serialize_struct("Region", 6)?;
+        region.serialize_field("name", &self.name)?;
+        region.serialize_field("kind", &self.kind)?;
+        region.serialize_field("start", &self.start)?;
+        region.serialize_field("end", &self.end)?;
+        region.serialize_field("size", &self.size())?;
+        region.serialize_field("nonzero", &self.nonzero)?;
+        region.end()
+    }
+}
+
+/// Walks the structs of a SEP firmware like `firmware_info`, and maps the regions of its image.
+/// # Arguments
+/// * `input` - The bytes of the SEP firmware, either raw or in an IM4P
+/// * `opts` - The options to split with
+/// # Errors
+/// * The same errors as `split_bytes`
+pub fn firmware_regions(input: &[u8], opts: &SplitOptions) -> Result<RegionMap, SepSplitError> {
+    let fw = split_image(input, opts, BufWriter::new(Box::new(std::io::sink())), Fix::None)?;
+    Ok(fw.region_map())
+}
+
+impl SepFirmware<'_> {
+    /// The regions of the image by physical address, see `firmware_regions`
+    #[must_use]
+    pub fn region_map(&self) -> RegionMap {
+        let mut regions = Vec::new();
+        for module in &self.modules {
+            let kind = match module.kind {
+                ModuleKind::Boot => RegionKind::Boot,
+                ModuleKind::Kernel => RegionKind::Kernel,
+                ModuleKind::Struct => RegionKind::Struct,
+                ModuleKind::Rootserver | ModuleKind::App | ModuleKind::SharedLib => RegionKind::Text,
+            };
+            regions.push(Region::new(&module.name, kind, module.phys_text, module.size_text));
+            if module.phys_data != 0 && module.size_data != 0 {
+                regions.push(Region::new(&module.name, RegionKind::Data, module.phys_data, module.size_data));
+            }
+            if module.stack_paddr != 0 && module.stack_size != 0 {
+                regions.push(Region::new(&format!("{} stack", module.name), RegionKind::Stack, module.stack_paddr, module.stack_size));
+            }
+        }
+        if let Some(header) = self.header.filter(|header| header.shm_size != 0) {
+            regions.push(Region::new("shared memory", RegionKind::Shm, header.shm_base, header.shm_size));
+        }
+        regions.sort_by_key(|region| (region.start, region.end));
+
+        let mut overlaps = Vec::new();
+        for (i, first) in regions.iter().enumerate() {
+            //sorted by start, so only the next ones that start before this one ends can overlap it
+            for second in regions[i + 1..].iter().take_while(|second| second.start < first.end) {
+                if second.start < second.end {
+                    overlaps.push(Overlap {
+                        first: first.name.clone(),
+                        second: second.name.clone(),
+                        start: second.start,
+                        end: first.end.min(second.end),
+                    });
+                }
+            }
+        }
+
+        //the bytes of the image that no region covers
+        let image_size = self.image.len() as u64;
+        let mut gaps = Vec::new();
+        let mut covered = 0;
+        for region in &regions {
+            if region.start > covered && covered < image_size {
+                gaps.push(self.gap(covered, region.start.min(image_size)));
+            }
+            covered = covered.max(region.end);
+        }
+        if covered < image_size {
+            gaps.push(self.gap(covered, image_size));
+        }
+        regions.extend(gaps);
+        regions.sort_by_key(|region| (region.start, region.end));
+
+        RegionMap { image_size, regions, overlaps }
+    }
+
+    fn gap(&self, start: u64, end: u64) -> Region {
+        let nonzero = self.image[start as usize..end as usize].iter().filter(|&&b| b != 0).count() as u64;
+        Region { nonzero, ..Region::new("gap", RegionKind::Gap, start, end - start) }
+    }
+}
+
+impl RegionMap {
+    /// The gaps that have bytes that are not zero, which the splitter does not write anywhere
+    pub fn nonzero_gaps(&self) -> impl Iterator<Item = &Region> {
+        self.regions.iter().filter(|region| region.kind == RegionKind::Gap && region.nonzero != 0)
+    }
+
+    /// The map as pretty printed JSON, the regions sorted by start with the gaps, and the overlapping pairs
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Report<'a> {
+            #[serde(flatten)]
+            map: &'a RegionMap,
+            unaccounted: Vec<&'a Region>,
+        }
+        to_json(&Report { map: self, unaccounted: self.nonzero_gaps().collect() })
+    }
+
+    //the name of the first region that overlaps this one
+    fn overlapping(&self, region: &Region) -> Option<&str> {
+        self.overlaps.iter().filter(|overlap| overlap.start >= region.start && overlap.end <= region.end).find_map(|overlap| {
+            if overlap.first == region.name {
+                Some(overlap.second.as_str())
+            } else if overlap.second == region.name {
+                Some(overlap.first.as_str())
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl fmt::Display for RegionMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "image            size {:#x}", self.image_size)?;
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_BOX_CHARS);
+        table.set_titles(row!["Kind", "Name", "Start", "End", "Size", ""]);
+        for region in &self.regions {
+            let note = match (region.kind, self.overlapping(region)) {
+                (RegionKind::Gap, _) if region.nonzero != 0 => format!("{:#x} bytes not zero", region.nonzero),
+                (RegionKind::Gap, _) => String::from("zeros"),
+                (_, Some(other)) => format!("overlaps {other}"),
+                _ if region.end > self.image_size && region.start < self.image_size => String::from("past the image"),
+                _ if region.start >= self.image_size => String::from("outside the image"),
+                _ => String::new(),
+            };
+            table.add_row(row![
+                region.kind.as_str(),
+                region.name,
+                r->format!("{:#x}", region.start),
+                r->format!("{:#x}", region.end),
+                r->format!("{:#x}", region.size()),
+                note,
+            ]);
+        }
+        write!(f, "{table}")?;
+        for overlap in &self.overlaps {
+            writeln!(f, "overlap          {} and {} at {:#x}..{:#x}", overlap.first, overlap.second, overlap.start, overlap.end)?;
+        }
+        for gap in self.nonzero_gaps() {
+            writeln!(f, "unaccounted      {:#x}..{:#x} has {:#x} bytes that are not zero", gap.start, gap.end, gap.nonzero)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::{Overlap, Region, RegionKind};
+    use crate::firmware::{ModuleBytes, ModuleKind, SepFirmware, SepFormat, SepHeader, SepModule};
+
+    fn module(kind: ModuleKind, name: &str, phys: u64, size: u64) -> SepModule {
+        SepModule::raw(kind, None, name, phys, ModuleBytes::new(phys as usize..(phys + size) as usize))
+    }
+
+    fn region(name: &str, kind: RegionKind, start: u64, end: u64, nonzero: u64) -> Region {
+        Region { name: name.to_owned(), kind, start, end, nonzero }
+    }
+
+    //an image of 0x1000 bytes: sks and ARTM overlap, the gap after sks has 4 bytes that are not zero,
+    //the stack of SEPOS goes past the end of the image and the data of libSEPOS is outside of it
+    fn firmware() -> SepFirmware<'static> {
+        let mut image = vec![0; 0x1000];
+        image[0x790..0x794].copy_from_slice(b"SEP!");
+        let mut fw = SepFirmware::new(SepFormat::Bits64 { subversion: 4, old: false });
+        fw.image = Cow::Owned(image);
+        fw.header = Some(SepHeader { shm_base: 0xe00, shm_size: 0x100, ..SepHeader::default() });
+        let mut sepos = module(ModuleKind::Rootserver, "SEPOS", 0x400, 0x200);
+        (sepos.phys_data, sepos.size_data) = (0x800, 0x100);
+        (sepos.stack_paddr, sepos.stack_size) = (0xf80, 0x400);
+        //a data segment or stack with no size is not a region
+        let mut sks = module(ModuleKind::App, "sks", 0x600, 0x180);
+        (sks.phys_data, sks.stack_size) = (0x780, 0x1000);
+        let mut lib = module(ModuleKind::SharedLib, "libSEPOS", 0x900, 0x80);
+        (lib.phys_data, lib.size_data) = (0x1400, 0x100);
+        fw.modules = vec![
+            module(ModuleKind::Boot, "boot", 0, 0x100),
+            module(ModuleKind::Kernel, "kernel", 0x100, 0x300),
+            sepos,
+            sks,
+            module(ModuleKind::App, "ARTM", 0x700, 0x40),
+            lib,
+        ];
+        fw
+    }
+
+    #[test]
+    fn test_region_map() {
+        let map = firmware().region_map();
+        assert_eq!(map.image_size, 0x1000);
+        assert_eq!(map.regions, [
+            region("boot", RegionKind::Boot, 0, 0x100, 0),
+            region("kernel", RegionKind::Kernel, 0x100, 0x400, 0),
+            region("SEPOS", RegionKind::Text, 0x400, 0x600, 0),
+            region("sks", RegionKind::Text, 0x600, 0x780, 0),
+            region("ARTM", RegionKind::Text, 0x700, 0x740, 0),
+            region("gap", RegionKind::Gap, 0x780, 0x800, 4),
+            region("SEPOS", RegionKind::Data, 0x800, 0x900, 0),
+            region("libSEPOS", RegionKind::Text, 0x900, 0x980, 0),
+            region("gap", RegionKind::Gap, 0x980, 0xe00, 0),
+            region("shared memory", RegionKind::Shm, 0xe00, 0xf00, 0),
+            region("gap", RegionKind::Gap, 0xf00, 0xf80, 0),
+            region("SEPOS stack", RegionKind::Stack, 0xf80, 0x1380, 0),
+            region("libSEPOS", RegionKind::Data, 0x1400, 0x1500, 0),
+        ]);
+        assert_eq!(map.overlaps, [Overlap { first: String::from("sks"), second: String::from("ARTM"), start: 0x700, end: 0x740 }]);
+        assert_eq!(map.nonzero_gaps().collect::<Vec<_>>(), [&map.regions[5]]);
+    }
+
+    #[test]
+    fn test_region_map_edges() {
+        //a region inside of another overlaps it, empty ones do not, and the end of the image is a gap
+        let mut fw = firmware();
+        fw.header = None;
+        fw.modules = vec![
+            module(ModuleKind::Kernel, "kernel", 0, 0x800),
+            module(ModuleKind::App, "sks", 0x200, 0x100),
+            module(ModuleKind::App, "empty", 0x400, 0),
+        ];
+        let map = fw.region_map();
+        assert_eq!(map.overlaps, [Overlap { first: String::from("kernel"), second: String::from("sks"), start: 0x200, end: 0x300 }]);
+        assert_eq!(map.regions.last(), Some(&region("gap", RegionKind::Gap, 0x800, 0x1000, 0)));
+        assert_eq!(map.nonzero_gaps().count(), 0);
+
+        //no regions at all, the whole image is a gap
+        fw.modules.clear();
+        let map = fw.region_map();
+        assert_eq!(map.regions, [region("gap", RegionKind::Gap, 0, 0x1000, 4)]);
+        assert!(map.overlaps.is_empty());
+    }
+
+    #[test]
+    fn test_region_map_output() {
+        let map = firmware().region_map();
+        let table = map.to_string();
+        assert!(table.contains("overlaps ARTM"));
+        assert!(table.contains("0x4 bytes not zero"));
+        assert!(table.contains("past the image"));
+        assert!(table.contains("outside the image"));
+        assert!(table.contains("overlap          sks and ARTM at 0x700..0x740"));
+        assert!(table.contains("unaccounted      0x780..0x800 has 0x4 bytes that are not zero"));
+        let json = map.to_json();
+        assert!(json.starts_with("{\n  \"image_size\": 4096,\n  \"regions\": [\n"));
+        assert!(json.contains("\"unaccounted\": [\n    {\n      \"name\": \"gap\",\n      \"kind\": \"gap\",\n      \"start\": 1920,\n      \"end\": 2048,\n      \"size\": 128,\n      \"nonzero\": 4\n    }\n  ]\n}"));
+    }
+}
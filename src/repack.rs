@@ -0,0 +1,233 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Puts (possibly modified) modules back into a SEP firmware, the inverse of splitting.
+    A split module is the bytes at phys_text, with __LINKEDIT moved to its vm offset,
+    the symbol tables moved with it (or zeroed) and the rw segments copied in (see restore_file), so:
+        - the text is the module without its trailing size_data bytes
+        - the rw segments are taken from where fix_data_segment put them
+        - __LINKEDIT and the symbol tables are restored from the original module
+    Modules keep their phys_text/phys_data, only the size of the text can change.
+    A firmware that came in the LZVN container is compressed again into the same container.
+*/
+
+use std::{
+    borrow::Cow,
+    fs,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    firmware::{ModuleKind, SepFormat, SepModule},
+    img4::{self, Im4p},
+    is_lzvn_container, lzvn_container,
+    macho::{Command, MachO, SEG_DATA, SEG_LINKEDIT},
+    split_buf,
+    utils::{filecreate, range_size, slice_from, slice_size, slice_size_mut},
+    DecryptKey, SepSplitError, SplitOptions,
+};
+
+fn repack_err(module: &SepModule, reason: &'static str) -> SepSplitError {
+    SepSplitError::Repack { module: module.name.clone(), reason }
+}
+
+//undoes fix_linkedit, by taking the __LINKEDIT offset and the symbol tables from the original module
+fn unfix_linkedit(image: &mut [u8], original: &[u8], module: &SepModule) -> Result<(), SepSplitError> {
+    let mut macho = MachO::parse(image)?;
+    let orig = MachO::parse(original)?;
+    if macho.commands.len() != orig.commands.len() || macho.is64() != orig.is64() {
+        return Err(repack_err(module, "the load commands differ from the original"))
+    }
+
+    for (lc, orig_lc) in macho.commands.iter_mut().zip(&orig.commands) {
+        if lc.cmd != orig_lc.cmd {
+            return Err(repack_err(module, "the load commands differ from the original"))
+        }
+        match (&mut lc.command, &orig_lc.command) {
+            (Command::Segment(seg), Command::Segment(orig_seg)) if seg.name() == SEG_LINKEDIT => seg.fileoff = orig_seg.fileoff,
+            (Command::SymTab(_) | Command::DySymTab(_), _) => *lc = orig_lc.clone(),
+            _ => ()
+        }
+    }
+
+    let lcmds = macho.to_bytes()?;
+    slice_size_mut(image, 0, lcmds.len())?.copy_from_slice(&lcmds);
+    Ok(())
+}
+
+//splits a modified module back into its text and rw segments, as they are stored in the firmware
+fn unrestore(krnl: &[u8], module: &SepModule, new: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>), SepSplitError> {
+    let size_data = module.size_data as usize;
+    let text_len = new.len().checked_sub(size_data).ok_or_else(|| repack_err(module, "smaller than its rw segments"))?;
+    let mut text = new[..text_len].to_owned();
+    let original = slice_from(krnl, module.phys_text as usize)?;
+
+    let data = if size_data == 0 {
+        None
+    } else {
+        //shared libraries have their rw segments right after the text
+        let at = if module.kind == ModuleKind::SharedLib {
+            text_len
+        } else {
+            MachO::parse(new)?.segment(SEG_DATA).ok_or_else(|| repack_err(module, "missing __DATA segment"))?.fileoff as usize
+        };
+        let data = new.get(range_size(at, size_data)).ok_or_else(|| repack_err(module, "__DATA segment is truncated"))?.to_owned();
+
+        //put back what the rw segments were copied over in the text
+        let orig_text = slice_size(original, 0, module.size_text as usize)?;
+        if let (Some(dst), Some(src)) = (text.get_mut(range_size(at, size_data)), orig_text.get(range_size(at, size_data))) {
+            dst.copy_from_slice(src);
+        }
+        Some(data)
+    };
+
+    if MachO::is_macho(original) && MachO::is_macho(&text) {
+        unfix_linkedit(&mut text, original, module)?;
+    }
+    Ok((text, data))
+}
+
+//the LZVN container of the firmware, either the input or the payload of its IM4P
+fn find_container<'a>(input: &'a [u8], key: Option<&DecryptKey>) -> Result<Option<Cow<'a, [u8]>>, SepSplitError> {
+    if !img4::is_img4(input) {
+        return Ok(is_lzvn_container(input).then_some(Cow::Borrowed(input)))
+    }
+    let im4p = Im4p::parse(input)?;
+    let payload = match key {
+        Some(key) if im4p.is_encrypted() => Cow::Owned(key.decrypt(im4p.payload)),
+        _ => Cow::Borrowed(im4p.payload)
+    };
+    Ok((im4p.compression.is_none() && is_lzvn_container(&payload)).then_some(payload))
+}
+
+/// Puts modified modules back into a SEP firmware.
+///
+/// The modules are read from `moddir` with the file names they were split with (like `sepdump03_sks`),
+/// missing or unchanged modules are kept as is.
+/// Every module is placed at its original `phys_text`/`phys_data`, the size of the text may change
+/// as long as it does not overlap with another module, and the SEP app table is updated to match.
+/// The CRC32 stored in the firmware is recomputed.
+/// If the original firmware is in the LZVN container ("eGirBwRD"), the new one is compressed into it again.
+/// # Arguments
+/// * `input` - The original SEP firmware, either raw or in an IM4P
+/// * `moddir` - The directory with the modules
+/// * `opts` - The options the original firmware is split with, `rebase` is ignored as the modules must be at the addresses the linker set
+/// # Returns
+/// * The new SEP firmware, uncompressed unless the original is in the LZVN container
+/// # Errors
+/// * Any error from `split_bytes` for the original firmware
+/// * The firmware could not be compressed again
+/// * A module could not be read
+/// * A module changed in a way that can not be repacked (e.g. overlaps with another module or its load commands changed)
+pub fn repack(input: &[u8], moddir: &Path, opts: &SplitOptions) -> Result<Vec<u8>, SepSplitError> {
+    //every module is needed to check for overlaps, and rebased modules would be written back with their slid addresses
+    let opts = &opts.clone().with_modules(Vec::new()).with_rebase(false);
+    let fw = split_buf(input, opts, BufWriter::new(Box::new(std::io::sink())))?;
+    let krnl = fw.image();
+    let mut image = krnl.to_vec();
+
+    //the regions every module is in, (start, end, modified)
+    let mut regions: Vec<(u64, u64, bool)> = Vec::new();
+    let mut modified = Vec::new();
+    for module in fw.modules.iter().filter(|m| m.index.is_some() && m.kind != ModuleKind::Struct) {
+        let path = moddir.join(&module.file_name);
+        let new = if path.exists() { Some(fs::read(&path)?) } else { None };
+        let Some(new) = new.filter(|new| **new != *fw.macho(module)) else {
+            regions.push((module.phys_text, module.phys_text + module.size_text, false));
+            if module.size_data != 0 {
+                regions.push((module.phys_data, module.phys_data + module.size_data, false));
+            }
+            continue
+        };
+
+        let (text, data) = unrestore(krnl, module, &new)?;
+        let text_len = text.len() as u64;
+        if text_len != module.size_text {
+            match (module.size_fields, module.kind) {
+                (Some((size_text_off, _)), _) => slice_size_mut(&mut image, size_text_off, 8)?.copy_from_slice(&text_len.to_le_bytes()),
+                (None, ModuleKind::Boot | ModuleKind::Kernel) => (),
+                (None, ModuleKind::Rootserver) if matches!(fw.format, SepFormat::Bits64 { .. }) => (),
+                _ => return Err(repack_err(module, "its size can not be changed"))
+            }
+        }
+
+        //clear the old module, in case the new one is smaller
+        slice_size_mut(&mut image, module.phys_text as usize, module.size_text as usize)?.fill(0);
+        let end = (module.phys_text + text_len) as usize;
+        if end > image.len() {
+            image.resize(end, 0);
+        }
+        slice_size_mut(&mut image, module.phys_text as usize, text.len())?.copy_from_slice(&text);
+        regions.push((module.phys_text, module.phys_text + text_len, true));
+        if let Some(data) = data {
+            slice_size_mut(&mut image, module.phys_data as usize, data.len())?.copy_from_slice(&data);
+            regions.push((module.phys_data, module.phys_data + module.size_data, true));
+        }
+        modified.push(module);
+    }
+
+    //modified modules can not overlap with any other module
+    for (i, &(start, end, _)) in regions.iter().enumerate().filter(|(_, r)| r.2) {
+        if regions.iter().enumerate().any(|(j, &(s, e, _))| i != j && s < end && start < e) {
+            let module = modified.iter().find(|m| m.phys_text == start || m.phys_data == start).copied();
+            return Err(SepSplitError::Repack {
+                module: module.map_or_else(String::new, |m| m.name.clone()),
+                reason: "overlaps with another module"
+            })
+        }
+    }
+
+    //recompute the CRC32 with the new sizes
+    if !modified.is_empty() && fw.crc.is_some() {
+        let newfw = split_buf(&image, &SplitOptions::default(), BufWriter::new(Box::new(std::io::sink())))?;
+        if let Some(crc) = newfw.crc {
+            slice_size_mut(&mut image, crc.offset as usize, 4)?.copy_from_slice(&crc.actual.to_le_bytes());
+        }
+    }
+
+    match find_container(input, opts.key.as_ref())? {
+        Some(container) if modified.is_empty() => Ok(container.into_owned()),
+        Some(container) => lzvn_container(&container, &image),
+        None => Ok(image)
+    }
+}
+
+/// Repacks a SEP firmware file with `repack`, and writes the new firmware.
+/// # Arguments
+/// * `input` - The original SEP firmware, either raw or in an IM4P
+/// * `moddir` - The directory with the modules
+/// * `output` - The file to write the new firmware to, kept if it exists and `opts.no_clobber` is set
+/// * `verbose` - The verbosity level (0 for no output, 1 to also say if the output was kept)
+/// * `opts` - The options the original firmware is split with
+/// # Errors
+/// * Input and output file errors (permissions, not found, etc.)
+/// * Any error from `repack`
+pub fn repack_file(input: &Path, moddir: &Path, output: &Path, verbose: usize, opts: &SplitOptions) -> Result<(), SepSplitError> {
+    let image = repack(&fs::read(input)?, moddir, opts)?;
+    let Some(mut file) = filecreate(output, !opts.no_clobber)? else {
+        if verbose >= 1 {
+            eprintln!("kept existing {}", output.display());
+        }
+        return Ok(())
+    };
+    file.write_all(&image)?;
+    file.flush()?;
+    Ok(())
+}
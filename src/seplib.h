@@ -23,12 +23,27 @@
 /*
   Calls the main logic of the program with FFI.
   Arguments:
-  - filein - the path to the extracted SEP firmware
+  - filein - the path to the SEP firmware, either raw or in an unencrypted IM4P, or an IPSW/OTA zip
   - outdir - the path to the output directory
   - verbose - the verbosity level (0 for no output, 1 for normal output)
   Returns:
   - 0 on success
-  - 1 on failure
+  - 1 if the arguments are not valid UTF-8
+  - 2 on I/O errors (reading the input or writing the output)
+  - 3 if the firmware is in an unknown format
+  - 4 if the IMG4 container could not be parsed or its payload is encrypted
+  - 5 if the firmware is truncated
+  - 6 if a struct in the firmware could not be read
+  - 7 if a module is not a valid Mach-O
+  - 8 if the app table could not be found or read
+  - 9 if the firmware could not be decompressed
+  - 10 if the IV or key to decrypt the IMG4 payload is not valid
+  - 11 if the IPSW/OTA zip could not be read
+  - 12 if the IPSW/OTA zip has no SEP firmware (or more than one, where only one can be read)
+  - 13 if the CRC32 stored in the firmware does not match (only in strict mode)
+  - 14 if a modified module could not be put back into the firmware
+  - 15 if no module with the selected name was found in the firmware
+  - 16 if the template of the output file names is not valid
   Safety:
   - filein must be a null terminated char array with valid UTF-8 characters and also be a path to a file
   - outdir must be a null terminated char array with valid UTF-8 characters and also be a path to a already existing directory
@@ -0,0 +1,276 @@
+/*
+    sepsplit-rs - A tool to split SEPOS firmware into its individual modules
+    Copyright (C) 2024 plzdonthaxme
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Where the split files go: loose files in a directory, or the entries of a tar or zip archive.
+    Tar archives are ustar, so they can be streamed to stdout:
+        header      512 bytes: name (100), mode, uid, gid, size and mtime in octal,
+                    checksum, type '0', "ustar\000", and the start of long names (155)
+        data        the file, padded with zeros to 512 bytes
+        end         two blocks of zeros
+    Every entry has a mode of 0644, no owner and an mtime of 0, so the same firmware gives the same archive.
+    Zip archives are written by the zip crate, with the same mode, every entry stored and dated 1980-01-01.
+*/
+
+use std::{
+    fs,
+    io::{self, Seek, Write},
+    path::Path,
+};
+
+use zip::{write::SimpleFileOptions, CompressionMethod, DateTime, ZipWriter};
+
+use crate::utils::{filecreate, CrcWriter};
+
+/// The archive to write the split files into, instead of a directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// A ustar archive, which can also be written to stdout
+    Tar,
+    /// A zip archive with the files stored uncompressed
+    Zip,
+}
+
+//where the files of a split firmware are written
+pub trait Sink {
+    //adds a file of len bytes that write writes, returns false if it already exists and is kept
+    fn add(&mut self, name: &str, len: u64, write: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>) -> io::Result<bool>;
+
+    fn add_bytes(&mut self, name: &str, data: &[u8]) -> io::Result<bool> {
+        self.add(name, data.len() as u64, &mut |out| out.write_all(data))
+    }
+}
+
+//loose files, the name can have subdirectories which are created
+pub struct DirSink<'a> {
+    pub dir: &'a Path,
+    pub clobber: bool,
+}
+
+impl Sink for DirSink<'_> {
+    fn add(&mut self, name: &str, _len: u64, write: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>) -> io::Result<bool> {
+        let path = self.dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let Some(mut file) = filecreate(&path, self.clobber)? else { return Ok(false) };
+        write(&mut file)?;
+        file.flush()?;
+        Ok(true)
+    }
+}
+
+//puts every file in a subdirectory, for the firmwares of an IPSW
+pub struct SubdirSink<'a> {
+    pub sink: &'a mut dyn Sink,
+    pub dir: &'a str,
+}
+
+impl Sink for SubdirSink<'_> {
+    fn add(&mut self, name: &str, len: u64, write: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>) -> io::Result<bool> {
+        self.sink.add(&format!("{}/{name}", self.dir), len, write)
+    }
+}
+
+fn octal(field: &mut [u8], value: u64) -> io::Result<()> {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    if digits.len() >= field.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "value is too large for a tar header"))
+    }
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    Ok(())
+}
+
+fn tar_header(name: &str, len: u64) -> io::Result<[u8; 512]> {
+    //names over 100 bytes are split at a '/' into the prefix and the name
+    let (prefix, name) = if name.len() <= 100 {
+        ("", name)
+    } else {
+        name.match_indices('/').map(|(i, _)| (&name[..i], &name[i + 1..]))
+            .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{name} is too long for a tar header")))?
+    };
+
+    let mut hdr = [0; 512];
+    hdr[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut hdr[100..108], 0o644)?;
+    octal(&mut hdr[108..116], 0)?;
+    octal(&mut hdr[116..124], 0)?;
+    octal(&mut hdr[124..136], len)?;
+    octal(&mut hdr[136..148], 0)?;
+    hdr[156] = b'0';
+    hdr[257..265].copy_from_slice(b"ustar\x0000");
+    hdr[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    //the checksum is computed with its own field as spaces
+    hdr[148..156].fill(b' ');
+    let sum: u64 = hdr.iter().map(|&b| u64::from(b)).sum();
+    octal(&mut hdr[148..155], sum)?;
+    hdr[154] = 0;
+    Ok(hdr)
+}
+
+//a tar archive, which only needs to be written forward
+pub struct TarSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TarSink<W> {
+    pub const fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    //writes the end of the archive
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0; 1024])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Sink for TarSink<W> {
+    fn add(&mut self, name: &str, len: u64, write: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>) -> io::Result<bool> {
+        self.out.write_all(&tar_header(name, len)?)?;
+        let mut data = CrcWriter { out: &mut self.out, crc: 0, len: 0 };
+        write(&mut data)?;
+        if data.len != len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{name} is not the size it was added with")))
+        }
+        let padding = (512 - len % 512) % 512;
+        self.out.write_all(&[0; 512][..padding as usize])?;
+        Ok(true)
+    }
+}
+
+impl<W: Write + Seek> Sink for ZipWriter<W> {
+    fn add(&mut self, name: &str, len: u64, write: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>) -> io::Result<bool> {
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .last_modified_time(DateTime::default())
+            .unix_permissions(0o644)
+            .large_file(len > u64::from(u32::MAX));
+        self.start_file(name, options)?;
+        let mut data = CrcWriter { out: self, crc: 0, len: 0 };
+        write(&mut data)?;
+        if data.len != len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{name} is not the size it was added with")))
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+    use super::{tar_header, Sink, TarSink};
+
+    //the value of an octal field, up to its first NUL or space
+    fn octal(field: &[u8]) -> u64 {
+        let digits = field.split(|&b| b == 0 || b == b' ').next().unwrap();
+        u64::from_str_radix(std::str::from_utf8(digits).unwrap(), 8).unwrap()
+    }
+
+    fn checksum(hdr: &[u8; 512]) -> u64 {
+        hdr.iter().enumerate().map(|(i, &b)| if (148..156).contains(&i) { u64::from(b' ') } else { u64::from(b) }).sum()
+    }
+
+    #[test]
+    fn test_tar_header() {
+        let hdr = tar_header("sepdump03_sks", 0x1234).unwrap();
+        assert_eq!(&hdr[..14], b"sepdump03_sks\0");
+        assert_eq!(&hdr[100..108], b"0000644\0");
+        assert_eq!(&hdr[108..116], b"0000000\0");
+        assert_eq!(&hdr[116..124], b"0000000\0");
+        assert_eq!(&hdr[124..136], b"00000011064\0");
+        assert_eq!(&hdr[136..148], b"00000000000\0");
+        assert_eq!(hdr[156], b'0');
+        assert_eq!(&hdr[257..265], b"ustar\x0000");
+        assert_eq!(&hdr[154..156], b"\0 ");
+        assert_eq!(octal(&hdr[148..156]), checksum(&hdr));
+        assert!(hdr[345..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_tar_header_long_names() {
+        //the name is split at the first '/' that leaves at most 100 bytes
+        let long = format!("{}/{}/{}", "a".repeat(60), "b".repeat(60), "c".repeat(90));
+        let hdr = tar_header(&long, 0).unwrap();
+        assert_eq!(&hdr[..91], format!("{}\0", "c".repeat(90)).as_bytes());
+        assert_eq!(&hdr[345..467], format!("{}/{}\0", "a".repeat(60), "b".repeat(60)).as_bytes());
+        assert_eq!(octal(&hdr[148..156]), checksum(&hdr));
+
+        assert!(tar_header(&"a".repeat(101), 0).is_err());
+        assert!(tar_header(&format!("{}/{}", "a".repeat(156), "b".repeat(10)), 0).is_err());
+        assert!(tar_header(&format!("{}/{}", "a".repeat(10), "b".repeat(101)), 0).is_err());
+        //the size has 11 octal digits
+        assert!(tar_header("big", 0o777_7777_7777).is_ok());
+        assert!(tar_header("big", 0o1_0000_0000_0000).is_err());
+    }
+
+    #[test]
+    fn test_tar_sink() {
+        let mut tar = TarSink::new(Vec::new());
+        assert!(tar.add_bytes("SEPOS", b"SEPOS").unwrap());
+        assert!(tar.add_bytes("empty", b"").unwrap());
+        assert!(tar.add_bytes("block", &[0xaa; 512]).unwrap());
+        let out = tar.finish().unwrap();
+
+        assert_eq!(out.len(), 512 * 2 + 512 + 512 * 2 + 1024);
+        assert_eq!(&out[..6], b"SEPOS\0");
+        assert_eq!(octal(&out[124..136]), 5);
+        assert_eq!(&out[512..517], b"SEPOS");
+        assert!(out[517..1024].iter().all(|&b| b == 0));
+        assert_eq!(&out[1024..1030], b"empty\0");
+        assert_eq!(octal(&out[1024 + 124..1024 + 136]), 0);
+        assert_eq!(&out[1536..1542], b"block\0");
+        assert!(out[2048..2560].iter().all(|&b| b == 0xaa));
+        assert!(out[2560..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_tar_sink_wrong_size() {
+        let mut tar = TarSink::new(Vec::new());
+        assert!(tar.add("short", 10, &mut |out| out.write_all(b"SEP")).is_err());
+    }
+
+    #[test]
+    fn test_zip_sink() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        assert!(zip.add_bytes("manifest.json", b"{}").unwrap());
+        assert!(zip.add_bytes("n61_16G192/sepdump02_SEPOS", &[0xaa; 0x1234]).unwrap());
+
+        let mut zip = ZipArchive::new(zip.finish().unwrap()).unwrap();
+        assert_eq!(zip.file_names().collect::<Vec<_>>(), ["manifest.json", "n61_16G192/sepdump02_SEPOS"]);
+        let mut sepos = zip.by_name("n61_16G192/sepdump02_SEPOS").unwrap();
+        assert_eq!(sepos.compression(), CompressionMethod::Stored);
+        assert_eq!(sepos.unix_mode(), Some(0o100_644));
+        assert_eq!(sepos.last_modified(), Some(DateTime::default()));
+        let mut data = Vec::new();
+        sepos.read_to_end(&mut data).unwrap();
+        assert_eq!(data, [0xaa; 0x1234]);
+    }
+
+    #[test]
+    fn test_zip_sink_wrong_size() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        assert!(zip.add("short", 10, &mut |out| out.write_all(b"SEP")).is_err());
+    }
+}
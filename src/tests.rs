@@ -38,6 +38,7 @@ use test_case::test_case;
 #[test_case("N71m.19F77")]
 fn test_fws(fname: &str) -> Result<(), Box<dyn Error>> {
     use assert_cmd::prelude::*;
+    use predicates::prelude::*;
     use std::process::Command;
 
     let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
@@ -48,9 +49,513 @@ fn test_fws(fname: &str) -> Result<(), Box<dyn Error>> {
         .assert()
         .success();
 
-    assert!(testfwp.join(format!("testout-{fname}/")).exists());
+    assert!(testfwp.join(format!("testout-{fname}/manifest.json")).exists());
+
+    //every firmware here is known-good, so the CRC32 region is right if the stored CRC32 matches
+    Command::cargo_bin("sepsplit-rs")?
+        .args(["verify", "--format", "json"])
+        .arg(testfwp.join(format!("sepfw.{fname}.bin")))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"matches\": true").or(predicate::str::starts_with("null")));
         
     fs::remove_dir_all(testfwp.join(format!("testout-{fname}/")))?; //cleanup
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test_case("D11.15A372")]
+#[test_case("N61.16G192")]
+fn test_json(fname: &str) -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use predicates::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg(testfwp.join(format!("sepfw.{fname}.bin")))
+        .arg(testfwp.join(format!("testout-json-{fname}/")))
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("{").and(predicate::str::contains("\"file\": \"sepdump00_boot\""))
+            .and(predicate::str::contains("\"crc32_check\"")));
+
+    fs::remove_dir_all(testfwp.join(format!("testout-json-{fname}/")))?; //cleanup
+
+    Ok(())
+}
+
+//encode a DER element, only what is needed to build an IM4P
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if contents.len() < 0x80 {
+        out.push(contents.len() as u8);
+    } else {
+        let len = contents.len().to_be_bytes();
+        let skip = len.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (len.len() - skip) as u8);
+        out.extend_from_slice(&len[skip..]);
+    }
+    out.extend_from_slice(contents);
+    out
+}
+
+#[test_case("D10.18A373")]
+#[test_case("N61.16G192")]
+fn test_im4p(fname: &str) -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+    let payload = fs::read(testfwp.join(format!("sepfw.{fname}.bin")))?;
+    let im4p = der(0x30, &[
+        der(0x16, b"IM4P"),
+        der(0x16, b"sepi"),
+        der(0x16, b"sep-firmware"),
+        der(0x04, &payload),
+    ].concat());
+    let im4pp = testfwp.join(format!("testim4p-{fname}.im4p"));
+    fs::write(&im4pp, im4p)?;
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg(&im4pp)
+        .arg(testfwp.join(format!("testout-im4p-{fname}/")))
+        .assert()
+        .success();
+
+    assert!(testfwp.join(format!("testout-im4p-{fname}/sepdump00_boot")).exists());
+
+    fs::remove_dir_all(testfwp.join(format!("testout-im4p-{fname}/")))?; //cleanup
+    fs::remove_file(im4pp)?;
+
+    Ok(())
+}
+//build a zip with deflated entries, like an IPSW
+fn zip(entries: &[(&str, &[u8])]) -> Result<Vec<u8>, Box<dyn Error>> {
+    use std::io::{Cursor, Write};
+    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data) in entries {
+        zip.start_file(*name, SimpleFileOptions::default().compression_method(CompressionMethod::Deflated))?;
+        zip.write_all(data)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+#[test]
+fn test_ipsw() -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use predicates::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+    let n61 = fs::read(testfwp.join("sepfw.N61.16G192.bin"))?;
+    let d10 = fs::read(testfwp.join("sepfw.D10.18A373.bin"))?;
+    let ipsw = zip(&[
+        ("BuildManifest.plist", b"<dict><key>ProductBuildVersion</key><string>16G192</string></dict>"),
+        ("Firmware/all_flash/sep-firmware.n61.RELEASE.im4p", &n61),
+        ("Firmware/all_flash/sep-firmware.d10.RELEASE.im4p", &d10),
+    ])?;
+    let ipswp = testfwp.join("testipsw.ipsw");
+    fs::write(&ipswp, ipsw)?;
+    let outp = testfwp.join("testout-ipsw/");
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg(&ipswp)
+        .arg(&outp)
+        .args(["--device", "n61ap"])
+        .assert()
+        .success();
+
+    assert!(outp.join("n61_16G192/sepdump00_boot").exists());
+    assert!(!outp.join("d10_16G192").exists());
+
+    //the subcommands that read a single firmware need the board when there is more than one
+    Command::cargo_bin("sepsplit-rs")?
+        .args(["list", "--device", "n61"])
+        .arg(&ipswp)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("sepdump00_boot"));
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("verify")
+        .arg(&ipswp)
+        .assert()
+        .failure()
+        .code(12);
+
+    fs::remove_dir_all(outp)?; //cleanup
+    fs::remove_file(ipswp)?;
+
+    Ok(())
+}
+
+#[test_case("D11.15A372")]
+#[test_case("N61.16G192")]
+fn test_repack(fname: &str) -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use predicates::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+    let fwp = testfwp.join(format!("sepfw.{fname}.bin"));
+    let outp = testfwp.join(format!("testout-repack-{fname}/"));
+    let repackp = testfwp.join(format!("testrepack.{fname}.bin"));
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg(&fwp)
+        .arg(&outp)
+        .assert()
+        .success();
+
+    //unchanged modules must give back the same firmware
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("repack")
+        .arg(&fwp)
+        .arg(&outp)
+        .arg(&repackp)
+        .assert()
+        .success();
+
+    assert_eq!(fs::read(&repackp)?, fs::read(&fwp)?);
+
+    //--no-clobber keeps the firmware that is already there
+    fs::write(&repackp, b"kept")?;
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("repack")
+        .arg(&fwp)
+        .arg(&outp)
+        .arg(&repackp)
+        .arg("--no-clobber")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("kept existing"));
+
+    assert_eq!(fs::read(&repackp)?, b"kept");
+
+    fs::remove_dir_all(outp)?; //cleanup
+    fs::remove_file(repackp)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_batch() -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use predicates::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+    let inp = testfwp.join("testin-batch/");
+    let outp = testfwp.join("testout-batch/");
+    fs::create_dir_all(&inp)?;
+    for fname in ["D11.15A372", "N61.16G192"] {
+        fs::copy(testfwp.join(format!("sepfw.{fname}.bin")), inp.join(format!("sepfw.{fname}.bin")))?;
+    }
+    fs::write(inp.join("sepfw.bogus.1A1.bin"), b"not a firmware")?;
+
+    //the bogus firmware fails, without stopping the others
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("batch")
+        .arg(&inp)
+        .arg(&outp)
+        .assert()
+        .code(3)
+        .stdout(predicate::str::contains("sepfw.bogus.1A1.bin").and(predicate::str::contains("2 split, 1 failed")));
+
+    assert!(outp.join("D11.15A372/manifest.json").exists());
+    assert!(outp.join("N61.16G192/sepdump00_boot").exists());
+
+    fs::remove_dir_all(inp)?; //cleanup
+    fs::remove_dir_all(outp)?;
+
+    Ok(())
+}
+
+#[test_case("D11.15A372")]
+#[test_case("N61.16G192")]
+fn test_subcommands(fname: &str) -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use predicates::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+    let fwp = testfwp.join(format!("sepfw.{fname}.bin"));
+    let extractp = testfwp.join(format!("testextract.{fname}"));
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("list")
+        .arg(&fwp)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("sepdump01_kernel"));
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("info")
+        .arg(&fwp)
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"kind\": \"rootserver\"").and(predicate::str::contains("\"sizes\"")));
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("verify")
+        .arg(&fwp)
+        .assert()
+        .success();
+
+    Command::cargo_bin("sepsplit-rs")?
+        .args(["extract", "--prefix", "test"])
+        .arg(&fwp)
+        .arg("test01_kernel")
+        .arg("-o")
+        .arg(&extractp)
+        .assert()
+        .success();
+    assert!(fs::metadata(&extractp)?.len() > 0);
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("extract")
+        .arg(&fwp)
+        .arg("not a module")
+        .assert()
+        .code(15);
+
+    fs::remove_file(extractp)?; //cleanup
+
+    Ok(())
+}
+
+#[test_case("D11.15A372")]
+#[test_case("N61.16G192")]
+fn test_module_filter(fname: &str) -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+    let outp = testfwp.join(format!("testout-filter-{fname}/"));
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg(testfwp.join(format!("sepfw.{fname}.bin")))
+        .arg(&outp)
+        .args(["-m", "sep*", "-m", "1"])
+        .assert()
+        .success();
+
+    assert!(outp.join("sepdump01_kernel").exists());
+    assert!(outp.join("sepdump02_SEPOS").exists());
+    assert!(!outp.join("sepdump00_boot").exists());
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg(testfwp.join(format!("sepfw.{fname}.bin")))
+        .arg(&outp)
+        .args(["-m", "not a module"])
+        .assert()
+        .code(15);
+
+    fs::remove_dir_all(outp)?; //cleanup
+
+    Ok(())
+}
+
+#[test]
+fn test_template() -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+    let outp = testfwp.join("testout-template/");
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg(testfwp.join("sepfw.D11.15A372.bin"))
+        .arg(&outp)
+        .args(["--template", "{name}.macho"])
+        .assert()
+        .success();
+
+    assert!(outp.join("kernel.macho").exists());
+    assert!(outp.join("SEPOS.macho").exists());
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg(testfwp.join("sepfw.D11.15A372.bin"))
+        .arg(&outp)
+        .args(["--template", "{name}-{version}"])
+        .assert()
+        .code(16);
+
+    fs::remove_dir_all(outp)?; //cleanup
+
+    Ok(())
+}
+
+#[test]
+fn test_archive() -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+    let tarp = testfwp.join("testout-archive.tar");
+    let zipp = testfwp.join("testout-archive.zip");
+
+    for (format, outp) in [("tar", &tarp), ("zip", &zipp)] {
+        Command::cargo_bin("sepsplit-rs")?
+            .arg(testfwp.join("sepfw.D11.15A372.bin"))
+            .arg(outp)
+            .args(["--archive", format])
+            .assert()
+            .success();
+    }
+
+    //the manifest is the first entry, its name is at the start of the tar header and after the zip local header
+    let tar = fs::read(&tarp)?;
+    assert!(tar.starts_with(b"manifest.json\0"));
+    assert_eq!(&tar[257..262], b"ustar");
+    let zip = fs::read(&zipp)?;
+    assert!(zip.starts_with(b"PK\x03\x04"));
+    assert_eq!(&zip[30..43], b"manifest.json");
+
+    //a zip can not be streamed to stdout
+    Command::cargo_bin("sepsplit-rs")?
+        .arg(testfwp.join("sepfw.D11.15A372.bin"))
+        .args(["-", "--archive", "zip"])
+        .assert()
+        .code(2);
+
+    fs::remove_file(tarp)?; //cleanup
+    fs::remove_file(zipp)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_diff() -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use predicates::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+    let old = testfwp.join("sepfw.D10.18A373.bin");
+    let new = testfwp.join("sepfw.D10.19A346.bin");
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("diff")
+        .args([&old, &old])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 added, 0 removed, 0 changed"));
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("diff")
+        .args([&old, &new])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"changed\": [\n").and(predicate::str::contains("\"unchanged\"")));
+
+    Ok(())
+}
+
+#[test]
+fn test_hashes() -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use predicates::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("list")
+        .arg(testfwp.join("sepfw.N61.16G192.bin"))
+        .args(["--format", "json", "--sha1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"raw\": {").and(predicate::str::contains("\"fixed\": {"))
+            .and(predicate::str::is_match("\"sha1\": \"[0-9a-f]{40}\"")?));
+
+    Ok(())
+}
+
+#[test_case("D11.15A372")]
+#[test_case("N61.16G192")]
+fn test_load_commands(fname: &str) -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use predicates::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+
+    //the UUIDs and source versions in the load commands are the ones in the SEP app table
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("list")
+        .arg(testfwp.join(format!("sepfw.{fname}.bin")))
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match("\"lc_uuid\": \"[0-9a-f-]{36}\"")?.and(predicate::str::contains("\"mismatch\": [\n").not()))
+        .stderr(predicate::str::contains("Warning: LC_").not());
+
+    Ok(())
+}
+
+#[test]
+fn test_rebase() -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use predicates::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("list")
+        .arg(testfwp.join("sepfw.D11.15A372.bin"))
+        .args(["--format", "json", "--rebase"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"rebase\": {").and(predicate::str::contains("\"orig_vmaddr\": ")))
+        .stderr(predicate::str::contains("could not be rebased").not());
+
+    Ok(())
+}
+
+#[test]
+fn test_regions() -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use predicates::prelude::*;
+    use std::process::Command;
+
+    let testfwp = &Path::new(env!("CARGO_MANIFEST_DIR")).join("testfws");
+
+    Command::cargo_bin("sepsplit-rs")?
+        .arg("regions")
+        .arg(testfwp.join("sepfw.D11.15A372.bin"))
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"kind\": \"kernel\"").and(predicate::str::contains("\"overlaps\": [")));
+
+    Ok(())
+}
+
+#[test_case(&["verify", "sepfw.bin", "--archive", "tar"])]
+#[test_case(&["repack", "sepfw.bin", "out", "sepfw.new.bin", "--rebase"])]
+#[test_case(&["info", "sepfw.bin", "--no-clobber"])]
+#[test_case(&["diff", "old.bin", "new.bin", "-m", "sks"])]
+#[test_case(&["batch", "in", "out", "--device", "n61"])]
+#[test_case(&["completions", "bash", "--format", "json"])]
+fn test_usage(args: &[&str]) -> Result<(), Box<dyn Error>> {
+    use assert_cmd::prelude::*;
+    use std::process::Command;
+
+    //each subcommand only takes the flags it uses, the others are usage errors
+    Command::cargo_bin("sepsplit-rs")?
+        .args(args)
+        .assert()
+        .failure()
+        .code(1);
+
+    Ok(())
+}
@@ -25,11 +25,12 @@
         - That the SEP HDR and SEP App 64-bit structs 
           will not have memory sizes if the stack size is 0
           (iOS 13 SEP)
-    If these assumptions are wrong, the code may panic due to the struct fields being off.
+    If these assumptions are wrong, the code may error out due to the struct fields being off.
 */
 #![allow(dead_code)] // fields kept for documentation
 
-use binrw::{BinRead, binrw};
+use binrw::{BinRead, PosValue};
+use crate::SepSplitError;
 
 //utility macros/functions to help make my life easier
 
@@ -37,14 +38,14 @@ use binrw::{BinRead, binrw};
 #[macro_export]
 macro_rules! cast_struct {
     ($t: ty, $arr: expr) => {
-        Cursor::new(&$arr).read_le::<$t>().unwrap_or_else(|e| panic!("Unable to deserialize to {}, err: {e}", stringify!($t)))
+        Cursor::new(&$arr).read_le::<$t>().map_err(|e| $crate::SepSplitError::BadStruct { name: stringify!($t), source: e })
     }
 }
 
 #[macro_export]
 macro_rules! write_struct {
     ($str: expr, $arr: expr) => {
-        Cursor::new(&mut $arr).write_le(&$str).unwrap_or_else(|e| panic!("Unable to serialize {}, err: {e}", stringify!($str)))
+        Cursor::new(&mut $arr).write_le(&$str).map_err(|e| $crate::SepSplitError::BadStruct { name: stringify!($str), source: e })
     }
 }
 
@@ -52,14 +53,7 @@ macro_rules! write_struct {
 #[macro_export]
 macro_rules! cast_struct_args {
     ($t: ty, $arr: expr, $args: expr) => {
-        <$t>::read_le_args(&mut Cursor::new($arr), $args)
-        .unwrap_or_else(|e|
-            panic!(
-                "Unable to deserialize to {}, err: {e}, first 4 bytes: {bytes:x?}", 
-                stringify!($t), 
-                bytes=&$arr[0..4]
-            )
-        )
+        <$t>::read_le_args(&mut Cursor::new($arr), $args).map_err(|e| $crate::SepSplitError::BadStruct { name: stringify!($t), source: e })
     }
 }
 
@@ -68,21 +62,134 @@ pub const fn range_size(start: usize, size: usize) -> std::ops::Range<usize> {
     start..start+size
 }
 
+//get a slice from the start and size, without panicking if it is out of bounds
+pub fn slice_size(buf: &[u8], start: usize, size: usize) -> Result<&[u8], SepSplitError> {
+    start.checked_add(size)
+        .and_then(|end| buf.get(start..end))
+        .ok_or(SepSplitError::Truncated { offset: start, needed: size })
+}
+
+//mutable version of the above
+pub fn slice_size_mut(buf: &mut [u8], start: usize, size: usize) -> Result<&mut [u8], SepSplitError> {
+    start.checked_add(size)
+        .and_then(|end| buf.get_mut(start..end))
+        .ok_or(SepSplitError::Truncated { offset: start, needed: size })
+}
+
+//get the rest of a slice from the start
+pub fn slice_from(buf: &[u8], start: usize) -> Result<&[u8], SepSplitError> {
+    buf.get(start..).ok_or(SepSplitError::Truncated { offset: start, needed: 1 })
+}
+
 //make a str from a slice
-pub fn strslice(slice: &[u8]) -> &str {
-    std::str::from_utf8(slice).unwrap_or_else(|e| 
-        panic!("Could not convert slice to utf-8, err: {e}")
-    ).split_whitespace().next().unwrap()
+pub fn strslice(slice: &[u8]) -> Result<&str, SepSplitError> {
+    std::str::from_utf8(slice).map_err(|_| SepSplitError::BadName)?
+        .split_whitespace().next().ok_or(SepSplitError::BadName)
+}
+
+//an optional value for a table, "-" if it is not there
+pub fn opt_str(value: Option<impl ToString>) -> String {
+    value.map_or_else(|| String::from("-"), |v| v.to_string())
+}
+
+//match a name against a glob with * and ?, ignoring ASCII case
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+    let (mut p, mut n) = (0, 0);
+    let mut star = None; //the last * and where the name was when it was reached
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            },
+            Some(&c) if c == b'?' || c.eq_ignore_ascii_case(&name[n]) => {
+                p += 1;
+                n += 1;
+            },
+            _ => match star {
+                //let the * take one more byte
+                Some((sp, sn)) => {
+                    star = Some((sp, sn + 1));
+                    p = sp + 1;
+                    n = sn + 1;
+                },
+                None => return false
+            }
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Creates a file with a buffer, or gives `None` if it already exists and should not be clobbered
+/// # Errors
+/// * The file could not be created
+pub fn filecreate(path: &std::path::Path, clobber: bool) -> Result<Option<std::io::BufWriter<std::fs::File>>, std::io::Error> {
+    let file = if clobber {
+        std::fs::File::create(path)
+    } else {
+        std::fs::OpenOptions::new().write(true).create_new(true).open(path)
+    };
+    match file {
+        Ok(file) => Ok(Some(std::io::BufWriter::new(file))),
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => Ok(None),
+        Err(err) => Err(err)
+    }
 }
 
-//write to file with a buffer
-pub fn filewrite(path: &std::path::Path, data: &[u8]) {
-    use std::io::Write;
-    std::io::BufWriter::new(std::fs::File::create(path).unwrap_or_else(|e| 
-        panic!("Unable to create \"{path}\" with err: {e}", path=path.display())
-    )).write_all(data).unwrap_or_else(|e| 
-        panic!("Unable to write to buffered file \"{path}\" with err: {e}", path=path.display())
-    );
+//CRC32 (IEEE, same as zlib), the table is generated at compile time
+static CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            crc = if crc & 1 == 0 { crc >> 1 } else { 0xedb8_8320 ^ (crc >> 1) };
+            k += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(buf: &[u8]) -> u32 {
+    crc32_update(0, buf)
+}
+
+//continues the CRC32 of the bytes before buf, for data that is streamed
+pub fn crc32_update(crc: u32, buf: &[u8]) -> u32 {
+    !buf.iter().fold(!crc, |crc, &b| CRC32_TABLE[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8))
+}
+
+/// Checks if the file starts with a zip local header, like IPSWs and OTAs
+/// # Errors
+/// * The file could not be opened or read
+pub fn is_zip(path: &std::path::Path) -> Result<bool, std::io::Error> {
+    let mut magic = [0; 4];
+    let read = std::io::Read::read(&mut std::fs::File::open(path)?, &mut magic)?;
+    Ok(read == 4 && &magic == b"PK\x03\x04")
+}
+
+//passes the bytes through, keeping their CRC32 and how many there were
+pub struct CrcWriter<'a> {
+    pub out: &'a mut dyn std::io::Write,
+    pub crc: u32,
+    pub len: u64,
+}
+
+impl std::io::Write for CrcWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.crc = crc32_update(self.crc, &buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
 }
 
 //structs
@@ -120,17 +227,21 @@ pub struct Legion32 {
 //all of the below allows are due to macro generated code
 #[allow(
     dead_code, 
+    unused_parens,
     clippy::map_unwrap_or, 
     clippy::no_effect_underscore_binding, 
-    clippy::cast_lossless
+    clippy::cast_lossless,
+    clippy::new_without_default,
+    clippy::must_use_candidate
 )]
 mod srcver { //in a module to be able to apply allow attribute
     use modular_bitfield::prelude::*;
     use ::binrw::BinRead;
+    use serde::{Serialize, Serializer};
     use std::fmt;
     
     #[bitfield(bits = 64)]
-    #[derive(BinRead, Debug, Clone, Copy)]
+    #[derive(BinRead, Debug, Clone, Copy, PartialEq, Eq)]
     #[br(map = Self::from_bytes)]
     pub struct SrcVer {
         patch3: B10,
@@ -148,9 +259,16 @@ mod srcver { //in a module to be able to apply allow attribute
             write!(f, "{}.{}.{}.{}.{}", self.major(), self.minor(), self.patch1(), self.patch2(), self.patch3())
         }
     }
+
+    //as it is displayed, like "1.2.3.4.5"
+    impl Serialize for SrcVer {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
 }
 
-use srcver::SrcVer;
+pub use srcver::SrcVer;
 
 #[derive(BinRead, Debug)]
 pub struct SEPMonitorBootArgs {
@@ -181,7 +299,7 @@ pub struct SEPKernBootArgs {
     shm_base: u64,
     smh_size: u32,
     reserved: [u32; 3],
-    sepos_crc32: u32,
+    pub sepos_crc32: PosValue<u32>, // with the offset from the start of the struct
     seprom_args_offset: u32,
     seprom_phys_offset: u32,
     entropy: [u64; 2],
@@ -260,7 +378,7 @@ pub struct SEPDataHDR64 {
         #[br(if(!is_old, SrcVer::from_bytes([0; 8])))] // old subversion 3 SEPOS
         pub srcver: SrcVer,         // The source version of the rootserver
     //rootserver end
-    pub crc32: u32, // CRC32 of all of the apps after SEPOS
+    pub crc32: PosValue<u32>, // CRC32 of all of the apps after SEPOS, with the offset from the start of the struct
     pub coredump_sup: u8, //actually bool but I don't want a panic in case it deserializes the wrong bytes
     pub pad: [u8; 3], //u32 alignment
     #[br(if(pad == [0x40, 0x04, 0x00], [0; 0x100]))]
@@ -289,7 +407,7 @@ pub struct SEPDataHDR64Ver2 {
         pub init_name: [u8; 16],    // The name of the rootserver (usually SEPOS)
         pub init_uuid: [u8; 16],    // The UUID of the rootserver
     //rootserver end
-    pub crc32: u32, // CRC32 of all of the apps after SEPOS
+    pub crc32: PosValue<u32>, // CRC32 of all of the apps after SEPOS, with the offset from the start of the struct
     pub coredump_sup: u8, //actually bool but I don't want a panic in case it deserializes the wrong bytes
     pub pad: [u8; 3], //u32 alignment
     pub n_apps: u32,      // The number of apps that follow
@@ -377,129 +495,71 @@ pub struct SEPAppOld {
 }
 
 
-//copied from Apple's loader.h
-type VMProt = i32;
-type CPUType = i32;
-type CPUSubtype = i32;
-
-#[derive(BinRead, Debug)]
-pub struct MachHeader {
-    pub magic: u32,
-    pub cputype: CPUType,
-    pub cpusubtype: CPUSubtype,
-    pub filetype: u32,
-    pub ncmds: u32,
-    pub sizeofcmds: u32,
-    pub flags: u32,
-}
-
-#[binrw]
-#[derive(Debug)]
-pub struct Segment {
-    pub segname: [u8; 16],
-    pub vmaddr: u32,
-    pub vmsize: u32,
-    pub fileoff: u32,
-    pub filesize: u32,
-    pub maxprot: VMProt,
-    pub initprot: VMProt,
-    pub flags: u32
-}
-
-#[binrw]
-#[derive(Debug)]
-pub struct Segment64 {
-    pub segname: [u8; 16],
-    pub vmaddr: u64,
-    pub vmsize: u64,
-    pub fileoff: u64,
-    pub filesize: u64,
-    pub maxprot: VMProt,
-    pub initprot: VMProt,
-    pub flags: u32,
-}
-
-#[binrw]
-#[derive(Debug, Default)]
-pub struct SymTab {
-    pub symoff: u32,
-    pub nsyms: u32,
-    pub stroff: u32,
-    pub strsize: u32,
-}
-
-#[binrw]
-#[derive(Debug, Default)]
-pub struct DySymTab {
-    pub ilocalsym: u32,
-    pub nlocalsym: u32,
-    pub iextdefsym: u32,
-    pub nextdefsym: u32,
-    pub iundefsym: u32,
-    pub nundefsym: u32,
-    pub tocoff: u32,
-    pub ntoc: u32,
-    pub modtaboff: u32,
-    pub nmodtab: u32,
-    pub extrefsymoff: u32,
-    pub nextrefsyms: u32,
-    pub indirectsymoff: u32,
-    pub nindirectsyms: u32,
-    pub extreloff: u32,
-    pub nextrel: u32,
-    pub locreloff: u32,
-    pub nlocrel: u32,
-}
-
-#[derive(BinRead)]
-pub struct SrcVerCmd {
-    pub cmd: u32,	        /* LC_SOURCE_VERSION */
-    pub cmdsize: u32,	    /* 16 */
-    pub version: SrcVer,	/* A.B.C.D.E packed as a24.b10.c10.d10.e10 */
-}
-
-//type of command in cmd field
-#[derive(PartialEq, Eq, Debug, BinRead)]
-pub enum Cmd {
-    #[br(magic = 0x1u32)] Segment,
-    #[br(magic = 0x19u32)] Segment64,
-    #[br(magic = 0x2u32)] SymTab,
-    #[br(magic = 0xBu32)] DySymTab,
-    #[br(magic = 0x2Au32)] SourceVersion,
-    Unknown = 0xFFFF
-}
-
-#[derive(BinRead, Debug)]
-pub struct LoadCommand {
-    pub cmd: Cmd,
-    pub cmdsize: u32
-}
-
 #[derive(Debug, PartialEq, Eq)]
 pub struct SEPinfo {
     pub sep_app_pos: usize,
     pub sepapp_size: usize,
     pub sepapps: Option<usize>,
-    pub shlibs: Option<usize>
+    pub shlibs: Option<usize>,
+    pub sepos_crc32: u32,
+    pub sepos_crc32_off: usize, // The offset of the CRC32 in the firmware
 }
 
 
-pub static SEG_DATA:     [u8; 16] = *b"__DATA\0\0\0\0\0\0\0\0\0\0";
-pub static SEG_PAGEZERO: [u8; 16] = *b"__PAGEZERO\0\0\0\0\0\0";
-pub static SEG_LINKEDIT: [u8; 16] = *b"__LINKEDIT\0\0\0\0\0\0";
-
 //pub static LEGION_32_SIZE:  usize = 22;
 //pub static LEGION_64_SIZE:  usize = 22;
 pub static SEPHDR_SIZE:       usize = 224;
 pub static SEPAPP_64_SIZE:    usize = 128;
 pub static SEPAPP_SIZE:       usize = 32;
-pub static MACHHEADER_SIZE:   usize = 28;
-pub static LOADCOMMAND_SIZE:  usize = 8;
 pub static KRNLBOOTARGS_SIZE: usize = 312;
 //pub static SEGMENT_SIZE: usize = 64;
 //pub static SEGMENT64_SIZE: usize = 80;
 
-impl MachHeader {
-    pub const fn is_macho(&self) -> bool { self.magic & 0xffff_fffe == 0xfeed_face } //bitwise AND with 0x0 ignores 64 bit
-    pub const fn is64(&self) -> bool { self.magic & 0x1 == 1 } // would mean 0xfeed_facf
+#[cfg(test)]
+mod tests {
+    use super::{crc32, crc32_update, glob_match, strslice, SrcVer};
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("SEPOS", "SEPOS"));
+        assert!(glob_match("sepos", "SEPOS"));
+        assert!(!glob_match("SEPOS", "SEPOS2"));
+        assert!(!glob_match("SEPOS2", "SEPOS"));
+        assert!(glob_match("sks*", "sks"));
+        assert!(glob_match("sks*", "sksd"));
+        assert!(glob_match("*d", "sksd"));
+        assert!(!glob_match("*d", "sks"));
+        assert!(glob_match("s?s", "sks"));
+        assert!(!glob_match("s?s", "ss"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("**", "ARTM"));
+        assert!(!glob_match("", "ARTM"));
+        //the * has to backtrack past the first match of what follows it
+        assert!(glob_match("*ab*c", "aabxabyc"));
+        assert!(glob_match("a*b*c*d", "aXbXbXcXd"));
+        assert!(!glob_match("a*b*c*d", "aXbXcXbX"));
+        assert!(glob_match("*_?", "sepdump01_x"));
+        assert!(!glob_match("*_?", "sepdump01_"));
+    }
+
+    #[test]
+    fn test_strslice() {
+        assert_eq!(strslice(b"SEPOS       ").unwrap(), "SEPOS");
+        assert!(strslice(b"            ").is_err());
+        assert!(strslice(b"\xffSEPOS").is_err());
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32_update(crc32(b"12345"), b"6789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_srcver() {
+        let srcver = SrcVer::from_bytes(((1234u64 << 40) | (5 << 30) | (6 << 20) | (7 << 10) | 8).to_le_bytes());
+        assert_eq!(srcver.to_string(), "1234.5.6.7.8");
+        assert_eq!(srcver.get_major(), 1234);
+    }
 }